@@ -1,22 +1,610 @@
 use anyhow::Result;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
 /// Shared module for detecting Claude Code binary installations
 /// Supports NVM installations, aliased paths, and version-based selection
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 use tauri::Manager;
 
+/// Whether `ClaudeVersion::parse` accepts `s`, checked without building a version
+/// For live validation of user input such as a "pin version" field.
+pub fn is_valid_version_str(s: &str) -> bool {
+    parse_version_parts(s).is_some()
+}
+
+/// The parser behind `ClaudeVersion::parse`: up to four numeric components (at least one)
+/// and the pre-release tag, borrowed from `s`
+fn parse_version_parts(s: &str) -> Option<([Option<u64>; 4], Option<&str>)> {
+    let s = s.trim();
+    let s = s.strip_prefix(['v', 'V']).unwrap_or(s);
+    let s = s.split('+').next().unwrap_or_default();
+    let (core, prerelease) = match s.split_once('-') {
+        Some((core, prerelease)) => (core, Some(prerelease)),
+        None => (s, None),
+    };
+    let is_identifier = |ident: &str| {
+        !ident.is_empty() && ident.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if prerelease.is_some_and(|prerelease| !prerelease.split('.').all(is_identifier)) {
+        return None;
+    }
+
+    let mut numbers = [None; 4];
+    for (i, part) in core.split('.').enumerate() {
+        if i >= numbers.len() || part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        numbers[i] = Some(part.parse::<u64>().ok()?);
+    }
+    Some((numbers, prerelease))
+}
+
+/// A parsed Claude version such as `1.0.17`, `1.2.3.456`, `2024.6.1` or `2.0.0-beta.1`
+/// Serialized as its display string so the frontend keeps receiving plain text
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClaudeVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Optional fourth numeric component; a missing revision orders below any present one
+    pub revision: Option<u64>,
+    /// Pre-release tag such as `beta.1`; a pre-release orders below the same release
+    pub prerelease: Option<String>,
+}
+
+impl ClaudeVersion {
+    /// Parses a single version token like `1.2.3`, `v2`, `1.2.3.456` or `1.0.17-beta`
+    /// Missing minor/patch components default to zero; anything non-numeric is rejected.
+    /// A `-beta.1` style suffix is kept as the pre-release, build metadata (`+build.5`) is
+    /// ignored.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (numbers, prerelease) = parse_version_parts(s)?;
+        Some(ClaudeVersion {
+            major: numbers[0].unwrap_or(0),
+            minor: numbers[1].unwrap_or(0),
+            patch: numbers[2].unwrap_or(0),
+            revision: numbers[3],
+            prerelease: prerelease.map(str::to_string),
+        })
+    }
+
+    /// Whether this is a pre-release such as `2.0.0-beta`
+    pub fn is_prerelease(&self) -> bool {
+        self.prerelease.is_some()
+    }
+
+    /// Finds the first version-looking token in free text such as `claude version: 1.0.0`
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        text.split_whitespace()
+            .map(|token| token.trim_matches(|c: char| !c.is_ascii_alphanumeric()))
+            // Version usually contains dots and numbers
+            .filter(|token| token.contains('.') && token.chars().any(|c| c.is_ascii_digit()))
+            .find_map(Self::parse)
+    }
+
+    /// Reads the version from structured output like `{ "version": "1.2.3", ... }`
+    /// Returns `None` for anything that isn't a JSON object with a parseable `version`
+    pub fn from_json(s: &str) -> Option<Self> {
+        let value: serde_json::Value = serde_json::from_str(s.trim()).ok()?;
+        let version = value.get("version")?.as_str()?;
+        Self::parse(version).or_else(|| Self::from_str(version))
+    }
+
+    /// Finds the version in multi-line `--version` output that may carry banners or notices
+    /// A line labeled `claude version` wins over bare numbers, and update notices such as
+    /// `A new version (1.9.0) is available` are never taken as the installed version. A line
+    /// that leads with its version, like `1.0.3 (Claude Code) - update available`, is only
+    /// a notice when another line has a version to offer.
+    pub fn from_multiline(output: &str) -> Option<Self> {
+        let mentions_update = |line: &str| {
+            let line = line.to_lowercase();
+            line.contains("new version") || line.contains("available") || line.contains("update")
+        };
+        let leads_with_version =
+            |line: &str| line.split_whitespace().next().and_then(Self::from_str).is_some();
+
+        output
+            .lines()
+            .filter(|line| line.to_lowercase().contains("claude version"))
+            .find_map(Self::from_str)
+            .or_else(|| {
+                output
+                    .lines()
+                    .filter(|line| !mentions_update(line))
+                    .find_map(Self::from_str)
+            })
+            .or_else(|| {
+                output
+                    .lines()
+                    .filter(|line| leads_with_version(line))
+                    .find_map(Self::from_str)
+            })
+    }
+}
+
+impl std::fmt::Display for ClaudeVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(revision) = self.revision {
+            write!(f, ".{}", revision)?;
+        }
+        if let Some(prerelease) = &self.prerelease {
+            write!(f, "-{}", prerelease)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for ClaudeVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch, self.revision)
+            .cmp(&(other.major, other.minor, other.patch, other.revision))
+            .then_with(|| match (&self.prerelease, &other.prerelease) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => compare_prerelease(a, b),
+            })
+    }
+}
+
+impl PartialOrd for ClaudeVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Semver pre-release precedence: dot-separated identifiers compared left to right,
+/// numeric ones numerically and below alphanumeric ones, and a shorter tag first on a tie
+fn compare_prerelease(a: &str, b: &str) -> Ordering {
+    let mut a_idents = a.split('.');
+    let mut b_idents = b.split('.');
+    loop {
+        let ordering = match (a_idents.next(), b_idents.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+impl Serialize for ClaudeVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ClaudeVersion {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        ClaudeVersion::parse(&s)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid version: {}", s)))
+    }
+}
+
+impl ClaudeVersion {
+    /// Whether this version meets every comparator of `req`
+    pub fn satisfies(&self, req: &VersionReq) -> bool {
+        req.comparators.iter().all(|comparator| comparator.matches(self))
+    }
+}
+
+/// A version requirement such as `>=1.2.0`, `^1.0` or `>=1.2, <2`
+/// Comparators are separated by commas and must all match. Supported operators are
+/// `=`, `>`, `>=`, `<`, `<=`, `^` and `~`; a bare version means `^`. Missing components
+/// count as zero, so `<2` means below `2.0.0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparator {
+    op: ComparatorOp,
+    version: ClaudeVersion,
+    /// How many components were written, which decides the upper bound of `^` and `~`
+    components: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ComparatorOp {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Caret,
+    Tilde,
+}
+
+impl VersionReq {
+    /// Parses a requirement; returns `None` if any comparator is malformed or none is given
+    pub fn parse(s: &str) -> Option<Self> {
+        let comparators = s
+            .split(',')
+            .map(Comparator::parse)
+            .collect::<Option<Vec<_>>>()?;
+        Some(VersionReq { comparators })
+    }
+}
+
+impl Comparator {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (op, rest) = [
+            (">=", ComparatorOp::GreaterEq),
+            ("<=", ComparatorOp::LessEq),
+            (">", ComparatorOp::Greater),
+            ("<", ComparatorOp::Less),
+            ("=", ComparatorOp::Exact),
+            ("^", ComparatorOp::Caret),
+            ("~", ComparatorOp::Tilde),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| s.strip_prefix(prefix).map(|rest| (op, rest)))
+        .unwrap_or((ComparatorOp::Caret, s));
+
+        let rest = rest.trim();
+        let version = ClaudeVersion::parse(rest)?;
+        Some(Comparator {
+            op,
+            version,
+            components: rest.split(['-', '+']).next().unwrap_or_default().split('.').count(),
+        })
+    }
+
+    fn matches(&self, version: &ClaudeVersion) -> bool {
+        match self.op {
+            ComparatorOp::Exact => version == &self.version,
+            ComparatorOp::Greater => version > &self.version,
+            ComparatorOp::GreaterEq => version >= &self.version,
+            ComparatorOp::Less => version < &self.version,
+            ComparatorOp::LessEq => version <= &self.version,
+            ComparatorOp::Caret | ComparatorOp::Tilde => {
+                version >= &self.version && version < &self.upper_bound()
+            }
+        }
+    }
+
+    /// Exclusive upper bound of a `^` or `~` range
+    fn upper_bound(&self) -> ClaudeVersion {
+        let ClaudeVersion {
+            major,
+            minor,
+            patch,
+            ..
+        } = self.version;
+        let (major, minor, patch) = match self.op {
+            ComparatorOp::Tilde if self.components >= 2 => (major, minor + 1, 0),
+            ComparatorOp::Tilde => (major + 1, 0, 0),
+            _ if major > 0 || self.components == 1 => (major + 1, 0, 0),
+            _ if minor > 0 || self.components == 2 => (0, minor + 1, 0),
+            _ => (0, 0, patch + 1),
+        };
+        ClaudeVersion {
+            major,
+            minor,
+            patch,
+            revision: None,
+            prerelease: None,
+        }
+    }
+}
+
+impl std::fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, comparator) in self.comparators.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            let op = match comparator.op {
+                ComparatorOp::Exact => "=",
+                ComparatorOp::Greater => ">",
+                ComparatorOp::GreaterEq => ">=",
+                ComparatorOp::Less => "<",
+                ComparatorOp::LessEq => "<=",
+                ComparatorOp::Caret => "^",
+                ComparatorOp::Tilde => "~",
+            };
+            let ClaudeVersion { major, minor, .. } = &comparator.version;
+            match comparator.components {
+                1 => write!(f, "{}{}", op, major)?,
+                2 => write!(f, "{}{}.{}", op, major, minor)?,
+                _ => write!(f, "{}{}", op, comparator.version)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for VersionReq {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionReq {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        VersionReq::parse(&s)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid version requirement: {}", s)))
+    }
+}
+
+/// How a Claude installation got onto the system
+/// Ordered by declaration, which is the order grouped listings show them in.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallationType {
+    /// A plain binary found on PATH or in a user directory other than the `Standalone` ones
+    #[default]
+    Direct,
+    /// System-wide locations such as /usr/local/bin or /usr/bin
+    System,
+    /// Installed through Homebrew
+    Homebrew,
+    /// Installed as a global package under an NVM-managed node version
+    Nvm,
+    /// Installed as a Snap package (Linux)
+    Snap,
+    /// Installed as a Flatpak app and launched through `flatpak run` (Linux)
+    Flatpak,
+    /// Installed inside a WSL distribution and launched through `wsl.exe` (Windows)
+    Wsl,
+    /// Installed globally with pnpm; the bin entry is a shim into the pnpm store
+    Pnpm,
+    /// Installed globally with npm under a custom prefix (`npm config get prefix`)
+    Npm,
+    /// Chosen explicitly by the user, e.g. through `CLAUDIA_CLAUDE_CANDIDATES`
+    Custom,
+    /// The executable inside a macOS `.app` bundle, e.g. one installed as a Homebrew Cask
+    AppBundle,
+    /// A self-contained binary dropped into `~/.local/bin`, `~/bin` or `~/.cargo/bin`
+    ///
+    /// Breaking change: binaries in `~/.local/bin` and `~/bin` used to be reported as
+    /// `Direct`. Filters that listed only `Direct` to get them must add `Standalone`, and
+    /// stored `"direct"` values for those paths now come back as `"standalone"`.
+    Standalone,
+}
+
+impl InstallationType {
+    /// Every installation type, i.e. an unfiltered discovery
+    pub const ALL: &'static [InstallationType] = &[
+        InstallationType::Direct,
+        InstallationType::System,
+        InstallationType::Homebrew,
+        InstallationType::Nvm,
+        InstallationType::Snap,
+        InstallationType::Flatpak,
+        InstallationType::Wsl,
+        InstallationType::Pnpm,
+        InstallationType::Npm,
+        InstallationType::Custom,
+        InstallationType::AppBundle,
+        InstallationType::Standalone,
+    ];
+
+    /// The name used in serialized output, e.g. `nvm` or `homebrew`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InstallationType::Direct => "direct",
+            InstallationType::System => "system",
+            InstallationType::Homebrew => "homebrew",
+            InstallationType::Nvm => "nvm",
+            InstallationType::Snap => "snap",
+            InstallationType::Flatpak => "flatpak",
+            InstallationType::Wsl => "wsl",
+            InstallationType::Pnpm => "pnpm",
+            InstallationType::Npm => "npm",
+            InstallationType::Custom => "custom",
+            InstallationType::AppBundle => "app_bundle",
+            InstallationType::Standalone => "standalone",
+        }
+    }
+}
+
+impl std::fmt::Display for InstallationType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How discovery came across an installation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryMethod {
+    /// Found by looking in a known install location
+    #[default]
+    Scan,
+    /// `claude` resolved against a PATH, as `which claude` would
+    Which,
+}
+
 /// Represents a Claude installation with metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ClaudeInstallation {
-    /// Full path to the Claude binary
+    /// Full path to the Claude binary (the app id for Flatpak installations)
     pub path: String,
-    /// Version string if available
-    pub version: Option<String>,
+    /// Version if available
+    pub version: Option<ClaudeVersion>,
     /// Source of discovery (e.g., "nvm", "system", "homebrew", "which")
     pub source: String,
+    /// Kind of installation, used to decide how it has to be launched
+    #[serde(default)]
+    pub install_type: InstallationType,
+    /// Whether the installation was found in a known location or through a PATH lookup
+    #[serde(default)]
+    pub discovered_via: DiscoveryMethod,
+    /// Launcher command line for installations that can't be executed directly,
+    /// e.g. `["flatpak", "run", "<appid>"]` or `["wsl.exe", "-d", "<distro>", "-e", "<path>"]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invocation: Option<Vec<String>>,
+    /// WSL distribution a `Wsl` installation lives in; `None` means the default one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wsl_distro: Option<String>,
+    /// Last modification time of the binary (symlinks followed)
+    #[serde(default)]
+    pub modified: Option<SystemTime>,
+    /// Size of the binary in bytes (symlinks followed)
+    #[serde(default)]
+    pub size_bytes: Option<u64>,
+    /// Version information inferred from the path alone, e.g. `node v20.11.0` for an
+    /// nvm install; set whenever the path reveals something, whether or not `version` was
+    /// also read by running the binary
+    #[serde(default)]
+    pub version_hint: Option<String>,
+    /// Whether `path` itself is a symlink (e.g. Homebrew's bin links into the Cellar)
+    #[serde(default)]
+    pub is_symlink: bool,
+    /// The file a symlinked `path` ultimately points at
+    #[serde(default)]
+    pub resolved_target: Option<PathBuf>,
+    /// User-chosen name such as "work account"; only used for display and lookup, never
+    /// for ordering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// One-line summary for logs, e.g. `claude 1.2.3 (nvm) at /home/me/.nvm/.../bin/claude`
+/// The derived `Debug` still shows every field.
+impl std::fmt::Display for ClaudeInstallation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "claude {}", version)?,
+            None => write!(f, "claude unknown")?,
+        }
+        write!(f, " ({}) at {}", self.install_type, self.path)?;
+        if let Some(label) = &self.label {
+            write!(f, " [{}]", label)?;
+        }
+        Ok(())
+    }
+}
+
+impl ClaudeInstallation {
+    /// The user's label for this installation, if any
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Sets or, with `None` or a blank label, clears the user's label
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label
+            .map(|label| label.trim().to_string())
+            .filter(|label| !label.is_empty());
+    }
+
+    /// Whether `path` is still a runnable file, checked without spawning anything
+    /// Unix looks at the execute bits; Windows requires a PATHEXT extension such as `.exe`.
+    /// Much cheaper than a version probe, e.g. for validating a pinned path on startup.
+    pub fn command_exists(&self) -> bool {
+        let path = std::path::Path::new(&self.path);
+        if cfg!(windows) {
+            let extension = path
+                .extension()
+                .map(|ext| format!(".{}", ext.to_string_lossy().to_ascii_lowercase()));
+            path.is_file() && extension.is_some_and(|ext| pathext_extensions().contains(&ext))
+        } else {
+            crate::path_utils::is_executable_file(path)
+        }
+    }
+
+    /// Short hex id of the underlying binary that stays the same across discoveries and
+    /// restarts, e.g. for cache keys
+    /// Derived from the canonical path, plus the launcher invocation for Flatpak and WSL
+    /// installs and the distribution for WSL; metadata such as `modified`, `size_bytes` or
+    /// `label` doesn't affect it.
+    pub fn stable_id(&self) -> String {
+        let path = std::path::Path::new(&self.path);
+        let canonical = crate::path_utils::canonical_cached(path)
+            .unwrap_or_else(|| path.to_path_buf());
+
+        let mut hasher = Sha256::new();
+        hasher.update(canonical.to_string_lossy().as_bytes());
+        if matches!(self.install_type, InstallationType::Flatpak | InstallationType::Wsl) {
+            for part in self.invocation.iter().flatten() {
+                hasher.update([0]);
+                hasher.update(part.as_bytes());
+            }
+        }
+        if self.install_type == InstallationType::Wsl {
+            if let Some(distro) = &self.wsl_distro {
+                hasher.update([1]);
+                hasher.update(distro.as_bytes());
+            }
+        }
+        let digest = format!("{:x}", hasher.finalize());
+        digest[..16].to_string()
+    }
+
+    /// Builds the command that launches this installation, including any launcher prefix
+    /// Plain binaries run their path directly; Flatpak and WSL installs go through
+    /// `invocation` when discovery recorded one, or the default launcher otherwise. For WSL
+    /// that's `wsl.exe -d <distro> -e <path>`, without `-d` when no distro is recorded.
+    pub fn build_command(&self) -> CommandSpec {
+        let launcher: Vec<String> = match (&self.invocation, self.install_type) {
+            (Some(invocation), _) if !invocation.is_empty() => invocation.clone(),
+            (_, InstallationType::Flatpak) => {
+                vec!["flatpak".to_string(), "run".to_string(), self.path.clone()]
+            }
+            (_, InstallationType::Wsl) => {
+                let mut launcher = vec!["wsl.exe".to_string()];
+                if let Some(distro) = &self.wsl_distro {
+                    launcher.extend(["-d".to_string(), distro.clone()]);
+                }
+                launcher.extend(["-e".to_string(), self.path.clone()]);
+                launcher
+            }
+            _ => vec![self.path.clone()],
+        };
+
+        create_command_spec(&launcher[0]).args(&launcher[1..])
+    }
+
+    /// Fills `modified`, `size_bytes` and the symlink fields from the filesystem
+    /// Paths that can't be stat'ed (e.g. a bare `claude` or a Flatpak app id) get `None`
+    pub fn refresh_metadata(&mut self) {
+        self.is_symlink = std::fs::symlink_metadata(&self.path)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false);
+        self.resolved_target = if self.is_symlink {
+            std::fs::canonicalize(&self.path).ok()
+        } else if self.install_type == InstallationType::Pnpm {
+            pnpm_shim_target(std::path::Path::new(&self.path))
+        } else {
+            None
+        };
+
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => {
+                self.modified = metadata.modified().ok();
+                self.size_bytes = Some(metadata.len());
+            }
+            Err(_) => {
+                self.modified = None;
+                self.size_bytes = None;
+            }
+        }
+    }
 }
 
 /// Main function to find the Claude binary
@@ -24,6 +612,12 @@ pub struct ClaudeInstallation {
 pub fn find_claude_binary(app_handle: &tauri::AppHandle) -> Result<String, String> {
     info!("Searching for claude binary...");
 
+    // An explicit list of candidates from the environment beats everything else
+    if let Some(installation) = claude_from_candidates_env() {
+        info!("Using claude from {}: {}", CANDIDATES_VAR, installation);
+        return Ok(installation.path);
+    }
+
     // First check if we have a stored path in the database
     if let Ok(app_data_dir) = app_handle.path().app_data_dir() {
         let db_path = app_data_dir.join("agents.db");
@@ -35,11 +629,14 @@ pub fn find_claude_binary(app_handle: &tauri::AppHandle) -> Result<String, Strin
                     |row| row.get::<_, String>(0),
                 ) {
                     info!("Found stored claude path in database: {}", stored_path);
-                    let path_buf = PathBuf::from(&stored_path);
-                    if path_buf.exists() && path_buf.is_file() {
+                    let stored = ClaudeInstallation {
+                        path: stored_path.clone(),
+                        ..Default::default()
+                    };
+                    if stored.command_exists() {
                         return Ok(stored_path);
                     } else {
-                        warn!("Stored claude path no longer exists: {}", stored_path);
+                        warn!("Stored claude path is no longer runnable: {}", stored_path);
                     }
                 }
             }
@@ -56,35 +653,230 @@ pub fn find_claude_binary(app_handle: &tauri::AppHandle) -> Result<String, Strin
 
     // Log all found installations
     for installation in &installations {
-        info!("Found Claude installation: {:?}", installation);
+        info!("Found Claude installation: {}", installation);
     }
 
     // Select the best installation (highest version)
     if let Some(best) = select_best_installation(installations) {
-        info!(
-            "Selected Claude installation: path={}, version={:?}, source={}",
-            best.path, best.version, best.source
-        );
+        info!("Selected Claude installation: {} (source: {})", best, best.source);
         Ok(best.path)
     } else {
         Err("No valid Claude installation found".to_string())
     }
 }
 
+/// Env var holding an ordered, PATH-style list of claude binaries to try
+pub const CANDIDATES_VAR: &str = "CLAUDIA_CLAUDE_CANDIDATES";
+
+/// First healthy binary listed in `CLAUDIA_CLAUDE_CANDIDATES`, as a `Custom` installation
+/// Entries are separated like PATH (`:`, or `;` on Windows). Missing entries and ones that
+/// fail the health check are skipped with a debug log.
+pub fn claude_from_candidates_env() -> Option<ClaudeInstallation> {
+    let candidates = std::env::var_os(CANDIDATES_VAR)?;
+    std::env::split_paths(&candidates)
+        .filter(|candidate| !candidate.as_os_str().is_empty())
+        .find_map(|candidate| {
+            let mut installation = ClaudeInstallation {
+                path: candidate.to_string_lossy().to_string(),
+                source: "env-candidates".to_string(),
+                install_type: InstallationType::Custom,
+                ..Default::default()
+            };
+            match check_health(&installation) {
+                Some(version) => {
+                    installation.version = Some(version);
+                    installation.refresh_metadata();
+                    Some(installation)
+                }
+                None => {
+                    debug!("Skipping unhealthy claude candidate {:?}", candidate);
+                    None
+                }
+            }
+        })
+}
+
+/// Env var holding a PATH-style list of extra directories discovery scans for claude
+pub const SEARCH_PATHS_VAR: &str = "CLAUDIA_CLAUDE_SEARCH_PATHS";
+
+/// The existing directories listed in `CLAUDIA_CLAUDE_SEARCH_PATHS`, in order
+/// Entries are separated like PATH; ones that don't exist are skipped with a debug log.
+pub fn search_paths_from_env() -> Vec<PathBuf> {
+    let Some(value) = std::env::var_os(SEARCH_PATHS_VAR) else {
+        return Vec::new();
+    };
+    std::env::split_paths(&value)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .filter(|dir| {
+            let exists = dir.is_dir();
+            if !exists {
+                debug!("Skipping {} entry {:?}: not a directory", SEARCH_PATHS_VAR, dir);
+            }
+            exists
+        })
+        .collect()
+}
+
+/// Health check for a specific binary: it must be a runnable file that reports a version
+fn check_health(installation: &ClaudeInstallation) -> Option<ClaudeVersion> {
+    if !installation.command_exists() {
+        return None;
+    }
+    get_claude_version(&installation.path).ok().flatten()
+}
+
 /// Discovers all available Claude installations and returns them for selection
 /// This allows UI to show a version selector
 pub fn discover_claude_installations() -> Vec<ClaudeInstallation> {
     info!("Discovering all Claude installations...");
 
-    let installations = discover_all_installations();
+    let mut installations = discover_all_installations();
+    sort_installations(&mut installations);
+    installations
+}
+
+/// Discovery results kept between `discover_claude_installations_cached` calls
+struct DiscoveryCache {
+    /// When the filesystem was last scanned for installation paths
+    scanned_at: Instant,
+    /// `discovery_env_key` when the cache was filled
+    env_key: u64,
+    entries: Vec<CachedInstallation>,
+}
+
+/// Variables that change where discovery looks; the cache is dropped when any of them do
+const DISCOVERY_ENV_VARS: &[&str] = &[
+    "HOME",
+    "USERPROFILE",
+    "PATH",
+    "CLAUDE_BINARY",
+    "npm_config_prefix",
+    "NPM_CONFIG_PREFIX",
+    "PNPM_HOME",
+    "NVM_BIN",
+    SEARCH_PATHS_VAR,
+];
+
+/// Hash of the current values of `DISCOVERY_ENV_VARS`
+fn discovery_env_key() -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for var in DISCOVERY_ENV_VARS {
+        std::env::var_os(var).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// One cached installation plus what's needed to decide whether its version is stale
+struct CachedInstallation {
+    installation: ClaudeInstallation,
+    probed_at: Instant,
+}
+
+impl CachedInstallation {
+    fn probed(mut installation: ClaudeInstallation) -> Self {
+        installation.refresh_metadata();
+        installation.version = probe_installation_version(&installation);
+        CachedInstallation {
+            installation,
+            probed_at: Instant::now(),
+        }
+    }
+
+    /// A cached version is reusable while the binary's mtime and size are unchanged
+    /// Entries that can't be stat'ed (bare `claude`, Flatpak apps) fall back to `ttl`
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        match std::fs::metadata(&self.installation.path) {
+            Ok(metadata) => {
+                metadata.modified().ok() == self.installation.modified
+                    && Some(metadata.len()) == self.installation.size_bytes
+            }
+            Err(_) => self.installation.modified.is_none() && self.probed_at.elapsed() < ttl,
+        }
+    }
+
+    /// The binary existed when cached but is gone now
+    fn is_removed(&self) -> bool {
+        self.installation.modified.is_some() && !PathBuf::from(&self.installation.path).exists()
+    }
+}
+
+static DISCOVERY_CACHE: Mutex<Option<DiscoveryCache>> = Mutex::new(None);
+
+/// Like `discover_claude_installations`, but only re-probes installations that changed
+/// Every call re-stats the cached binaries and re-runs `--version` just for those whose mtime
+/// or size differ. Once `ttl` has passed since the last scan, the filesystem is rescanned
+/// (without executing anything) to pick up new paths, which are probed as well.
+/// A change to HOME, PATH, CLAUDE_BINARY or the npm prefix discards the cache regardless.
+pub fn discover_claude_installations_cached(ttl: Duration) -> Vec<ClaudeInstallation> {
+    let mut cache = DISCOVERY_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let env_key = discovery_env_key();
+
+    match cache.as_mut() {
+        Some(cache) if cache.env_key == env_key => return cache.refresh(ttl),
+        Some(_) => debug!("Environment changed since the last discovery, rediscovering"),
+        None => {}
+    }
+
+    let installations = discover_claude_installations();
+    *cache = Some(DiscoveryCache {
+        scanned_at: Instant::now(),
+        env_key,
+        entries: installations
+            .iter()
+            .map(|installation| CachedInstallation {
+                installation: installation.clone(),
+                probed_at: Instant::now(),
+            })
+            .collect(),
+    });
+    installations
+}
+
+impl DiscoveryCache {
+    fn refresh(&mut self, ttl: Duration) -> Vec<ClaudeInstallation> {
+        self.entries.retain(|entry| !entry.is_removed());
+
+        if self.scanned_at.elapsed() >= ttl {
+            debug!("Rescanning for new Claude installations");
+            for found in discover_all_installations_filtered(InstallationType::ALL, false) {
+                if !self.entries.iter().any(|entry| entry.installation.path == found.path) {
+                    self.entries.push(CachedInstallation::probed(found));
+                }
+            }
+            self.scanned_at = Instant::now();
+        }
+
+        for entry in &mut self.entries {
+            if !entry.is_fresh(ttl) {
+                debug!("Re-probing changed installation {}", entry.installation.path);
+                *entry = CachedInstallation::probed(entry.installation.clone());
+            }
+        }
+
+        let mut installations: Vec<ClaudeInstallation> = self
+            .entries
+            .iter()
+            .map(|entry| entry.installation.clone())
+            .collect();
+        sort_installations(&mut installations);
+        installations
+    }
+}
+
+/// Drops cached discovery results so the next cached lookup rediscovers from scratch
+pub fn clear_discovery_cache() {
+    *DISCOVERY_CACHE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
 
-    // Sort by version (highest first), then by source preference
-    let mut sorted = installations;
-    sorted.sort_by(|a, b| {
+/// Sorts installations by version (highest first), then by source preference
+fn sort_installations(installations: &mut [ClaudeInstallation]) {
+    installations.sort_by(|a, b| {
         match (&a.version, &b.version) {
             (Some(v1), Some(v2)) => {
                 // Compare versions in descending order (newest first)
-                match compare_versions(v2, v1) {
+                match v2.cmp(v1) {
                     Ordering::Equal => {
                         // If versions are equal, prefer by source
                         source_preference(a).cmp(&source_preference(b))
@@ -97,355 +889,3906 @@ pub fn discover_claude_installations() -> Vec<ClaudeInstallation> {
             (None, None) => source_preference(a).cmp(&source_preference(b)),
         }
     });
+}
 
-    sorted
+/// Installations that report the same version but run different binaries
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionConflict {
+    pub version: ClaudeVersion,
+    /// Installation paths, one per distinct canonical binary, in discovery order
+    pub paths: Vec<String>,
 }
 
-/// Returns a preference score for installation sources (lower is better)
-fn source_preference(installation: &ClaudeInstallation) -> u8 {
-    match installation.source.as_str() {
-        "which" => 1,
-        "homebrew" => 2,
-        "system" => 3,
-        source if source.starts_with("nvm") => 4,
-        "local-bin" => 5,
-        "claude-local" => 6,
-        "npm-global" => 7,
-        "yarn" | "yarn-global" => 8,
-        "bun" => 9,
-        "node-modules" => 10,
-        "home-bin" => 11,
-        "PATH" => 12,
-        _ => 13,
+/// Groups installations by version and reports every version served by more than one binary
+/// Paths that canonicalize to the same file (symlinks, shims) are not treated as conflicts;
+/// installations without a known version are ignored
+pub fn find_version_conflicts(installs: &[ClaudeInstallation]) -> Vec<VersionConflict> {
+    let mut groups: BTreeMap<ClaudeVersion, Vec<(PathBuf, String)>> = BTreeMap::new();
+    for install in installs {
+        let Some(version) = &install.version else {
+            continue;
+        };
+        let canonical =
+            crate::path_utils::canonical_cached(std::path::Path::new(&install.path))
+                .unwrap_or_else(|| PathBuf::from(&install.path));
+        let group = groups.entry(version.clone()).or_default();
+        if !group.iter().any(|(existing, _)| existing == &canonical) {
+            group.push((canonical, install.path.clone()));
+        }
     }
-}
 
-/// Discovers all Claude installations on the system
-fn discover_all_installations() -> Vec<ClaudeInstallation> {
-    let mut installations = Vec::new();
+    groups
+        .into_iter()
+        .filter(|(_, binaries)| binaries.len() > 1)
+        .map(|(version, binaries)| VersionConflict {
+            version,
+            paths: binaries.into_iter().map(|(_, path)| path).collect(),
+        })
+        .collect()
+}
 
-    // 1. Try 'which' command first (now works in production)
-    if let Some(installation) = try_which_command() {
-        installations.push(installation);
+/// Groups discovery output by installation type for display under per-type headers
+/// Within a group installations are ordered newest version first, unknown versions last.
+pub fn group_installations(
+    installs: Vec<ClaudeInstallation>,
+) -> BTreeMap<InstallationType, Vec<ClaudeInstallation>> {
+    let mut groups: BTreeMap<InstallationType, Vec<ClaudeInstallation>> = BTreeMap::new();
+    for install in installs {
+        groups.entry(install.install_type).or_default().push(install);
     }
+    for group in groups.values_mut() {
+        sort_installations(group);
+    }
+    groups
+}
 
-    // 2. Check NVM paths
-    installations.extend(find_nvm_installations());
+/// How a rescan's installations differ from the previous discovery's
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InstallationDiff {
+    /// Installations new in this discovery, in its order
+    pub added: Vec<ClaudeInstallation>,
+    /// Installations no longer found, in the previous discovery's order
+    pub removed: Vec<ClaudeInstallation>,
+    /// Installations found both times whose version or metadata changed, as now discovered
+    pub changed: Vec<ClaudeInstallation>,
+}
 
-    // 3. Check standard paths
-    installations.extend(find_standard_installations());
+impl InstallationDiff {
+    /// Whether the two discoveries found the same installations, unchanged
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
 
-    // Remove duplicates by path
-    let mut unique_paths = std::collections::HashSet::new();
-    installations.retain(|install| unique_paths.insert(install.path.clone()));
+/// Matches two discoveries by `stable_id` and reports what was added, removed or changed
+/// Only discovered fields count as changes; the user's `label` doesn't.
+pub fn diff_installations(
+    old: &[ClaudeInstallation],
+    new: &[ClaudeInstallation],
+) -> InstallationDiff {
+    let old_by_id: HashMap<String, &ClaudeInstallation> =
+        old.iter().map(|install| (install.stable_id(), install)).collect();
+    let new_ids: std::collections::HashSet<String> =
+        new.iter().map(ClaudeInstallation::stable_id).collect();
 
-    installations
+    let mut diff = InstallationDiff::default();
+    for install in new {
+        match old_by_id.get(&install.stable_id()) {
+            None => diff.added.push(install.clone()),
+            Some(previous) if discovered_fields_differ(previous, install) => {
+                diff.changed.push(install.clone())
+            }
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .iter()
+        .filter(|install| !new_ids.contains(&install.stable_id()))
+        .cloned()
+        .collect();
+    diff
 }
 
-/// Try using the 'which' command to find Claude
-fn try_which_command() -> Option<ClaudeInstallation> {
-    debug!("Trying 'which claude' to find binary...");
+/// Whether anything discovery reports differs between two sightings of one installation
+fn discovered_fields_differ(a: &ClaudeInstallation, b: &ClaudeInstallation) -> bool {
+    a.version != b.version
+        || a.source != b.source
+        || a.install_type != b.install_type
+        || a.invocation != b.invocation
+        || a.modified != b.modified
+        || a.size_bytes != b.size_bytes
+        || a.version_hint != b.version_hint
+        || a.is_symlink != b.is_symlink
+        || a.resolved_target != b.resolved_target
+}
 
-    match Command::new("which").arg("claude").output() {
-        Ok(output) if output.status.success() => {
-            let output_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
+/// The first installation carrying exactly `label` (surrounding whitespace ignored)
+pub fn find_by_label<'a>(
+    installs: &'a [ClaudeInstallation],
+    label: &str,
+) -> Option<&'a ClaudeInstallation> {
+    let label = label.trim();
+    installs.iter().find(|install| install.label() == Some(label))
+}
 
-            if output_str.is_empty() {
-                return None;
-            }
+/// Keeps the installations whose known version satisfies `req`, in their original order
+/// Installations without a detected version are left out.
+pub fn filter_by_requirement(
+    installs: &[ClaudeInstallation],
+    req: &VersionReq,
+) -> Vec<ClaudeInstallation> {
+    installs
+        .iter()
+        .filter(|install| install.version.as_ref().is_some_and(|version| version.satisfies(req)))
+        .cloned()
+        .collect()
+}
 
-            // Parse aliased output: "claude: aliased to /path/to/claude"
-            let path = if output_str.starts_with("claude:") && output_str.contains("aliased to") {
-                output_str
-                    .split("aliased to")
-                    .nth(1)
-                    .map(|s| s.trim().to_string())
-            } else {
-                Some(output_str)
-            }?;
+/// Inputs for `resolve_claude`
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+    /// Path the user pinned; used when it still exists and satisfies `min_version`
+    pub preferred_path: Option<String>,
+    /// Config file to read the pinned path from when `preferred_path` isn't given
+    pub pinned_config: Option<PathBuf>,
+    /// Installations older than this are never selected
+    pub min_version: Option<ClaudeVersion>,
+    /// Version range the selected installation must satisfy, e.g. `>=1.2, <2`
+    pub version_requirement: Option<VersionReq>,
+    /// Extra directories to scan for a `claude` binary on top of the standard locations
+    pub extra_dirs: Vec<PathBuf>,
+    /// Reuse a previous discovery younger than this; `None` always rediscovers
+    pub cache_ttl: Option<Duration>,
+    /// Whether stable or pre-release versions win when picking the best installation
+    pub channel: Channel,
+}
 
-            debug!("'which' found claude at: {}", path);
+/// Release channel used to rank installations with known versions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    /// Any stable version beats any pre-release; pre-releases are only a fallback
+    Stable,
+    /// Any pre-release beats any stable version; stable versions are only a fallback
+    Prerelease,
+    /// Plain version order, where `2.0.0-beta` beats `1.9.0`
+    #[default]
+    Any,
+}
 
-            // Verify the path exists
-            if !PathBuf::from(&path).exists() {
-                warn!("Path from 'which' does not exist: {}", path);
-                return None;
-            }
+impl Channel {
+    /// Rank of `version` on this channel; higher ranks win before versions are compared
+    fn rank(self, version: &ClaudeVersion) -> u8 {
+        match self {
+            Channel::Stable => u8::from(!version.is_prerelease()),
+            Channel::Prerelease => u8::from(version.is_prerelease()),
+            Channel::Any => 0,
+        }
+    }
+}
 
-            // Get version
-            let version = get_claude_version(&path).ok().flatten();
+/// Result of `resolve_claude`: the chosen installation plus everything needed to explain it
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResolveOutcome {
+    /// The installation Claudia should run, if any is usable
+    pub selected: Option<ClaudeInstallation>,
+    /// Every installation that was considered, best first
+    pub candidates: Vec<ClaudeInstallation>,
+    /// Human-readable notes about preferences that were ignored and why
+    pub warnings: Vec<String>,
+    /// Set when `version_requirement` ruled out every installation
+    pub unsatisfied: Option<NoSatisfyingVersion>,
+}
 
-            Some(ClaudeInstallation {
-                path,
-                version,
-                source: "which".to_string(),
-            })
+/// No installation satisfied the `version_requirement` passed to `resolve_claude`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NoSatisfyingVersion {
+    /// The requirement, as written by `VersionReq`'s `Display`
+    pub requirement: String,
+    /// Distinct versions that were found, highest first
+    pub available: Vec<ClaudeVersion>,
+}
+
+impl std::fmt::Display for NoSatisfyingVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No Claude installation satisfies {}", self.requirement)?;
+        if self.available.is_empty() {
+            write!(f, " (no versions detected)")
+        } else {
+            let available: Vec<String> = self.available.iter().map(|v| v.to_string()).collect();
+            write!(f, " (available: {})", available.join(", "))
         }
-        _ => None,
     }
 }
 
-/// Find Claude installations in NVM directories
-fn find_nvm_installations() -> Vec<ClaudeInstallation> {
-    let mut installations = Vec::new();
+impl std::error::Error for NoSatisfyingVersion {}
 
-    if let Ok(home) = std::env::var("HOME") {
-        let nvm_dir = PathBuf::from(&home)
-            .join(".nvm")
-            .join("versions")
-            .join("node");
+/// Discovers installations and selects one in a single call
+/// Honors the preferred path when it's still valid, otherwise falls back to the best candidate
+pub fn resolve_claude(opts: ResolveOptions) -> ResolveOutcome {
+    let mut warnings = Vec::new();
 
-        debug!("Checking NVM directory: {:?}", nvm_dir);
+    let mut candidates = match opts.cache_ttl {
+        Some(ttl) => discover_claude_installations_cached(ttl),
+        None => discover_claude_installations(),
+    };
+    for dir in &opts.extra_dirs {
+        let claude_path = dir.join("claude");
+        if crate::path_utils::is_executable_file(&claude_path) {
+            let path = claude_path.to_string_lossy().to_string();
+            let version = get_claude_version(&path).ok().flatten();
+            let mut installation = ClaudeInstallation {
+                path,
+                version,
+                source: "extra-dir".to_string(),
+                ..Default::default()
+            };
+            installation.refresh_metadata();
+            candidates.push(installation);
+        } else {
+            debug!("No claude binary in extra directory {:?}", dir);
+        }
+    }
+    let mut unique_paths = std::collections::HashSet::new();
+    candidates.retain(|install| unique_paths.insert(install.path.clone()));
+    sort_installations(&mut candidates);
 
-        if let Ok(entries) = std::fs::read_dir(&nvm_dir) {
-            for entry in entries.flatten() {
-                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
-                    let claude_path = entry.path().join("bin").join("claude");
+    let meets_minimum = |install: &ClaudeInstallation| match (&opts.min_version, &install.version) {
+        (None, _) => true,
+        (Some(min), Some(version)) => version >= min,
+        (Some(_), None) => false,
+    };
+    let satisfies_requirement = |install: &ClaudeInstallation| {
+        opts.version_requirement.as_ref().is_none_or(|req| {
+            install.version.as_ref().is_some_and(|version| version.satisfies(req))
+        })
+    };
 
-                    if claude_path.exists() && claude_path.is_file() {
-                        let path_str = claude_path.to_string_lossy().to_string();
-                        let node_version = entry.file_name().to_string_lossy().to_string();
+    let preferred_path = opts
+        .preferred_path
+        .clone()
+        .or_else(|| opts.pinned_config.as_deref().and_then(read_pinned_installation));
+    if let Some(preferred) = &preferred_path {
+        let pinned = ClaudeInstallation {
+            path: preferred.clone(),
+            ..Default::default()
+        };
+        if !pinned.command_exists() {
+            let reason = if PathBuf::from(preferred).exists() {
+                "not an executable file"
+            } else {
+                "no longer exists"
+            };
+            warnings.push(format!("Ignored preferred path {}: {}", preferred, reason));
+        } else {
+            let preferred_install = candidates
+                .iter()
+                .find(|install| &install.path == preferred)
+                .cloned()
+                .unwrap_or_else(|| ClaudeInstallation {
+                    path: preferred.clone(),
+                    version: get_claude_version(preferred).ok().flatten(),
+                    source: "preferred".to_string(),
+                    ..Default::default()
+                });
 
-                        debug!("Found Claude in NVM node {}: {}", node_version, path_str);
+            let unmet = if !meets_minimum(&preferred_install) {
+                Some(format!(
+                    "does not meet the minimum {}",
+                    opts.min_version.as_ref().map(|v| v.to_string()).unwrap_or_default()
+                ))
+            } else if !satisfies_requirement(&preferred_install) {
+                Some(format!(
+                    "does not satisfy {}",
+                    opts.version_requirement.as_ref().map(|r| r.to_string()).unwrap_or_default()
+                ))
+            } else {
+                None
+            };
+            let Some(unmet) = unmet else {
+                return ResolveOutcome {
+                    selected: Some(preferred_install),
+                    candidates,
+                    warnings,
+                    unsatisfied: None,
+                };
+            };
+            warnings.push(format!(
+                "Ignored preferred path {}: version {} {}",
+                preferred,
+                preferred_install
+                    .version
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| "unknown".to_string()),
+                unmet
+            ));
+        }
+    }
 
-                        // Get Claude version
-                        let version = get_claude_version(&path_str).ok().flatten();
+    let eligible: Vec<ClaudeInstallation> =
+        candidates.iter().filter(|i| meets_minimum(i)).cloned().collect();
+    if eligible.len() < candidates.len() {
+        warnings.push(format!(
+            "Skipped {} installation(s) below the minimum version",
+            candidates.len() - eligible.len()
+        ));
+    }
 
-                        installations.push(ClaudeInstallation {
-                            path: path_str,
-                            version,
-                            source: format!("nvm ({})", node_version),
-                        });
-                    }
-                }
+    let mut unsatisfied = None;
+    let eligible = match &opts.version_requirement {
+        Some(req) => {
+            let satisfying = filter_by_requirement(&eligible, req);
+            if satisfying.is_empty() {
+                let mut available: Vec<ClaudeVersion> =
+                    candidates.iter().filter_map(|i| i.version.clone()).collect();
+                available.sort_by(|a, b| b.cmp(a));
+                available.dedup();
+                let error = NoSatisfyingVersion {
+                    requirement: req.to_string(),
+                    available,
+                };
+                warnings.push(error.to_string());
+                unsatisfied = Some(error);
             }
+            satisfying
         }
+        None => eligible,
+    };
+
+    let selected = best_installation(eligible, opts.channel);
+    if selected.is_none() {
+        warnings.push("No usable Claude installation found".to_string());
     }
 
-    installations
+    ResolveOutcome {
+        selected,
+        candidates,
+        warnings,
+        unsatisfied,
+    }
 }
 
-/// Check standard installation paths
-fn find_standard_installations() -> Vec<ClaudeInstallation> {
-    let mut installations = Vec::new();
+/// Reads the pinned claude path from a JSON config file like `{ "claude_path": "..." }`
+/// Returns `None` when the file is missing, has no (or an empty) `claude_path`, or is not
+/// valid JSON; the latter is logged as a warning
+pub fn read_pinned_installation(config_path: &std::path::Path) -> Option<String> {
+    let contents = match std::fs::read_to_string(config_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            debug!("No pinned installation config at {:?}: {}", config_path, e);
+            return None;
+        }
+    };
 
-    // Common installation paths for claude
-    let mut paths_to_check: Vec<(String, String)> = vec![
-        ("/usr/local/bin/claude".to_string(), "system".to_string()),
-        (
-            "/opt/homebrew/bin/claude".to_string(),
-            "homebrew".to_string(),
-        ),
-        ("/usr/bin/claude".to_string(), "system".to_string()),
-        ("/bin/claude".to_string(), "system".to_string()),
-    ];
+    let config: serde_json::Value = match serde_json::from_str(&contents) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Ignoring malformed config {:?}: {}", config_path, e);
+            return None;
+        }
+    };
 
-    // Also check user-specific paths
-    if let Ok(home) = std::env::var("HOME") {
-        paths_to_check.extend(vec![
-            (
-                format!("{}/.claude/local/claude", home),
-                "claude-local".to_string(),
-            ),
-            (
-                format!("{}/.local/bin/claude", home),
-                "local-bin".to_string(),
-            ),
-            (
-                format!("{}/.npm-global/bin/claude", home),
-                "npm-global".to_string(),
-            ),
-            (format!("{}/.yarn/bin/claude", home), "yarn".to_string()),
-            (format!("{}/.bun/bin/claude", home), "bun".to_string()),
-            (format!("{}/bin/claude", home), "home-bin".to_string()),
-            // Check common node_modules locations
-            (
-                format!("{}/node_modules/.bin/claude", home),
-                "node-modules".to_string(),
-            ),
-            (
-                format!("{}/.config/yarn/global/node_modules/.bin/claude", home),
-                "yarn-global".to_string(),
-            ),
-        ]);
+    config
+        .get("claude_path")
+        .and_then(serde_json::Value::as_str)
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+}
+
+/// The claude binary a project asks for, looking from `cwd` up to the repository root
+/// Each directory is checked for `.claude/settings.json` with a `binary` field, then a
+/// `package.json` with `claude.binary`; relative paths are taken from that directory. The
+/// nearest config wins, and it's only honored when the path is an executable file.
+pub fn project_claude_hint(cwd: &std::path::Path) -> Option<PathBuf> {
+    for dir in cwd.ancestors() {
+        let configs = [
+            (dir.join(".claude").join("settings.json"), &["binary"][..]),
+            (dir.join("package.json"), &["claude", "binary"][..]),
+        ];
+        for (config_path, field) in configs {
+            let Some(binary) = read_project_binary(&config_path, field) else {
+                continue;
+            };
+            let binary = dir.join(binary);
+            if crate::path_utils::is_executable_file(&binary) {
+                debug!("Project config {:?} selects claude at {:?}", config_path, binary);
+                return Some(binary);
+            }
+            warn!("Project config {:?} points at unusable claude {:?}", config_path, binary);
+            return None;
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
     }
+    None
+}
 
-    // Check each path
-    for (path, source) in paths_to_check {
-        let path_buf = PathBuf::from(&path);
-        if path_buf.exists() && path_buf.is_file() {
-            debug!("Found claude at standard path: {} ({})", path, source);
+/// Reads the string at `field` (a key path) from a JSON config, if it's set and not blank
+fn read_project_binary(config_path: &std::path::Path, field: &[&str]) -> Option<String> {
+    let contents = std::fs::read_to_string(config_path).ok()?;
+    let config: serde_json::Value = match serde_json::from_str(&contents) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Ignoring malformed config {:?}: {}", config_path, e);
+            return None;
+        }
+    };
 
-            // Get version
-            let version = get_claude_version(&path).ok().flatten();
+    field
+        .iter()
+        .try_fold(&config, |value, key| value.get(key))?
+        .as_str()
+        .map(str::trim)
+        .filter(|binary| !binary.is_empty())
+        .map(str::to_string)
+}
 
-            installations.push(ClaudeInstallation {
-                path,
-                version,
-                source,
-            });
+/// Finds the first claude on PATH that is executable and reports a version
+/// Walks PATH entries in order and stops at the first working candidate,
+/// which is much cheaper than a full discovery when only something runnable is needed
+pub fn first_usable_claude(timeout: Duration) -> Option<ClaudeInstallation> {
+    let path_var = std::env::var_os("PATH")?;
+
+    for dir in std::env::split_paths(&path_var) {
+        let candidate = dir.join("claude");
+        if !crate::path_utils::is_executable_file(&candidate) {
+            continue;
+        }
+
+        let path_str = candidate.to_string_lossy().to_string();
+        match get_claude_version_with_timeout(&path_str, timeout) {
+            Some(version) => {
+                debug!("First usable claude on PATH: {} ({})", path_str, version);
+                let mut installation = ClaudeInstallation {
+                    path: path_str,
+                    version: Some(version),
+                    source: "PATH".to_string(),
+                    ..Default::default()
+                };
+                installation.refresh_metadata();
+                return Some(installation);
+            }
+            None => debug!("Skipping unusable claude candidate: {}", path_str),
         }
     }
 
-    // Also check if claude is available in PATH (without full path)
-    if let Ok(output) = Command::new("claude").arg("--version").output() {
-        if output.status.success() {
-            debug!("claude is available in PATH");
-            let version = extract_version_from_output(&output.stdout);
+    None
+}
 
-            installations.push(ClaudeInstallation {
-                path: "claude".to_string(),
-                version,
-                source: "PATH".to_string(),
-            });
-        }
+/// Returns a preference score for installation sources (lower is better)
+fn source_preference(installation: &ClaudeInstallation) -> u8 {
+    match installation.source.as_str() {
+        "which" => 1,
+        "homebrew" => 2,
+        "system" => 3,
+        source if source.starts_with("nvm") => 4,
+        "local-bin" => 5,
+        "claude-local" => 6,
+        "npm-global" | "npm-prefix" | "pnpm" => 7,
+        "yarn" | "yarn-global" => 8,
+        "bun" => 9,
+        "node-modules" => 10,
+        "home-bin" | "cargo-bin" => 11,
+        "PATH" => 12,
+        _ => 13,
     }
+}
+
+/// Discovers installations of the given types only, e.g. to rescan just NVM after
+/// installing a new node version without re-probing Homebrew and system paths.
+/// Passing `InstallationType::ALL` is equivalent to `discover_claude_installations`.
+pub fn discover_claude_installations_filtered(types: &[InstallationType]) -> Vec<ClaudeInstallation> {
+    info!("Discovering Claude installations of types {:?}...", types);
 
+    let mut installations = discover_all_installations_filtered(types, true);
+    sort_installations(&mut installations);
     installations
 }
 
-/// Get Claude version by running --version command
-fn get_claude_version(path: &str) -> Result<Option<String>, String> {
-    match Command::new(path).arg("--version").output() {
-        Ok(output) => {
-            if output.status.success() {
-                Ok(extract_version_from_output(&output.stdout))
-            } else {
-                Ok(None)
-            }
-        }
-        Err(e) => {
-            warn!("Failed to get version for {}: {}", path, e);
-            Ok(None)
+/// Discovers installations by scanning the filesystem only, never spawning a process
+/// For sandboxed environments where running candidate binaries is blocked or unsafe.
+/// Every result has `version: None`; `version_hint` is filled in when the path reveals
+/// something (currently the node version of nvm installs). Sources that can only be
+/// queried by running a command (`which`, `flatpak list`) are skipped.
+pub fn discover_claude_installations_no_exec() -> Vec<ClaudeInstallation> {
+    info!("Discovering Claude installations without executing anything...");
+
+    let _guard = ExecGuard::forbid();
+    let mut installations = discover_all_installations_filtered(InstallationType::ALL, false);
+    sort_installations(&mut installations);
+    installations
+}
+
+thread_local! {
+    static EXEC_FORBIDDEN: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Forbids discovery on the current thread from spawning processes while alive
+struct ExecGuard {
+    previous: bool,
+}
+
+impl ExecGuard {
+    fn forbid() -> Self {
+        ExecGuard {
+            previous: EXEC_FORBIDDEN.with(|forbidden| forbidden.replace(true)),
         }
     }
 }
 
-/// Extract version string from command output
-fn extract_version_from_output(stdout: &[u8]) -> Option<String> {
-    let output_str = String::from_utf8_lossy(stdout);
+impl Drop for ExecGuard {
+    fn drop(&mut self) {
+        EXEC_FORBIDDEN.with(|forbidden| forbidden.set(self.previous));
+    }
+}
 
-    // Extract version: first token before whitespace that looks like a version
-    output_str
-        .split_whitespace()
-        .find(|token| {
-            // Version usually contains dots and numbers
-            token.chars().any(|c| c == '.') && token.chars().any(|c| c.is_numeric())
-        })
-        .map(|s| s.to_string())
+/// Called before every discovery subprocess; a no-exec discovery reaching one is a bug
+fn assert_exec_allowed(program: &str) {
+    if EXEC_FORBIDDEN.with(|forbidden| forbidden.get()) {
+        panic!("attempted to run {} during no-exec discovery", program);
+    }
 }
 
-/// Select the best installation based on version
-fn select_best_installation(installations: Vec<ClaudeInstallation>) -> Option<ClaudeInstallation> {
-    // In production builds, version information may not be retrievable because
-    // spawning external processes can be restricted. We therefore no longer
-    // discard installations that lack a detected version – the mere presence
-    // of a readable binary on disk is enough to consider it valid. We still
-    // prefer binaries with version information when it is available so that
-    // in development builds we keep the previous behaviour of picking the
-    // most recent version.
-    installations.into_iter().max_by(|a, b| {
-        match (&a.version, &b.version) {
-            // If both have versions, compare them semantically.
-            (Some(v1), Some(v2)) => compare_versions(v1, v2),
-            // Prefer the entry that actually has version information.
-            (Some(_), None) => Ordering::Greater,
-            (None, Some(_)) => Ordering::Less,
-            // Neither have version info: prefer the one that is not just
-            // the bare "claude" lookup from PATH, because that may fail
-            // at runtime if PATH is sandbox-stripped.
-            (None, None) => {
-                if a.path == "claude" && b.path != "claude" {
-                    Ordering::Less
-                } else if a.path != "claude" && b.path == "claude" {
-                    Ordering::Greater
-                } else {
-                    Ordering::Equal
-                }
-            }
-        }
-    })
+/// Discovers all Claude installations on the system
+fn discover_all_installations() -> Vec<ClaudeInstallation> {
+    discover_all_installations_filtered(InstallationType::ALL, true)
 }
 
-/// Compare two version strings
-fn compare_versions(a: &str, b: &str) -> Ordering {
-    // Simple semantic version comparison
-    let a_parts: Vec<u32> = a
-        .split('.')
-        .filter_map(|s| {
-            // Handle versions like "1.0.17-beta" by taking only numeric part
-            s.chars()
-                .take_while(|c| c.is_numeric())
-                .collect::<String>()
-                .parse()
-                .ok()
-        })
-        .collect();
+/// Runs only the discovery sources that can produce one of `types`
+/// With `allow_exec` unset, sources that need a subprocess are skipped and versions are
+/// left unprobed.
+fn discover_all_installations_filtered(
+    types: &[InstallationType],
+    allow_exec: bool,
+) -> Vec<ClaudeInstallation> {
+    let mut installations = collect_installations(types, allow_exec);
+    if allow_exec {
+        let workers = probe_worker_count(installations.len());
+        probe_versions_with(&mut installations, workers, probe_installation_version);
+    }
+    installations
+}
 
-    let b_parts: Vec<u32> = b
-        .split('.')
-        .filter_map(|s| {
-            s.chars()
-                .take_while(|c| c.is_numeric())
-                .collect::<String>()
-                .parse()
-                .ok()
-        })
-        .collect();
+/// Finds the candidates `discover_all_installations_filtered` reports, without probing
+/// their versions
+fn collect_installations(types: &[InstallationType], allow_exec: bool) -> Vec<ClaudeInstallation> {
+    collect_installations_until(types, allow_exec, None).0
+}
+
+/// `collect_installations`, skipping the sources not yet searched once `deadline` passes;
+/// also returns whether any were skipped
+fn collect_installations_until(
+    types: &[InstallationType],
+    allow_exec: bool,
+    deadline: Option<Instant>,
+) -> (Vec<ClaudeInstallation>, bool) {
+    let wants = |install_type: InstallationType| types.contains(&install_type);
+    let mut skipped = false;
+    // Whether the next source still fits in the deadline
+    let mut in_time = || {
+        skipped |= deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        !skipped
+    };
+    let mut installations = Vec::new();
 
-    // Compare each part
-    for i in 0..std::cmp::max(a_parts.len(), b_parts.len()) {
-        let a_val = a_parts.get(i).unwrap_or(&0);
-        let b_val = b_parts.get(i).unwrap_or(&0);
-        match a_val.cmp(b_val) {
-            Ordering::Equal => continue,
-            other => return other,
+    // 1. Try 'which' command first (now works in production); without exec, walk PATH
+    if wants(InstallationType::Direct) && in_time() {
+        let found = if allow_exec {
+            try_which_command()
+        } else {
+            find_on_path_without_exec()
+        };
+        if let Some(installation) = found {
+            installations.push(installation);
         }
     }
 
-    Ordering::Equal
-}
+    // 2. Check NVM paths
+    if wants(InstallationType::Nvm) && in_time() {
+        installations.extend(find_nvm_installations());
+    }
 
-/// Helper function to create a Command with proper environment variables
-/// This ensures commands like Claude can find Node.js and other dependencies
-pub fn create_command_with_env(program: &str) -> Command {
-    let mut cmd = Command::new(program);
+    // 3. Check standard paths
+    if in_time() {
+        installations.extend(find_standard_installations(types, allow_exec));
+    }
 
-    // Inherit essential environment variables from parent process
-    for (key, value) in std::env::vars() {
-        // Pass through PATH and other essential environment variables
-        if key == "PATH"
-            || key == "HOME"
-            || key == "USER"
-            || key == "SHELL"
-            || key == "LANG"
-            || key == "LC_ALL"
-            || key.starts_with("LC_")
-            || key == "NODE_PATH"
-            || key == "NVM_DIR"
-            || key == "NVM_BIN"
-            || key == "HOMEBREW_PREFIX"
-            || key == "HOMEBREW_CELLAR"
-        {
-            debug!("Inheriting env var: {}={}", key, value);
-            cmd.env(&key, &value);
+    // 4. Check pnpm's global bin directory
+    if wants(InstallationType::Pnpm) && in_time() {
+        installations.extend(find_pnpm_installations(allow_exec));
+    }
+
+    // 5. Check the bin directory of npm's configured global prefix
+    if wants(InstallationType::Npm) && in_time() {
+        installations.extend(find_npm_prefix_installations(allow_exec));
+    }
+
+    // 6. Check Linux package managers (Snap, Flatpak)
+    #[cfg(target_os = "linux")]
+    if (wants(InstallationType::Snap) || wants(InstallationType::Flatpak)) && in_time() {
+        installations.extend(find_linux_package_installations(allow_exec));
+    }
+
+    // 7. On Windows, resolve claude.exe/.cmd/... via PATHEXT
+    #[cfg(windows)]
+    if wants(InstallationType::Direct) && in_time() {
+        installations.extend(find_windows_installations());
+    }
+
+    // 8. On macOS, look inside Claude*.app bundles (Applications folders, Homebrew Caskroom)
+    #[cfg(target_os = "macos")]
+    if wants(InstallationType::AppBundle) && in_time() {
+        let dirs = app_bundle_search_dirs();
+        installations.extend(find_app_bundle_installations(&dirs, allow_exec));
+    }
+
+    // 9. Scan the extra directories listed in CLAUDIA_CLAUDE_SEARCH_PATHS
+    if wants(InstallationType::Custom) && in_time() {
+        for dir in search_paths_from_env() {
+            let found = find_in_bin_dir(&dir, "env-search-paths", InstallationType::Custom);
+            installations.extend(found);
         }
     }
 
-    // Add NVM support if the program is in an NVM directory
-    if program.contains("/.nvm/versions/node/") {
-        if let Some(node_bin_dir) = std::path::Path::new(program).parent() {
-            // Ensure the Node.js bin directory is in PATH
-            let node_bin_str = node_bin_dir.to_string_lossy();
-            let new_path = crate::path_utils::add_to_path_if_missing(&node_bin_str);
-            
-            // Only set PATH if it was modified
-            if new_path != std::env::var("PATH").unwrap_or_default() {
-                debug!("Adding NVM bin directory to PATH: {}", node_bin_str);
-                cmd.env("PATH", new_path);
+    // Sources can yield several types, so filter the combined result as well
+    installations.retain(|install| wants(install.install_type));
+
+    // Remove duplicates by path
+    let mut unique_paths = std::collections::HashSet::new();
+    installations.retain(|install| unique_paths.insert(install.path.clone()));
+
+    for installation in &mut installations {
+        installation.refresh_metadata();
+    }
+
+    (installations, skipped)
+}
+
+/// Number of concurrent `--version` probes: one per candidate, capped at the CPU count
+/// Dozens of nvm versions would otherwise mean dozens of node processes at once.
+fn probe_worker_count(candidates: usize) -> usize {
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    candidates.min(cpus).max(1)
+}
+
+/// Fills in missing versions by running `probe` on at most `max_workers` threads at a time
+fn probe_versions_with<F>(installations: &mut [ClaudeInstallation], max_workers: usize, probe: F)
+where
+    F: Fn(&ClaudeInstallation) -> Option<ClaudeVersion> + Sync,
+{
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    let pending: Vec<usize> = (0..installations.len())
+        .filter(|&i| installations[i].version.is_none())
+        .collect();
+    if pending.is_empty() {
+        return;
+    }
+
+    let next = AtomicUsize::new(0);
+    let probed = Mutex::new(Vec::with_capacity(pending.len()));
+    {
+        let targets: &[ClaudeInstallation] = installations;
+        std::thread::scope(|scope| {
+            for _ in 0..max_workers.clamp(1, pending.len()) {
+                scope.spawn(|| {
+                    while let Some(&i) = pending.get(next.fetch_add(1, AtomicOrdering::Relaxed)) {
+                        let version = probe(&targets[i]);
+                        probed.lock().unwrap_or_else(|e| e.into_inner()).push((i, version));
+                    }
+                });
             }
-        }
+        });
     }
 
-    cmd
+    for (i, version) in probed.into_inner().unwrap_or_else(|e| e.into_inner()) {
+        installations[i].version = version;
+    }
+}
+
+/// Runs discovery in the background, yielding each installation once it has been probed
+/// Items arrive in completion order rather than sorted, so a UI can list installations as
+/// they're found on machines with many node versions. Probing stops early when the stream
+/// is dropped. Must be called from within a tokio runtime.
+pub fn discover_claude_installations_stream() -> impl futures::Stream<Item = ClaudeInstallation> {
+    let (sender, receiver) = futures::channel::mpsc::unbounded();
+    tokio::task::spawn_blocking(move || {
+        let candidates = collect_installations(InstallationType::ALL, true);
+        let workers = probe_worker_count(candidates.len());
+        probe_each_with(candidates, workers, probe_installation_version, |installation| {
+            sender.unbounded_send(installation).is_ok()
+        });
+    });
+    receiver
+}
+
+/// Probes every installation on at most `max_workers` threads, handing each to `done` as
+/// soon as its probe finishes; stops taking new candidates once `done` returns false
+fn probe_each_with<F, D>(
+    installations: Vec<ClaudeInstallation>,
+    max_workers: usize,
+    probe: F,
+    done: D,
+) where
+    F: Fn(&ClaudeInstallation) -> Option<ClaudeVersion> + Sync,
+    D: Fn(ClaudeInstallation) -> bool + Sync,
+{
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+
+    if installations.is_empty() {
+        return;
+    }
+    let next = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        for _ in 0..max_workers.clamp(1, installations.len()) {
+            scope.spawn(|| {
+                while !stopped.load(AtomicOrdering::Relaxed) {
+                    let Some(candidate) =
+                        installations.get(next.fetch_add(1, AtomicOrdering::Relaxed))
+                    else {
+                        break;
+                    };
+                    let mut installation = candidate.clone();
+                    if installation.version.is_none() {
+                        installation.version = probe(&installation);
+                    }
+                    if !done(installation) {
+                        stopped.store(true, AtomicOrdering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// Like `probe_versions_with`, but gives up at `deadline`; returns whether it had to
+/// Workers take no new candidate once the deadline has passed. Probes still running then
+/// are abandoned with their results discarded, so the installations they were for keep
+/// `version: None`; `probe` should stop its child by the deadline itself.
+fn probe_versions_until<F>(
+    installations: &mut [ClaudeInstallation],
+    max_workers: usize,
+    deadline: Instant,
+    probe: F,
+) -> bool
+where
+    F: Fn(&ClaudeInstallation) -> Option<ClaudeVersion> + Send + Sync + 'static,
+{
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::sync::Arc;
+
+    let pending: Arc<Vec<(usize, ClaudeInstallation)>> = Arc::new(
+        installations
+            .iter()
+            .enumerate()
+            .filter(|(_, install)| install.version.is_none())
+            .map(|(i, install)| (i, install.clone()))
+            .collect(),
+    );
+    if pending.is_empty() {
+        return false;
+    }
+
+    let next = Arc::new(AtomicUsize::new(0));
+    let probe = Arc::new(probe);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for _ in 0..max_workers.clamp(1, pending.len()) {
+        let (pending, next, probe, sender) =
+            (pending.clone(), next.clone(), probe.clone(), sender.clone());
+        std::thread::spawn(move || {
+            while Instant::now() < deadline {
+                let Some((i, install)) = pending.get(next.fetch_add(1, AtomicOrdering::Relaxed))
+                else {
+                    break;
+                };
+                if sender.send((*i, probe(install))).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(sender);
+
+    let mut received = 0;
+    while received < pending.len() {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(wait) {
+            Ok((i, version)) => {
+                installations[i].version = version;
+                received += 1;
+            }
+            // Timed out, or every worker stopped at the deadline
+            Err(_) => break,
+        }
+    }
+    received < pending.len()
+}
+
+/// Installations found by `discover_claude_installations_with_deadline`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BoundedDiscovery {
+    pub installations: Vec<ClaudeInstallation>,
+    /// Whether the deadline cut the search or version probing short; unprobed installations
+    /// are still listed, with `version: None`
+    pub truncated: bool,
+}
+
+/// Discovers all installations like `discover_claude_installations`, but stops searching
+/// and probing versions once `deadline` passes
+/// Bounds the whole operation when many candidates (or a slow network home) would make
+/// the per-probe timeouts add up. No probe outlives the deadline: each is killed when it
+/// reaches it.
+pub fn discover_claude_installations_with_deadline(deadline: Instant) -> BoundedDiscovery {
+    info!("Discovering all Claude installations with a deadline...");
+
+    let (mut installations, skipped) =
+        collect_installations_until(InstallationType::ALL, true, Some(deadline));
+    let workers = probe_worker_count(installations.len());
+    let abandoned = probe_versions_until(&mut installations, workers, deadline, move |install| {
+        let budget = deadline.saturating_duration_since(Instant::now());
+        probe_installation_version_within(install, &[], budget)
+    });
+    let truncated = skipped || abandoned;
+    if truncated {
+        warn!("Discovery deadline passed, some installations were not probed");
+    }
+    sort_installations(&mut installations);
+    BoundedDiscovery {
+        installations,
+        truncated,
+    }
+}
+
+/// Runs `--version` for a discovered installation, through its launcher if it has one
+fn probe_installation_version(installation: &ClaudeInstallation) -> Option<ClaudeVersion> {
+    probe_installation_version_with_env(installation, &[])
+}
+
+/// `probe_installation_version` with `env` set on top of the ambient environment
+fn probe_installation_version_with_env(
+    installation: &ClaudeInstallation,
+    env: &[(String, String)],
+) -> Option<ClaudeVersion> {
+    probe_installation_version_within(installation, env, VERSION_PROBE_TIMEOUT)
+}
+
+/// `probe_installation_version_with_env`, killing the probe once `budget` runs out
+fn probe_installation_version_within(
+    installation: &ClaudeInstallation,
+    env: &[(String, String)],
+    budget: Duration,
+) -> Option<ClaudeVersion> {
+    match &installation.invocation {
+        #[cfg(target_os = "linux")]
+        Some(invocation) if installation.install_type == InstallationType::Flatpak => {
+            let mut cmd = Command::new(&invocation[0]);
+            cmd.args(&invocation[1..]).arg("--version");
+            cmd.envs(env.iter().map(|(key, value)| (key, value)));
+            match output_with_timeout(cmd, FLATPAK_PROBE_TIMEOUT.min(budget)) {
+                Ok(Some(output)) if output.status.success() => {
+                    extract_version_from_output(&output.stdout)
+                }
+                _ => None,
+            }
+        }
+        _ => {
+            assert_exec_allowed(&installation.path);
+            get_claude_version_with_env(&installation.path, VERSION_PROBE_TIMEOUT.min(budget), env)
+        }
+    }
+}
+
+/// Discovers all installations like `discover_claude_installations`, probing versions with
+/// `extra` set in the probe's environment
+/// For claudes whose behavior depends on variables Claudia will launch them with (such as
+/// `CLAUDE_CONFIG_DIR`), so discovery sees what the real session will.
+pub fn discover_claude_installations_with_env(
+    extra: &[(String, String)],
+) -> Vec<ClaudeInstallation> {
+    info!("Discovering all Claude installations with {} extra env vars...", extra.len());
+
+    let mut installations = collect_installations(InstallationType::ALL, true);
+    let workers = probe_worker_count(installations.len());
+    probe_versions_with(&mut installations, workers, |installation| {
+        probe_installation_version_with_env(installation, extra)
+    });
+    sort_installations(&mut installations);
+    installations
+}
+
+/// The claude version of an installation and of the node interpreter that runs it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RuntimeVersions {
+    pub claude: Option<ClaudeVersion>,
+    /// `None` for native binaries and when node's version couldn't be determined
+    pub node: Option<NodeVersion>,
+}
+
+/// Probes claude's version and its node's with as few launches as possible
+/// claude runs once, as in a normal version probe. For node shims the interpreter is read
+/// from the shebang; nvm encodes its version in the path, so only node outside nvm costs an
+/// extra (claude-free) `node --version`.
+pub fn probe_runtime_versions(installation: &ClaudeInstallation) -> RuntimeVersions {
+    let node = find_shim_node(std::path::Path::new(&installation.path))
+        .and_then(|node| node_version_of(&node));
+
+    RuntimeVersions {
+        claude: probe_installation_version(installation),
+        node,
+    }
+}
+
+/// Version of the node interpreter at `node`: read from an nvm path, else `node --version`
+fn node_version_of(node: &std::path::Path) -> Option<NodeVersion> {
+    nvm_node_version(node).or_else(|| {
+        let mut cmd = Command::new(node);
+        cmd.arg("--version");
+        match output_with_timeout(cmd, VERSION_PROBE_TIMEOUT) {
+            Ok(Some(output)) if output.status.success() => {
+                NodeVersion::parse(&String::from_utf8_lossy(&output.stdout))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// The node a claude node shim runs with differs in major version from the shell's `node`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NodeMismatch {
+    /// Interpreter the installation's shebang resolves to
+    pub install_node: PathBuf,
+    pub install_version: NodeVersion,
+    /// First `node` on the current PATH
+    pub active_node: PathBuf,
+    pub active_version: NodeVersion,
+}
+
+impl std::fmt::Display for NodeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Your active node is {} ({}) but this claude needs {} ({})",
+            self.active_version.major,
+            self.active_node.display(),
+            self.install_version.major,
+            self.install_node.display()
+        )
+    }
+}
+
+/// Compares the node an installation runs with against the `node` on the current PATH
+/// `None` when the major versions agree, the installation isn't a node shim, or either
+/// version can't be determined.
+pub fn node_version_mismatch(install: &ClaudeInstallation) -> Option<NodeMismatch> {
+    let install_node = find_shim_node(std::path::Path::new(&install.path))?;
+    let active_node = which_in_path("node", &std::env::var("PATH").unwrap_or_default())?;
+
+    let install_version = node_version_of(&install_node)?;
+    let active_version = node_version_of(&active_node)?;
+    (install_version.major != active_version.major).then_some(NodeMismatch {
+        install_node,
+        install_version,
+        active_node,
+        active_version,
+    })
+}
+
+/// Where claude will look for its configuration, for diagnosing profiles that don't load
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConfigDirProbe {
+    /// `CLAUDE_CONFIG_DIR` when set, else the platform default `~/.claude`; `None` when no
+    /// home directory is known
+    pub config_dir: Option<PathBuf>,
+    /// Whether `config_dir` is an existing directory
+    pub exists: bool,
+}
+
+/// Reports claude's config directory the way claude resolves it, without launching it
+pub fn probe_config_dir() -> ConfigDirProbe {
+    let config_dir = std::env::var_os("CLAUDE_CONFIG_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".claude")));
+    let exists = config_dir.as_deref().is_some_and(std::path::Path::is_dir);
+    ConfigDirProbe { config_dir, exists }
+}
+
+/// Finds the first executable `claude` on PATH by looking at the filesystem only
+fn find_on_path_without_exec() -> Option<ClaudeInstallation> {
+    path_resolved_claude().map(|candidate| ClaudeInstallation {
+        path: candidate.to_string_lossy().to_string(),
+        source: "PATH".to_string(),
+        discovered_via: DiscoveryMethod::Which,
+        ..Default::default()
+    })
+}
+
+/// Try using the 'which' command to find Claude
+fn try_which_command() -> Option<ClaudeInstallation> {
+    debug!("Trying 'which claude' to find binary...");
+
+    assert_exec_allowed("which");
+    match Command::new("which").arg("claude").output() {
+        Ok(output) if output.status.success() => {
+            let output_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+            if output_str.is_empty() {
+                return None;
+            }
+
+            // Parse aliased output: "claude: aliased to /path/to/claude"
+            let path = if output_str.starts_with("claude:") && output_str.contains("aliased to") {
+                output_str
+                    .split("aliased to")
+                    .nth(1)
+                    .map(|s| s.trim().to_string())
+            } else {
+                Some(output_str)
+            }?;
+
+            debug!("'which' found claude at: {}", path);
+
+            // Verify the path exists
+            if !PathBuf::from(&path).exists() {
+                warn!("Path from 'which' does not exist: {}", path);
+                return None;
+            }
+
+            Some(ClaudeInstallation {
+                path,
+                source: "which".to_string(),
+                discovered_via: DiscoveryMethod::Which,
+                ..Default::default()
+            })
+        }
+        _ => None,
+    }
+}
+
+/// A node version as nvm names its install directories, e.g. `v20.3.1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NodeVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl NodeVersion {
+    /// Parses `v20.3.1` or `20.3.1`, as printed by `node --version`
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let mut parts = s.strip_prefix('v').unwrap_or(s).split('.');
+        let mut next = || parts.next()?.parse::<u32>().ok();
+        let version = NodeVersion {
+            major: next()?,
+            minor: next()?,
+            patch: next()?,
+        };
+        next().is_none().then_some(version)
+    }
+}
+
+impl std::fmt::Display for NodeVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The node version of an nvm path such as `~/.nvm/versions/node/v20.3.1/bin/claude`
+/// `None` unless the path has a `versions/node/v<major>.<minor>.<patch>` segment.
+pub fn nvm_node_version(path: &std::path::Path) -> Option<NodeVersion> {
+    let components: Vec<_> = path.components().map(|c| c.as_os_str()).collect();
+    components.windows(3).find_map(|window| {
+        if window[0] != "versions" || window[1] != "node" {
+            return None;
+        }
+        let segment = window[2].to_str()?;
+        segment.starts_with('v').then(|| NodeVersion::parse(segment))?
+    })
+}
+
+/// PATH entries under an nvm node version that no longer exists, e.g. after `nvm uninstall`
+/// These are the missing entries `audit_path` reports, narrowed to nvm version directories,
+/// so they can be offered for pruning.
+pub fn stale_nvm_entries(path: &str) -> Vec<String> {
+    crate::path_utils::audit_path(path)
+        .missing
+        .into_iter()
+        .filter(|entry| nvm_node_version(std::path::Path::new(entry)).is_some())
+        .collect()
+}
+
+/// Find Claude installations in NVM directories
+fn find_nvm_installations() -> Vec<ClaudeInstallation> {
+    let mut installations = Vec::new();
+
+    if let Ok(home) = std::env::var("HOME") {
+        let nvm_dir = PathBuf::from(&home)
+            .join(".nvm")
+            .join("versions")
+            .join("node");
+
+        debug!("Checking NVM directory: {:?}", nvm_dir);
+
+        if let Ok(entries) = std::fs::read_dir(&nvm_dir) {
+            for entry in entries.flatten() {
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    let claude_path = entry.path().join("bin").join("claude");
+
+                    if claude_path.exists() && claude_path.is_file() {
+                        let path_str = claude_path.to_string_lossy().to_string();
+                        let node_version = entry.file_name().to_string_lossy().to_string();
+
+                        debug!("Found Claude in NVM node {}: {}", node_version, path_str);
+
+                        installations.push(ClaudeInstallation {
+                            path: path_str,
+                            source: format!("nvm ({})", node_version),
+                            install_type: InstallationType::Nvm,
+                            version_hint: Some(format!("node {}", node_version)),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    installations
+}
+
+/// Check standard installation paths, skipping those that can't yield one of `types`
+fn find_standard_installations(
+    types: &[InstallationType],
+    allow_exec: bool,
+) -> Vec<ClaudeInstallation> {
+    let mut installations = Vec::new();
+
+    // Check each path
+    for (path, source, install_type) in standard_candidates(types, allow_exec) {
+        let path_buf = PathBuf::from(&path);
+        if path_buf.exists() && path_buf.is_file() {
+            debug!("Found claude at standard path: {} ({})", path, source);
+
+            installations.push(ClaudeInstallation {
+                path,
+                source,
+                install_type,
+                ..Default::default()
+            });
+        }
+    }
+
+    // Also check if claude is available in PATH (without full path)
+    if !types.contains(&InstallationType::Direct) || !allow_exec {
+        return installations;
+    }
+    assert_exec_allowed("claude");
+    if let Ok(output) = Command::new("claude").arg("--version").output() {
+        if output.status.success() {
+            debug!("claude is available in PATH");
+            let version = extract_version_from_output(&output.stdout);
+
+            installations.push(ClaudeInstallation {
+                path: "claude".to_string(),
+                version,
+                source: "PATH".to_string(),
+                ..Default::default()
+            });
+        }
+    }
+
+    installations
+}
+
+/// Well-known claude locations as `(path, source)` pairs, in lookup order
+fn standard_claude_paths(types: &[InstallationType], allow_exec: bool) -> Vec<(String, String)> {
+    // Homebrew prefixes come first so a brew-managed /usr/local is tagged as Homebrew
+    let mut paths_to_check: Vec<(String, String)> = Vec::new();
+    if types.contains(&InstallationType::Homebrew) {
+        let prefixes = if allow_exec {
+            homebrew_prefixes()
+        } else {
+            default_homebrew_prefixes()
+        };
+        for prefix in prefixes {
+            let claude_path = prefix.join("bin").join("claude");
+            paths_to_check.push((
+                claude_path.to_string_lossy().to_string(),
+                "homebrew".to_string(),
+            ));
+        }
+    }
+
+    // Common installation paths for claude
+    paths_to_check.extend(vec![
+        ("/usr/local/bin/claude".to_string(), "system".to_string()),
+        ("/usr/bin/claude".to_string(), "system".to_string()),
+        ("/bin/claude".to_string(), "system".to_string()),
+    ]);
+
+    // Also check user-specific paths
+    if let Ok(home) = std::env::var("HOME") {
+        paths_to_check.extend(vec![
+            (
+                format!("{}/.claude/local/claude", home),
+                "claude-local".to_string(),
+            ),
+            (
+                format!("{}/.local/bin/claude", home),
+                "local-bin".to_string(),
+            ),
+            (
+                format!("{}/.npm-global/bin/claude", home),
+                "npm-global".to_string(),
+            ),
+            (format!("{}/.yarn/bin/claude", home), "yarn".to_string()),
+            (format!("{}/.bun/bin/claude", home), "bun".to_string()),
+            (format!("{}/bin/claude", home), "home-bin".to_string()),
+            (format!("{}/.cargo/bin/claude", home), "cargo-bin".to_string()),
+            // Check common node_modules locations
+            (
+                format!("{}/node_modules/.bin/claude", home),
+                "node-modules".to_string(),
+            ),
+            (
+                format!("{}/.config/yarn/global/node_modules/.bin/claude", home),
+                "yarn-global".to_string(),
+            ),
+        ]);
+    }
+
+    paths_to_check
+}
+
+/// Installation type of a claude found at `path` by other means than scanning, such as a
+/// PATH lookup: that of the matching standard location or nvm directory, else `Direct`
+fn installation_type_of_path(path: &std::path::Path) -> InstallationType {
+    if path.to_string_lossy().contains("/.nvm/versions/node/") {
+        return InstallationType::Nvm;
+    }
+    standard_candidates(InstallationType::ALL, false)
+        .into_iter()
+        .find(|(candidate, _, _)| std::path::Path::new(candidate) == path)
+        .map_or(InstallationType::Direct, |(_, _, install_type)| install_type)
+}
+
+/// The `standard_claude_paths` entries that can yield one of `types`, as
+/// `(path, source, type)`; what both discovery and its reported search locations go by
+fn standard_candidates(
+    types: &[InstallationType],
+    allow_exec: bool,
+) -> Vec<(String, String, InstallationType)> {
+    standard_claude_paths(types, allow_exec)
+        .into_iter()
+        .filter_map(|(path, source)| {
+            let install_type = standard_source_type(&source);
+            types
+                .contains(&install_type)
+                .then_some((path, source, install_type))
+        })
+        .collect()
+}
+
+/// Installation type of a `standard_claude_paths` source
+fn standard_source_type(source: &str) -> InstallationType {
+    match source {
+        "system" => InstallationType::System,
+        "homebrew" => InstallationType::Homebrew,
+        "local-bin" | "home-bin" | "cargo-bin" => InstallationType::Standalone,
+        _ => InstallationType::Direct,
+    }
+}
+
+/// Homebrew installation prefixes to search for claude
+/// Asks `brew --prefix` when brew is on PATH, otherwise falls back to the platform defaults
+pub fn homebrew_prefixes() -> Vec<PathBuf> {
+    if let Some(brew) = find_executable_on_path("brew") {
+        let mut cmd = Command::new(&brew);
+        cmd.arg("--prefix");
+        match output_with_timeout(cmd, BREW_PREFIX_TIMEOUT) {
+            Ok(Some(output)) if output.status.success() => {
+                let prefix = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                if !prefix.is_empty() {
+                    debug!("brew --prefix reported {}", prefix);
+                    return vec![PathBuf::from(prefix)];
+                }
+            }
+            _ => warn!("Failed to run {:?} --prefix, using default prefixes", brew),
+        }
+    }
+
+    default_homebrew_prefixes()
+}
+
+/// Looks up an executable on PATH by checking the filesystem, without running `which`
+fn find_executable_on_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| crate::path_utils::is_executable_file(candidate))
+}
+
+/// `brew --prefix` can be slow on first run, but discovery shouldn't hang on it
+const BREW_PREFIX_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where Homebrew installs by default: Apple Silicon and Intel Macs, then Linuxbrew
+fn default_homebrew_prefixes() -> Vec<PathBuf> {
+    let mut prefixes = Vec::new();
+    if cfg!(target_os = "macos") {
+        prefixes.push(PathBuf::from("/opt/homebrew"));
+        prefixes.push(PathBuf::from("/usr/local"));
+    } else if cfg!(target_os = "linux") {
+        prefixes.push(PathBuf::from("/home/linuxbrew/.linuxbrew"));
+        if let Ok(home) = std::env::var("HOME") {
+            prefixes.push(PathBuf::from(home).join(".linuxbrew"));
+        }
+    }
+    prefixes
+}
+
+/// Check pnpm's global bin directory for a claude shim
+fn find_pnpm_installations(allow_exec: bool) -> Vec<ClaudeInstallation> {
+    let Some(bin_dir) = pnpm_global_bin_dir(allow_exec) else {
+        return Vec::new();
+    };
+    debug!("Checking pnpm global bin directory: {:?}", bin_dir);
+    find_in_bin_dir(&bin_dir, "pnpm", InstallationType::Pnpm)
+}
+
+/// Check the bin directory under npm's global prefix for claude
+fn find_npm_prefix_installations(allow_exec: bool) -> Vec<ClaudeInstallation> {
+    let Some(prefix) = npm_global_prefix(allow_exec) else {
+        return Vec::new();
+    };
+    // npm puts global bins in <prefix>/bin on Unix but directly in <prefix> on Windows
+    let bin_dir = if cfg!(windows) { prefix } else { prefix.join("bin") };
+    debug!("Checking npm prefix bin directory: {:?}", bin_dir);
+    find_in_bin_dir(&bin_dir, "npm-prefix", InstallationType::Npm)
+}
+
+/// Lists the claude binaries (every PATHEXT variant on Windows) in a package manager's bin dir
+fn find_in_bin_dir(
+    bin_dir: &std::path::Path,
+    source: &str,
+    install_type: InstallationType,
+) -> Vec<ClaudeInstallation> {
+    let candidates = if cfg!(windows) {
+        windows_executable_candidates(bin_dir, "claude")
+    } else {
+        vec![bin_dir.join("claude")]
+    };
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.is_file())
+        .map(|candidate| ClaudeInstallation {
+            path: candidate.to_string_lossy().to_string(),
+            source: source.to_string(),
+            install_type,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Runs a package manager found on PATH and returns the first line it prints
+/// `None` when the tool isn't installed, fails, times out or prints nothing.
+fn query_package_manager(name: &str, args: &[&str]) -> Option<String> {
+    let tool = find_executable_on_path(name)?;
+    let mut cmd = Command::new(&tool);
+    cmd.args(args);
+    match output_with_timeout(cmd, PACKAGE_MANAGER_TIMEOUT) {
+        Ok(Some(output)) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let line = stdout.lines().next().unwrap_or_default().trim();
+            (!line.is_empty()).then(|| line.to_string())
+        }
+        Ok(Some(output)) => {
+            warn!("{:?} {} exited with {}", tool, args.join(" "), output.status);
+            None
+        }
+        Ok(None) => {
+            warn!("{:?} {} timed out", tool, args.join(" "));
+            None
+        }
+        Err(e) => {
+            warn!("Failed to run {:?}: {}", tool, e);
+            None
+        }
+    }
+}
+
+/// npm's global prefix: `npm_config_prefix` when set, else `npm config get prefix`
+fn npm_global_prefix(allow_exec: bool) -> Option<PathBuf> {
+    let from_env = ["npm_config_prefix", "NPM_CONFIG_PREFIX"]
+        .into_iter()
+        .find_map(|key| std::env::var_os(key).filter(|prefix| !prefix.is_empty()));
+    if let Some(prefix) = from_env {
+        return Some(PathBuf::from(prefix));
+    }
+    if !allow_exec {
+        return None;
+    }
+    query_package_manager("npm", &["config", "get", "prefix"]).map(PathBuf::from)
+}
+
+/// pnpm's global bin directory: `pnpm bin -g` when pnpm is on PATH, else `PNPM_HOME` or the
+/// platform default
+fn pnpm_global_bin_dir(allow_exec: bool) -> Option<PathBuf> {
+    if allow_exec {
+        if let Some(dir) = query_package_manager("pnpm", &["bin", "-g"]) {
+            return Some(PathBuf::from(dir));
+        }
+    }
+
+    if let Some(pnpm_home) = std::env::var_os("PNPM_HOME").filter(|home| !home.is_empty()) {
+        return Some(PathBuf::from(pnpm_home));
+    }
+    if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join("pnpm"))
+    } else if cfg!(target_os = "macos") {
+        dirs::home_dir().map(|home| home.join("Library").join("pnpm"))
+    } else {
+        let home = std::env::var_os("HOME").map(PathBuf::from)?;
+        Some(home.join(".local").join("share").join("pnpm"))
+    }
+}
+
+/// Running npm or pnpm starts node, which can take a moment on cold caches
+const PACKAGE_MANAGER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Follows a pnpm shell shim to the script it runs inside the pnpm store
+/// Shims exec node on `"$basedir/global/.../cli.js"`; the package path there is a symlink
+/// into the content-addressed `.pnpm` store, so the result is canonicalized when possible.
+fn pnpm_shim_target(shim: &std::path::Path) -> Option<PathBuf> {
+    let script = std::fs::read_to_string(shim).ok()?;
+    let basedir = shim.parent()?;
+
+    script
+        .split('"')
+        .filter_map(|token| token.strip_prefix("$basedir/"))
+        .find(|relative| *relative != "node" && !relative.is_empty())
+        .map(|relative| {
+            let target = basedir.join(relative);
+            std::fs::canonicalize(&target).unwrap_or(target)
+        })
+}
+
+/// Check Snap and Flatpak installations on Linux
+#[cfg(target_os = "linux")]
+fn find_linux_package_installations(allow_exec: bool) -> Vec<ClaudeInstallation> {
+    let mut installations = Vec::new();
+
+    if let Some(installation) = find_snap_installation(std::path::Path::new("/snap/bin")) {
+        installations.push(installation);
+    }
+    // Flatpak apps can only be listed by asking flatpak
+    if allow_exec {
+        installations.extend(find_flatpak_installations("flatpak"));
+    }
+
+    installations
+}
+
+/// Check for a Snap-provided claude in the given snap bin directory
+#[cfg(target_os = "linux")]
+fn find_snap_installation(snap_bin: &std::path::Path) -> Option<ClaudeInstallation> {
+    let claude_path = snap_bin.join("claude");
+    if !claude_path.is_file() {
+        return None;
+    }
+
+    let path = claude_path.to_string_lossy().to_string();
+    debug!("Found Snap claude at: {}", path);
+
+    Some(ClaudeInstallation {
+        path,
+        source: "snap".to_string(),
+        install_type: InstallationType::Snap,
+        ..Default::default()
+    })
+}
+
+/// Ask `flatpak` for installed apps whose id mentions claude
+#[cfg(target_os = "linux")]
+fn find_flatpak_installations(flatpak: &str) -> Vec<ClaudeInstallation> {
+    assert_exec_allowed(flatpak);
+    let output = match Command::new(flatpak)
+        .args(["list", "--app", "--columns=application"])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+
+    parse_flatpak_app_ids(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .map(|app_id| {
+            debug!("Found Flatpak claude app: {}", app_id);
+            let invocation = vec![flatpak.to_string(), "run".to_string(), app_id.clone()];
+
+            ClaudeInstallation {
+                path: app_id,
+                source: "flatpak".to_string(),
+                install_type: InstallationType::Flatpak,
+                invocation: Some(invocation),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Starting a Flatpak sandbox is slow, so its version probe gets a generous timeout
+#[cfg(target_os = "linux")]
+const FLATPAK_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Extract claude app ids from `flatpak list --app --columns=application` output
+#[cfg(target_os = "linux")]
+fn parse_flatpak_app_ids(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && line.to_lowercase().contains("claude"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Folders that may hold a `Claude*.app`: both Applications folders and, one level down
+/// per cask and version, the Homebrew Caskroom
+#[cfg(target_os = "macos")]
+fn app_bundle_search_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from("/Applications")];
+    if let Some(home) = dirs::home_dir() {
+        dirs.push(home.join("Applications"));
+    }
+    for caskroom in ["/opt/homebrew/Caskroom", "/usr/local/Caskroom"] {
+        let versions = std::fs::read_dir(caskroom)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .flat_map(|cask| std::fs::read_dir(cask.path()).into_iter().flatten().flatten());
+        dirs.extend(versions.map(|version| version.path()));
+    }
+    dirs
+}
+
+/// Finds the claude CLI shipped in every `Claude*.app` directly inside `dirs`
+/// Only bundles with a `Contents/Resources/claude` executable qualify, which leaves out the
+/// Electron desktop app. With `allow_exec`, each one must also report a Claude Code version.
+#[cfg(target_os = "macos")]
+fn find_app_bundle_installations(dirs: &[PathBuf], allow_exec: bool) -> Vec<ClaudeInstallation> {
+    let mut installations = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !(name.starts_with("Claude") && name.ends_with(".app")) {
+                continue;
+            }
+            let executable = entry.path().join("Contents").join("Resources").join("claude");
+            if !crate::path_utils::is_executable_file(&executable) {
+                continue;
+            }
+            let path = executable.to_string_lossy().to_string();
+            let version = if allow_exec {
+                match probe_claude_code_version(&path) {
+                    Some(version) => Some(version),
+                    None => {
+                        debug!("Skipping {}: not a Claude Code CLI", path);
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+            debug!("Found claude in app bundle: {}", path);
+            installations.push(ClaudeInstallation {
+                path,
+                version,
+                source: "app-bundle".to_string(),
+                install_type: InstallationType::AppBundle,
+                ..Default::default()
+            });
+        }
+    }
+    installations
+}
+
+/// Runs `path --version` and returns the version only if the output names Claude Code
+#[cfg(target_os = "macos")]
+fn probe_claude_code_version(path: &str) -> Option<ClaudeVersion> {
+    let mut cmd = Command::new(path);
+    cmd.arg("--version");
+    let output = output_with_timeout(cmd, VERSION_PROBE_TIMEOUT).ok()??;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || !stdout.contains("Claude Code") {
+        return None;
+    }
+    extract_version_from_output(&output.stdout)
+}
+
+/// Extensions Windows tries when PATHEXT is not set
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// Lists `stem` + each PATHEXT extension that exists in `dir`, in PATHEXT order
+/// On Windows a bare `claude` never resolves; npm installs `claude.cmd` and friends instead
+pub fn windows_executable_candidates(dir: &std::path::Path, stem: &str) -> Vec<PathBuf> {
+    pathext_extensions()
+        .into_iter()
+        .map(|ext| dir.join(format!("{}{}", stem, ext)))
+        .filter(|candidate| candidate.is_file())
+        .collect()
+}
+
+/// PATHEXT extensions, lowercased with their leading dot, e.g. `.exe`
+fn pathext_extensions() -> Vec<String> {
+    let pathext = std::env::var("PATHEXT")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PATHEXT.to_string());
+
+    pathext
+        .split(';')
+        .map(str::trim)
+        .filter(|ext| ext.starts_with('.') && ext.len() > 1)
+        .map(|ext| ext.to_ascii_lowercase())
+        .collect()
+}
+
+/// Scan PATH and the npm global directory for claude executables on Windows
+#[cfg(windows)]
+fn find_windows_installations() -> Vec<ClaudeInstallation> {
+    let mut dirs: Vec<PathBuf> = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect())
+        .unwrap_or_default();
+    if let Ok(appdata) = std::env::var("APPDATA") {
+        dirs.push(PathBuf::from(appdata).join("npm"));
+    }
+
+    let mut installations = Vec::new();
+    for dir in dirs {
+        for candidate in windows_executable_candidates(&dir, "claude") {
+            let path = candidate.to_string_lossy().to_string();
+            debug!("Found claude via PATHEXT: {}", path);
+
+            installations.push(ClaudeInstallation {
+                path,
+                source: "PATH".to_string(),
+                ..Default::default()
+            });
+        }
+    }
+
+    installations
+}
+
+/// Arguments asking claude for structured version output; builds that don't know
+/// `--output-format` either ignore it and print text or fail, which moves on to the next probe
+const VERSION_JSON_ARGS: [&str; 3] = ["--version", "--output-format", "json"];
+
+/// Invocations tried in order when probing a version; the first parseable answer wins
+/// Some distributions only answer `-v` or a `version` subcommand. The bare positional goes
+/// last because claude itself would take it as a prompt, and every spelling before it
+/// already answers on a working claude.
+const VERSION_PROBE_ARGS: &[&[&str]] =
+    &[&VERSION_JSON_ARGS, &["--version"], &["-v"], &["version"]];
+
+/// Total time one candidate's version probe may take, across all of its attempts
+const VERSION_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Get Claude version by running --version command
+fn get_claude_version(path: &str) -> Result<Option<ClaudeVersion>, String> {
+    assert_exec_allowed(path);
+    Ok(get_claude_version_with_timeout(path, VERSION_PROBE_TIMEOUT))
+}
+
+/// Get Claude version, giving up (and killing the probe) once `timeout` has passed in total
+/// Tries `VERSION_PROBE_ARGS` in order and stops at the first that yields a version; an
+/// attempt that fails or prints nothing parseable moves on to the next.
+fn get_claude_version_with_timeout(path: &str, timeout: Duration) -> Option<ClaudeVersion> {
+    get_claude_version_with_env(path, timeout, &[])
+}
+
+/// `get_claude_version_with_timeout` with `env` set on top of the ambient environment
+fn get_claude_version_with_env(
+    path: &str,
+    timeout: Duration,
+    env: &[(String, String)],
+) -> Option<ClaudeVersion> {
+    match run_version_probes(path, timeout, env) {
+        ProbeOutcome::Version(version) => Some(version),
+        _ => None,
+    }
+}
+
+/// Binaries (by path, with their modification time) whose version only a plain flag got
+/// out of: the JSON probe is skipped for them until the binary changes
+/// Versions are probed again on every discovery refresh and version check, and each attempt
+/// is a full node start-up (a few hundred ms), so this halves the probe cost of builds that
+/// predate `--output-format`. Only a binary a plain flag has answered is recorded: one that
+/// answers nothing is rejected anyway, and skipping an attempt there would save nothing.
+static JSON_PROBE_UNSUPPORTED: Mutex<Option<HashMap<String, Option<SystemTime>>>> =
+    Mutex::new(None);
+
+/// Modification time of `path`, to tell whether a remembered probe result still applies
+fn probe_memo_stamp(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Whether an earlier probe found that `path`, as it is now, doesn't answer the JSON probe
+fn json_probe_unsupported(path: &str) -> bool {
+    let memo = JSON_PROBE_UNSUPPORTED.lock().unwrap_or_else(|e| e.into_inner());
+    memo.as_ref()
+        .and_then(|memo| memo.get(path))
+        .is_some_and(|stamp| *stamp == probe_memo_stamp(path))
+}
+
+/// Runs `VERSION_PROBE_ARGS` against `path` within one time `budget` and classifies the result
+/// A clean exit that printed usage for a plain flag ends the probe: the binary doesn't know
+/// the flag, and trying more spellings on something that isn't claude only risks side
+/// effects. Usage in reply to the JSON attempt just means `--output-format` is unknown, so
+/// the text spellings are still tried. When only a plain flag gets a version, that's
+/// remembered and later probes skip the JSON attempt.
+fn run_version_probes(path: &str, budget: Duration, env: &[(String, String)]) -> ProbeOutcome {
+    let deadline = Instant::now() + budget;
+    let skip_json = json_probe_unsupported(path);
+    let mut json_failed = false;
+    for args in VERSION_PROBE_ARGS {
+        let is_json = args[..] == VERSION_JSON_ARGS[..];
+        if is_json && skip_json {
+            continue;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return ProbeOutcome::TransientError;
+        }
+        let mut cmd = Command::new(path);
+        cmd.args(*args);
+        cmd.envs(env.iter().map(|(key, value)| (key, value)));
+        match output_with_timeout(cmd, remaining) {
+            Ok(Some(output)) if output.status.success() => {
+                if let Some(version) = extract_version_from_output(&output.stdout) {
+                    if json_failed {
+                        JSON_PROBE_UNSUPPORTED
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .get_or_insert_with(HashMap::new)
+                            .insert(path.to_string(), probe_memo_stamp(path));
+                    }
+                    return ProbeOutcome::Version(version);
+                }
+                if !is_json && prints_usage(&output.stdout) {
+                    debug!("{} {} printed usage, not probing further", path, args.join(" "));
+                    return ProbeOutcome::NotClaude;
+                }
+            }
+            // No exit code means a signal ended it
+            Ok(Some(output)) if output.status.code().is_none() => {
+                return ProbeOutcome::TransientError
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                warn!("Timed out probing the version of {}", path);
+                return ProbeOutcome::TransientError;
+            }
+            // Spawning fails the same way whatever the arguments
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                warn!("Cannot run {} to get its version: {}", path, e);
+                return ProbeOutcome::NotRunnable;
+            }
+            Err(e) => {
+                warn!("Failed to get version for {}: {}", path, e);
+                return ProbeOutcome::TransientError;
+            }
+        }
+        json_failed |= is_json;
+    }
+    ProbeOutcome::NotClaude
+}
+
+/// Whether a program's output is a usage or help text rather than an answer
+fn prints_usage(stdout: &[u8]) -> bool {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .any(|line| line.trim_start().to_lowercase().starts_with("usage:"))
+}
+
+/// What probing a candidate's version established
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ProbeOutcome {
+    Version(ClaudeVersion),
+    /// It ran and exited normally without printing a version: not claude, reject it
+    NotClaude,
+    /// It doesn't exist or isn't executable, so it can't be started at all; reject it
+    NotRunnable,
+    /// It failed to start for another reason, timed out or was killed by a signal; may work
+    /// on a retry
+    TransientError,
+}
+
+/// Pause before the first retry of a transient probe failure, doubled for each further one
+const PROBE_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Probes `path`'s version, retrying transient failures up to `max_attempts` in total
+/// A clean exit without a parseable version is final (`NotClaude`), as is a missing or
+/// non-executable file (`NotRunnable`); neither is retried.
+pub fn probe_version_with_retries(path: &str, max_attempts: u32) -> ProbeOutcome {
+    let attempts = max_attempts.max(1);
+    let mut delay = PROBE_RETRY_DELAY;
+    let mut attempt = 1;
+
+    loop {
+        match probe_version_once(path, VERSION_PROBE_TIMEOUT) {
+            ProbeOutcome::TransientError if attempt < attempts => {
+                warn!(
+                    "Transient version probe failure for {} (attempt {}/{}), retrying in {:?}",
+                    path, attempt, attempts, delay
+                );
+                std::thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            outcome => return outcome,
+        }
+    }
+}
+
+/// One classified pass over `VERSION_PROBE_ARGS`
+fn probe_version_once(path: &str, timeout: Duration) -> ProbeOutcome {
+    assert_exec_allowed(path);
+    run_version_probes(path, timeout, &[])
+}
+
+/// Runs a command to completion, killing it if it doesn't finish within `timeout`
+/// Returns `Ok(None)` when the timeout was hit. Output is read while waiting, so a child
+/// writing more than a pipe buffer holds doesn't stall until the timeout.
+fn output_with_timeout(mut cmd: Command, timeout: Duration) -> std::io::Result<Option<Output>> {
+    assert_exec_allowed(&cmd.get_program().to_string_lossy());
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = crate::path_utils::read_in_background(child.stdout.take());
+    let stderr = crate::path_utils::read_in_background(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            // A grandchild may keep the pipes open; take what was read by the deadline
+            let collect = |pipe: std::sync::mpsc::Receiver<Vec<u8>>| {
+                pipe.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    .unwrap_or_default()
+            };
+            return Ok(Some(Output {
+                status,
+                stdout: collect(stdout),
+                stderr: collect(stderr),
+            }));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Extract version from command output
+/// Structured JSON output is preferred; anything else is parsed as text
+fn extract_version_from_output(stdout: &[u8]) -> Option<ClaudeVersion> {
+    let text = String::from_utf8_lossy(stdout);
+    ClaudeVersion::from_json(&text).or_else(|| ClaudeVersion::from_multiline(&text))
+}
+
+/// Select the best installation based on version
+fn select_best_installation(installations: Vec<ClaudeInstallation>) -> Option<ClaudeInstallation> {
+    best_installation(installations, Channel::Any)
+}
+
+/// Picks the installation to run, ranking known versions by `channel` first
+/// With `Channel::Stable`, a stable `1.9.0` is chosen over a `2.0.0-beta`; the pre-release is
+/// only picked when no stable version is available.
+pub fn best_installation(
+    installations: Vec<ClaudeInstallation>,
+    channel: Channel,
+) -> Option<ClaudeInstallation> {
+    // In production builds, version information may not be retrievable because
+    // spawning external processes can be restricted. We therefore no longer
+    // discard installations that lack a detected version – the mere presence
+    // of a readable binary on disk is enough to consider it valid. We still
+    // prefer binaries with version information when it is available so that
+    // in development builds we keep the previous behaviour of picking the
+    // most recent version.
+    installations.into_iter().max_by(|a, b| {
+        match (&a.version, &b.version) {
+            // If both have versions, compare them semantically within the channel.
+            (Some(v1), Some(v2)) => channel.rank(v1).cmp(&channel.rank(v2)).then(v1.cmp(v2)),
+            // Prefer the entry that actually has version information.
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            // Neither have version info: prefer the one that is not just
+            // the bare "claude" lookup from PATH, because that may fail
+            // at runtime if PATH is sandbox-stripped.
+            (None, None) => {
+                if a.path == "claude" && b.path != "claude" {
+                    Ordering::Less
+                } else if a.path != "claude" && b.path == "claude" {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            }
+        }
+    })
+}
+
+/// Fully resolved description of a subprocess invocation
+/// Unlike a `Command`, every part of it can be inspected, which keeps PATH handling testable
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandSpec {
+    /// Program to execute
+    pub program: String,
+    /// Arguments passed verbatim to the program
+    pub args: Vec<OsString>,
+    /// Environment variables set explicitly on the child (on top of the inherited environment)
+    pub env: BTreeMap<String, String>,
+    /// Working directory for the child, if it differs from the parent's
+    pub current_dir: Option<PathBuf>,
+    /// Start the child from an empty environment so only `env` reaches it
+    pub clear_env: bool,
+    /// Why each entry of the child's PATH is there, in PATH order; empty when the spec
+    /// doesn't set PATH
+    pub path_origins: Vec<(String, crate::path_utils::EntryOrigin)>,
+}
+
+impl CommandSpec {
+    /// Creates a spec for `program` with no args or environment overrides
+    pub fn new(program: impl Into<String>) -> Self {
+        CommandSpec {
+            program: program.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Appends one argument, passed to the program verbatim (no shell interpretation)
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Appends several arguments, passed to the program verbatim
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// PATH the subprocess will receive, or an empty string when none is set
+    pub fn effective_path(&self) -> &str {
+        self.env.get("PATH").map(String::as_str).unwrap_or_default()
+    }
+
+    /// Renders the program and arguments as a single shell command line (POSIX sh, or
+    /// cmd.exe on Windows), quoted so it can be copy-pasted into a terminal
+    pub fn to_shell_string(&self) -> String {
+        std::iter::once(shell_quote(&self.program))
+            .chain(self.args.iter().map(|arg| shell_quote(&arg.to_string_lossy())))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Wraps this spec in `shell -lic '<command line>'` so it runs with the login PATH
+    /// Environment overrides and the working directory carry over to the shell.
+    #[cfg(unix)]
+    pub fn wrap_in_login_shell(&self, shell: &str) -> CommandSpec {
+        CommandSpec {
+            program: shell.to_string(),
+            args: vec!["-lic".into(), self.to_shell_string().into()],
+            ..self.clone()
+        }
+    }
+
+    /// Builds a `Command` that runs this spec
+    pub fn to_command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        if self.clear_env {
+            cmd.env_clear();
+        }
+        for (key, value) in &self.env {
+            cmd.env(key, value);
+        }
+        if let Some(dir) = &self.current_dir {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+
+    /// Writes the environment this spec sets as `KEY=value` lines, for support tickets
+    /// Secret-looking values are redacted with `redact_env_for_log`. Nothing is spawned.
+    pub fn write_env_dump(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let dump: String = redact_env_for_log(&self.env)
+            .into_iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect();
+        std::fs::write(path, dump)
+    }
+
+    /// Renders a self-contained snippet reproducing this spec in a terminal, for bug reports
+    /// One `export` per environment override (secrets redacted), a `cd` to the working
+    /// directory if set, then `to_shell_string`. Uses `set`/`cd /d` for cmd.exe on Windows.
+    /// With `clear_env` on Unix the command runs under `env -i` with the overrides as its
+    /// only variables instead.
+    pub fn reproduction_snippet(&self) -> String {
+        if self.clear_env && !cfg!(windows) {
+            let mut lines: Vec<String> = Vec::new();
+            if let Some(dir) = &self.current_dir {
+                lines.push(format!("cd {}", shell_quote(&dir.to_string_lossy())));
+            }
+            let command = std::iter::once("env -i".to_string())
+                .chain(
+                    redact_env_for_log(&self.env)
+                        .into_iter()
+                        .map(|(key, value)| format!("{}={}", key, shell_quote(&value))),
+                )
+                .chain(std::iter::once(self.to_shell_string()))
+                .collect::<Vec<_>>()
+                .join(" ");
+            lines.push(command);
+            return lines.join("\n");
+        }
+
+        let mut lines: Vec<String> = redact_env_for_log(&self.env)
+            .into_iter()
+            .map(|(key, value)| {
+                if cfg!(windows) {
+                    format!("set {}", shell_quote(&format!("{}={}", key, value)))
+                } else {
+                    format!("export {}={}", key, shell_quote(&value))
+                }
+            })
+            .collect();
+        if let Some(dir) = &self.current_dir {
+            let dir = shell_quote(&dir.to_string_lossy());
+            lines.push(if cfg!(windows) {
+                format!("cd /d {}", dir)
+            } else {
+                format!("cd {}", dir)
+            });
+        }
+        lines.push(self.to_shell_string());
+        lines.join("\n")
+    }
+}
+
+/// Parts of variable names whose values shouldn't end up in logs or dumps
+const SECRET_ENV_MARKERS: &[&str] = &[
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "API_KEY",
+    "ACCESS_KEY",
+    "PRIVATE_KEY",
+    "CREDENTIAL",
+];
+
+/// Replaced value of a variable `redact_env_for_log` considers secret
+pub const REDACTED_VALUE: &str = "<redacted>";
+
+/// Copy of `env` with the values of secret-looking variables (`*_TOKEN`, `*_API_KEY`, ...)
+/// replaced by `REDACTED_VALUE`
+pub fn redact_env_for_log(env: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    env.iter()
+        .map(|(key, value)| {
+            let upper = key.to_ascii_uppercase();
+            let value = if SECRET_ENV_MARKERS.iter().any(|marker| upper.contains(marker)) {
+                REDACTED_VALUE.to_string()
+            } else {
+                value.clone()
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+/// Quotes a word so the platform shell passes it through as a single argument
+/// POSIX sh on Unix (single quotes), cmd.exe on Windows (double quotes plus carets).
+pub fn shell_quote(word: &str) -> String {
+    if cfg!(windows) {
+        cmd_quote(word)
+    } else {
+        posix_quote(word)
+    }
+}
+
+/// Quotes a word for a POSIX shell, leaving obviously safe words untouched
+fn posix_quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// Quotes a word for cmd.exe, leaving obviously safe words untouched
+/// The word is first quoted for the program's own argv parsing (`"` and the backslashes
+/// before it escaped), then every cmd metacharacter, quotes included, is caret-escaped so
+/// cmd passes the result through without expanding `%VAR%` or splitting on `&`.
+fn cmd_quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || r"_-+=/.,:@\".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        return word.to_string();
+    }
+
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in word.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+
+    let mut escaped = String::with_capacity(quoted.len() * 2);
+    for c in quoted.chars() {
+        if "()%!^\"<>&|".contains(c) {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether the directory containing `binary` is an entry of the PATH string `path`
+pub fn binary_dir_on_path(binary: &std::path::Path, path: &str) -> bool {
+    binary
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .is_some_and(|dir| crate::path_utils::path_contains_dir(path, &dir.to_string_lossy()))
+}
+
+/// Computes the directories that must be added to PATH for `binary` to run
+/// That is the binary's own directory plus, when it's a `#!` node script, the directory of
+/// the node interpreter. Directories already on the current PATH are left out.
+pub fn required_path_additions(binary: &std::path::Path) -> Vec<String> {
+    path_additions_for(binary, &std::env::var("PATH").unwrap_or_default())
+}
+
+/// `required_path_additions` against the PATH string `current_path`
+fn path_additions_for(binary: &std::path::Path, current_path: &str) -> Vec<String> {
+    let mut dirs: Vec<PathBuf> = binary.parent().map(PathBuf::from).into_iter().collect();
+    if let Some(node) = find_shim_node(binary) {
+        dirs.extend(node.parent().map(PathBuf::from));
+    }
+
+    let mut additions: Vec<String> = Vec::new();
+    for dir in dirs {
+        let dir = dir.to_string_lossy().to_string();
+        if dir.is_empty()
+            || additions.contains(&dir)
+            || crate::path_utils::path_contains_dir(current_path, &dir)
+        {
+            continue;
+        }
+        additions.push(dir);
+    }
+    additions
+}
+
+/// Well-known install locations every child PATH gets, when they exist
+const STANDARD_PATH_LOCATIONS: &[&str] =
+    &["/opt/homebrew/bin", "/usr/local/bin", "/usr/bin", "/bin"];
+
+/// The one PATH to run `binary` with, built from `current_path`
+/// The binary's directory and, for a node shim, the node interpreter's go in front, then
+/// the standard common locations missing from `current_path`, then `current_path` itself.
+/// Existing entries keep their place and the result has no duplicates or empty segments,
+/// so calling it again is a no-op.
+pub fn ensure_claude_path(binary: &std::path::Path, current_path: &str) -> String {
+    ensure_claude_path_builder(binary, current_path).to_string()
+}
+
+/// `ensure_claude_path`, keeping track of why each entry is there
+fn ensure_claude_path_builder(
+    binary: &std::path::Path,
+    current_path: &str,
+) -> crate::path_utils::PathBuilder {
+    let mut path = crate::path_utils::PathBuilder::inherited(current_path);
+
+    // Each directory goes to the front, so add in reverse to keep their order
+    for location in STANDARD_PATH_LOCATIONS.iter().rev() {
+        if std::path::Path::new(location).is_dir() {
+            path.prepend(location, crate::path_utils::EntryOrigin::CommonLocation);
+        }
+    }
+
+    let additions = if binary.is_absolute() {
+        path_additions_for(binary, current_path)
+    } else {
+        Vec::new()
+    };
+    for dir in additions.iter().rev() {
+        if path.prepend(dir, dependency_origin(dir)) {
+            debug!("Adding {} to the child PATH", dir);
+        }
+    }
+    path
+}
+
+/// Returns the node interpreter a `#!` script runs with, if the script is a node shim
+/// `#!/usr/bin/env node` is resolved next to the script, next to its symlink target,
+/// in `NVM_BIN`, then on PATH.
+fn find_shim_node(script: &std::path::Path) -> Option<PathBuf> {
+    use std::io::Read;
+
+    let mut head = [0u8; 256];
+    let read = std::fs::File::open(script).ok()?.read(&mut head).ok()?;
+    let first_line = String::from_utf8_lossy(&head[..read]);
+    let shebang = first_line.lines().next()?.strip_prefix("#!")?;
+
+    let mut words = shebang.split_whitespace();
+    let interpreter = PathBuf::from(words.next()?);
+    if interpreter.file_name().is_some_and(|name| name == "env") {
+        if !words.any(|word| word == "node") {
+            return None;
+        }
+    } else {
+        return (interpreter.file_name()? == "node").then_some(interpreter);
+    }
+
+    let mut search_dirs: Vec<PathBuf> = Vec::new();
+    search_dirs.extend(script.parent().map(PathBuf::from));
+    if let Ok(target) = std::fs::canonicalize(script) {
+        search_dirs.extend(target.parent().map(PathBuf::from));
+    }
+    search_dirs.extend(std::env::var_os("NVM_BIN").map(PathBuf::from));
+    if let Some(path) = std::env::var_os("PATH") {
+        search_dirs.extend(std::env::split_paths(&path));
+    }
+
+    search_dirs
+        .into_iter()
+        .map(|dir| dir.join("node"))
+        .find(|node| crate::path_utils::is_executable_file(node))
+}
+
+/// Whether two installations run on the same node interpreter
+/// Compares the resolved (canonical) node of each node shim, so a global and a linked
+/// install under one nvm version match. False when either runtime can't be determined.
+pub fn same_runtime(a: &ClaudeInstallation, b: &ClaudeInstallation) -> bool {
+    let runtime = |install: &ClaudeInstallation| {
+        let node = find_shim_node(std::path::Path::new(&install.path))?;
+        Some(crate::path_utils::canonical_cached(&node).unwrap_or(node))
+    };
+    match (runtime(a), runtime(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Resolves the program and environment for running a Claude-related binary
+/// This ensures commands like Claude can find Node.js and other dependencies. The spec
+/// carries the child's complete PATH, so callers never need to touch the parent's.
+///
+/// With `CLAUDIA_NO_PATH_ENHANCE=1` the inherited PATH is passed through as set, except
+/// that duplicate and empty entries are still dropped; the program's own directory and its
+/// node interpreter's are not added, so a node shim must find node on that PATH.
+pub fn create_command_spec(program: &str) -> CommandSpec {
+    let mut spec = CommandSpec::new(program);
+
+    // Inherit essential environment variables from parent process
+    for (key, value) in std::env::vars() {
+        // Pass through PATH and other essential environment variables
+        if key == "PATH"
+            || key == "HOME"
+            || key == "USER"
+            || key == "SHELL"
+            || key == "LANG"
+            || key == "LC_ALL"
+            || key.starts_with("LC_")
+            || key == "NODE_PATH"
+            || key == "NVM_DIR"
+            || key == "NVM_BIN"
+            || key == "HOMEBREW_PREFIX"
+            || key == "HOMEBREW_CELLAR"
+            || key == "CLAUDE_CONFIG_DIR"
+        {
+            debug!("Inheriting env var: {}={}", key, value);
+            spec.env.insert(key, value);
+        }
+    }
+
+    // Put the directories the program needs on the child's PATH only; the parent process
+    // environment is never modified. Either way the child gets each entry once.
+    let inherited = spec.env.get("PATH").map_or("", String::as_str);
+    let path = if crate::path_utils::path_enhancement_disabled() {
+        debug!("PATH enhancement disabled, passing the inherited PATH through");
+        spec.env
+            .contains_key("PATH")
+            .then(|| crate::path_utils::PathBuilder::inherited(inherited))
+    } else {
+        Some(ensure_claude_path_builder(std::path::Path::new(program), inherited))
+    };
+    if let Some(path) = path {
+        spec.env.insert("PATH".to_string(), path.to_string());
+        spec.path_origins = path.into_entries();
+    }
+
+    spec
+}
+
+/// Origin of a directory added to PATH because the program needs it
+fn dependency_origin(dir: &str) -> crate::path_utils::EntryOrigin {
+    if dir.contains("/.nvm/versions/node/") {
+        crate::path_utils::EntryOrigin::NvmResolution
+    } else {
+        crate::path_utils::EntryOrigin::ProgramDependency
+    }
+}
+
+/// System directories every minimal PATH keeps: `/usr/bin:/bin`, or the Windows directory
+/// and its System32 on Windows
+fn minimal_path_baseline() -> Vec<String> {
+    if cfg!(windows) {
+        let root = std::env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".to_string());
+        vec![format!(r"{}\System32", root), root]
+    } else {
+        vec!["/usr/bin".to_string(), "/bin".to_string()]
+    }
+}
+
+/// Helper function to create a Command with proper environment variables
+/// This ensures commands like Claude can find Node.js and other dependencies
+pub fn create_command_with_env(program: &str) -> Command {
+    let spec = create_command_spec(program);
+
+    // Enhancement should have put the binary's directory on PATH; if it didn't, claude's own
+    // lookups of sibling tools like `node` can fail
+    let binary = std::path::Path::new(program);
+    if binary.is_absolute() && !binary_dir_on_path(binary, spec.effective_path()) {
+        warn!(
+            "Directory of {} is not on the child PATH; tools next to it may not be found",
+            program
+        );
+    }
+
+    spec.to_command()
+}
+
+/// The PATH change `create_command_with_env` would make for `binary`, for previewing it
+/// Compares the current PATH with the child's; nothing is spawned or modified.
+pub fn preview_path_enhancement(binary: &str) -> crate::path_utils::PathDiff {
+    let before = std::env::var("PATH").unwrap_or_default();
+    let spec = create_command_spec(binary);
+    crate::path_utils::diff_paths(&before, spec.effective_path())
+}
+
+/// Why a program can't be run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotRunnableReason {
+    /// Nothing exists at the path (or, for a bare name, on PATH)
+    Missing,
+    /// The path exists but is a directory or other non-file
+    NotAFile,
+    /// The file lacks execute permission
+    NotExecutable,
+}
+
+/// A program `create_command_with_env_validated` refused to build a command for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BinaryNotRunnable {
+    pub path: PathBuf,
+    pub reason: NotRunnableReason,
+}
+
+impl std::fmt::Display for BinaryNotRunnable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self.reason {
+            NotRunnableReason::Missing => "does not exist",
+            NotRunnableReason::NotAFile => "is not a file",
+            NotRunnableReason::NotExecutable => "is not executable",
+        };
+        write!(f, "Cannot run {}: it {}", self.path.display(), reason)
+    }
+}
+
+impl std::error::Error for BinaryNotRunnable {}
+
+/// Like `create_command_with_env`, but first checks that `program` exists and is executable
+/// so a bad path fails here with a clear reason instead of as an opaque spawn error. A bare
+/// name such as `claude` is looked up on PATH.
+pub fn create_command_with_env_validated(program: &str) -> Result<Command, BinaryNotRunnable> {
+    let path = std::path::Path::new(program);
+    let not_runnable = |reason| BinaryNotRunnable {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    if path.components().count() == 1 {
+        let found = std::env::var_os("PATH").is_some_and(|dirs| {
+            std::env::split_paths(&dirs).any(|dir| {
+                if cfg!(windows) {
+                    !windows_executable_candidates(&dir, program).is_empty()
+                } else {
+                    crate::path_utils::is_executable_file(&dir.join(program))
+                }
+            })
+        });
+        if !found {
+            return Err(not_runnable(NotRunnableReason::Missing));
+        }
+    } else {
+        match path.metadata() {
+            Err(_) => return Err(not_runnable(NotRunnableReason::Missing)),
+            Ok(metadata) if !metadata.is_file() => {
+                return Err(not_runnable(NotRunnableReason::NotAFile))
+            }
+            Ok(_) if !crate::path_utils::is_executable_file(path) => {
+                return Err(not_runnable(NotRunnableReason::NotExecutable))
+            }
+            Ok(_) => {}
+        }
+    }
+
+    Ok(create_command_with_env(program))
+}
+
+/// Reusable rule for which parent environment variables a claude child may see
+/// A variable passes when it's listed in `always` or matches a `glob_allow` pattern (e.g.
+/// `CLAUDE_*`), unless it's listed in `never`, which always wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvPolicy {
+    #[serde(default)]
+    pub always: Vec<String>,
+    #[serde(default)]
+    pub never: Vec<String>,
+    #[serde(default)]
+    pub glob_allow: Vec<String>,
+}
+
+impl EnvPolicy {
+    /// Returns the subset of `parent` this policy lets through
+    pub fn apply(&self, parent: &HashMap<String, String>) -> HashMap<String, String> {
+        let patterns: Vec<glob::Pattern> = self
+            .glob_allow
+            .iter()
+            .filter_map(|pattern| {
+                glob::Pattern::new(pattern)
+                    .map_err(|e| warn!("Ignoring invalid env glob {}: {}", pattern, e))
+                    .ok()
+            })
+            .collect();
+
+        parent
+            .iter()
+            .filter(|(key, _)| {
+                !self.never.contains(key)
+                    && (self.always.contains(key)
+                        || patterns.iter().any(|pattern| pattern.matches(key)))
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// Builds the `CommandSpec` for a claude run, layering per-session settings on top of
+/// the environment `create_command_spec` resolves
+#[derive(Debug, Clone)]
+pub struct ClaudeCommandBuilder {
+    spec: CommandSpec,
+    /// Number of leading args that belong to a launcher such as `flatpak run <app>`
+    launcher_args: usize,
+    config_dir: Option<PathBuf>,
+    env_policy: Option<EnvPolicy>,
+    node_max_old_space_mb: Option<u32>,
+    minimal_path: Option<crate::path_utils::PathBuilder>,
+}
+
+impl ClaudeCommandBuilder {
+    /// Starts from the resolved command for `program`
+    pub fn new(program: &str) -> Self {
+        ClaudeCommandBuilder {
+            spec: create_command_spec(program),
+            launcher_args: 0,
+            config_dir: None,
+            env_policy: None,
+            node_max_old_space_mb: None,
+            minimal_path: None,
+        }
+    }
+
+    /// Starts from the launch command of an installation, launcher prefix included
+    pub fn for_installation(installation: &ClaudeInstallation) -> Self {
+        let spec = installation.build_command();
+        ClaudeCommandBuilder {
+            launcher_args: spec.args.len(),
+            spec,
+            config_dir: None,
+            env_policy: None,
+            node_max_old_space_mb: None,
+            minimal_path: None,
+        }
+    }
+
+    /// Appends one argument, passed to claude verbatim
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.spec = self.spec.arg(arg);
+        self
+    }
+
+    /// Appends several arguments, passed to claude verbatim
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.spec = self.spec.args(args);
+        self
+    }
+
+    /// Runs claude against this config directory via `CLAUDE_CONFIG_DIR`, overriding any
+    /// inherited value so separate profiles don't share state
+    /// The directory is created by `build` if it doesn't exist yet.
+    pub fn config_dir(mut self, path: &std::path::Path) -> Self {
+        self.config_dir = Some(path.to_path_buf());
+        self
+    }
+
+    /// Passes only the parent variables `policy` admits instead of the whole environment
+    /// Values resolved for the child (such as an nvm-enhanced PATH) replace the inherited
+    /// ones when the policy lets that variable through.
+    pub fn env_policy(mut self, policy: EnvPolicy) -> Self {
+        self.env_policy = Some(policy);
+        self
+    }
+
+    /// Raises node's heap limit by appending `--max-old-space-size=<mb>` to the child's
+    /// `NODE_OPTIONS`, after any flags it already inherits
+    /// Without this, `NODE_OPTIONS` is passed through untouched.
+    pub fn node_max_old_space_mb(mut self, mb: u32) -> Self {
+        self.node_max_old_space_mb = Some(mb);
+        self
+    }
+
+    /// Replaces the child's PATH with a minimal known-good one: `binary`'s directory, its node
+    /// interpreter's directory for node shims, then the system baseline (`/usr/bin:/bin`)
+    /// Nothing from the inherited PATH is kept, so a stray tool there can't break claude.
+    pub fn minimal_path(mut self, binary: &std::path::Path) -> Self {
+        let mut path = crate::path_utils::PathBuilder::default();
+        let mut dirs: Vec<PathBuf> = binary.parent().map(PathBuf::from).into_iter().collect();
+        dirs.extend(find_shim_node(binary).and_then(|node| node.parent().map(PathBuf::from)));
+        for dir in dirs {
+            let dir = dir.to_string_lossy();
+            path.append(&dir, dependency_origin(&dir));
+        }
+        for dir in minimal_path_baseline() {
+            path.append(&dir, crate::path_utils::EntryOrigin::CommonLocation);
+        }
+        self.minimal_path = Some(path);
+        self
+    }
+
+    /// Checks the arguments added so far (not the launcher's) against `policy`
+    pub fn validate(&self, policy: &ArgPolicy) -> Result<(), DisallowedArg> {
+        validate_args(&self.spec.args[self.launcher_args..], policy)
+    }
+
+    /// Produces the final spec, creating the config directory if one was set
+    pub fn build(self) -> std::io::Result<CommandSpec> {
+        let mut spec = self.spec;
+
+        if let Some(policy) = &self.env_policy {
+            let parent: HashMap<String, String> = std::env::vars().collect();
+            let mut env: BTreeMap<String, String> = policy.apply(&parent).into_iter().collect();
+            for (key, value) in &spec.env {
+                if let Some(allowed) = env.get_mut(key) {
+                    *allowed = value.clone();
+                }
+            }
+            spec.env = env;
+            spec.clear_env = true;
+        }
+
+        if let Some(path) = self.minimal_path {
+            spec.env.insert("PATH".to_string(), path.to_string());
+            spec.path_origins = path.into_entries();
+        }
+
+        if let Some(mb) = self.node_max_old_space_mb {
+            // A cleared environment only has what the policy admitted
+            let inherited = match spec.env.get("NODE_OPTIONS") {
+                Some(options) => Some(options.clone()),
+                None if !spec.clear_env => std::env::var("NODE_OPTIONS").ok(),
+                None => None,
+            };
+            let flag = format!("--max-old-space-size={}", mb);
+            let options = match inherited.as_deref().map(str::trim) {
+                Some(existing) if !existing.is_empty() => format!("{} {}", existing, flag),
+                _ => flag,
+            };
+            spec.env.insert("NODE_OPTIONS".to_string(), options);
+        }
+
+        if let Some(config_dir) = self.config_dir {
+            std::fs::create_dir_all(&config_dir)?;
+            spec.env.insert(
+                "CLAUDE_CONFIG_DIR".to_string(),
+                config_dir.to_string_lossy().to_string(),
+            );
+        }
+
+        Ok(spec)
+    }
+}
+
+/// Which flags may be forwarded to claude, e.g. for a read-only preview mode
+/// Flags are compared by name, so `--model=opus` is checked as `--model`. An `allowed` list
+/// switches to allowlist mode where every flag must start with one of its prefixes; `denied`
+/// always wins. Positional arguments and everything after `--` are not flags.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArgPolicy {
+    /// Flags that are never allowed
+    #[serde(default)]
+    pub denied: Vec<String>,
+    /// When set, only flags starting with one of these prefixes are allowed
+    #[serde(default)]
+    pub allowed: Option<Vec<String>>,
+}
+
+/// The first argument `validate_args` refused
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DisallowedArg {
+    pub arg: String,
+}
+
+impl std::fmt::Display for DisallowedArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Argument {} is not allowed here", self.arg)
+    }
+}
+
+impl std::error::Error for DisallowedArg {}
+
+/// Checks every flag in `args` against `policy`, returning the first one it rejects
+pub fn validate_args(args: &[OsString], policy: &ArgPolicy) -> Result<(), DisallowedArg> {
+    for arg in args {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            continue;
+        }
+
+        let flag = arg.split('=').next().unwrap_or_default();
+        let denied = policy.denied.iter().any(|denied| denied == flag);
+        let not_allowed = policy.allowed.as_ref().is_some_and(|allowed| {
+            !allowed
+                .iter()
+                .any(|prefix| flag.starts_with(prefix.as_str()))
+        });
+        if denied || not_allowed {
+            return Err(DisallowedArg {
+                arg: arg.into_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// A file that looked like a claude installation but can't be used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RejectedCandidate {
+    pub path: String,
+    pub reason: String,
+}
+
+/// No usable claude anywhere; explains where discovery looked and what it turned down
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct NoClaudeFound {
+    /// Every directory discovery searched, in lookup order
+    pub searched: Vec<PathBuf>,
+    /// Candidates that were found but rejected
+    pub rejected: Vec<RejectedCandidate>,
+}
+
+impl std::fmt::Display for NoClaudeFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No Claude Code installation found (searched {} locations",
+            self.searched.len()
+        )?;
+        if !self.rejected.is_empty() {
+            write!(f, ", rejected {} candidates", self.rejected.len())?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl std::error::Error for NoClaudeFound {}
+
+/// Directories discovery looks in for the given types: PATH, the nvm versions directory and
+/// the standard locations
+pub fn discovery_search_locations(types: &[InstallationType]) -> Vec<PathBuf> {
+    let mut locations: Vec<PathBuf> = Vec::new();
+    if types.contains(&InstallationType::Direct) {
+        locations.extend(
+            std::env::var_os("PATH")
+                .map(|path| std::env::split_paths(&path).collect::<Vec<_>>())
+                .unwrap_or_default(),
+        );
+    }
+    if types.contains(&InstallationType::Nvm) {
+        if let Ok(home) = std::env::var("HOME") {
+            locations.push(PathBuf::from(home).join(".nvm").join("versions").join("node"));
+        }
+    }
+    for (path, _, _) in standard_candidates(types, true) {
+        locations.extend(std::path::Path::new(&path).parent().map(PathBuf::from));
+    }
+    #[cfg(target_os = "linux")]
+    if types.contains(&InstallationType::Snap) {
+        locations.push(PathBuf::from("/snap/bin"));
+    }
+    if types.contains(&InstallationType::Custom) {
+        locations.extend(search_paths_from_env());
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    locations.retain(|location| !location.as_os_str().is_empty() && seen.insert(location.clone()));
+    locations
+}
+
+/// Discovers installations and returns the best usable one, or a `NoClaudeFound` explaining
+/// where discovery looked
+/// Files that exist but aren't executable are reported as rejected rather than selected.
+pub fn require_claude() -> Result<ClaudeInstallation, NoClaudeFound> {
+    require_claude_filtered(InstallationType::ALL)
+}
+
+/// `require_claude` restricted to installations of the given types
+pub fn require_claude_filtered(
+    types: &[InstallationType],
+) -> Result<ClaudeInstallation, NoClaudeFound> {
+    let mut rejected = Vec::new();
+    let usable: Vec<ClaudeInstallation> = discover_claude_installations_filtered(types)
+        .into_iter()
+        .filter(|install| {
+            let path = std::path::Path::new(&install.path);
+            let launchable = install.invocation.is_some()
+                || path.components().count() == 1
+                || crate::path_utils::is_executable_file(path);
+            if !launchable {
+                rejected.push(RejectedCandidate {
+                    path: install.path.clone(),
+                    reason: "not executable".to_string(),
+                });
+            }
+            launchable
+        })
+        .collect();
+
+    select_best_installation(usable).ok_or_else(|| NoClaudeFound {
+        searched: discovery_search_locations(types),
+        rejected,
+    })
+}
+
+/// A claude process that is already running on this machine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RunningInstance {
+    pub pid: u32,
+    /// Executable the process runs, with symlinks resolved when possible
+    pub path: PathBuf,
+    /// When the process started, if the platform reports it
+    pub started_at: Option<SystemTime>,
+}
+
+/// Finds running processes whose executable is named `claude`
+/// Linux reads `/proc`, which also finds node running a `claude` script; other Unix systems
+/// (and Linux without `/proc`) fall back to `ps -axo pid,comm`, which doesn't report start
+/// times. Claudia's own process is skipped.
+pub fn running_claude_instances() -> Vec<RunningInstance> {
+    #[cfg(unix)]
+    {
+        #[cfg(target_os = "linux")]
+        if let Some(instances) = running_instances_from_proc() {
+            return instances;
+        }
+
+        let mut cmd = Command::new("ps");
+        cmd.args(["-axo", "pid,comm"]);
+        match output_with_timeout(cmd, PS_TIMEOUT) {
+            Ok(Some(output)) if output.status.success() => {
+                parse_ps_output(&String::from_utf8_lossy(&output.stdout))
+            }
+            Ok(Some(output)) => {
+                warn!("ps exited with {}", output.status);
+                Vec::new()
+            }
+            Ok(None) => {
+                warn!("ps did not finish within {:?}", PS_TIMEOUT);
+                Vec::new()
+            }
+            Err(e) => {
+                warn!("Failed to run ps: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    Vec::new()
+}
+
+/// Listing processes is quick; a hung `ps` shouldn't block the advisory
+#[cfg(unix)]
+const PS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Parses `ps -axo pid,comm` output into the claude processes it lists
+/// `comm` may contain spaces, so everything after the pid column is the executable. Only
+/// absolute paths are resolved; a bare name like `claude` says nothing about where it lives.
+#[cfg(unix)]
+fn parse_ps_output(output: &str) -> Vec<RunningInstance> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (pid, comm) = line.trim_start().split_once(char::is_whitespace)?;
+            let pid = pid.parse::<u32>().ok()?;
+            let comm = comm.trim();
+            let path = PathBuf::from(comm);
+            if pid == std::process::id() || path.file_name()? != "claude" {
+                return None;
+            }
+            let path = if path.is_absolute() {
+                std::fs::canonicalize(&path).unwrap_or(path)
+            } else {
+                path
+            };
+            Some(RunningInstance {
+                pid,
+                path,
+                started_at: None,
+            })
+        })
+        .collect()
+}
+
+/// Scans `/proc` for claude processes with `proc_claude_path`; `None` when `/proc` can't
+/// be read
+#[cfg(target_os = "linux")]
+fn running_instances_from_proc() -> Option<Vec<RunningInstance>> {
+    let boot_time = proc_boot_time();
+    let instances = std::fs::read_dir("/proc")
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            if pid == std::process::id() {
+                return None;
+            }
+            let path = proc_claude_path(&entry.path())?;
+            let started_at = boot_time.and_then(|boot| proc_start_time(&entry.path(), boot));
+            Some(RunningInstance {
+                pid,
+                path,
+                started_at,
+            })
+        })
+        .collect();
+    Some(instances)
+}
+
+/// The claude a `/proc/<pid>` directory's process runs, if it runs one
+/// That's its `exe` when named `claude`, or for node its first argument (`argv[1]` in
+/// `cmdline`) when that is a `claude` script; a relative script is resolved against the
+/// process's `cwd`. Other users' processes can't be inspected, which simply skips them.
+#[cfg(target_os = "linux")]
+fn proc_claude_path(proc_dir: &std::path::Path) -> Option<PathBuf> {
+    let exe = std::fs::read_link(proc_dir.join("exe")).ok()?;
+    let exe_name = exe.file_name()?;
+    if exe_name == "claude" {
+        return Some(exe);
+    }
+    if exe_name != "node" {
+        return None;
+    }
+
+    let cmdline = std::fs::read(proc_dir.join("cmdline")).ok()?;
+    let script = cmdline.split(|&byte| byte == 0).nth(1)?;
+    let script = PathBuf::from(String::from_utf8_lossy(script).as_ref());
+    if script.file_name()? != "claude" {
+        return None;
+    }
+    if script.is_absolute() {
+        Some(script)
+    } else {
+        Some(std::fs::read_link(proc_dir.join("cwd")).ok()?.join(script))
+    }
+}
+
+/// Boot time from the `btime` line of `/proc/stat`
+#[cfg(target_os = "linux")]
+fn proc_boot_time() -> Option<SystemTime> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let secs = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Start time of a process from field 22 of `/proc/<pid>/stat` (clock ticks after boot)
+#[cfg(target_os = "linux")]
+fn proc_start_time(proc_dir: &std::path::Path, boot_time: SystemTime) -> Option<SystemTime> {
+    let stat = std::fs::read_to_string(proc_dir.join("stat")).ok()?;
+    // The command name in field 2 may contain spaces, so count fields after its ')'
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+    let ticks = after_comm.split_whitespace().nth(19)?.parse::<u64>().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_sec <= 0 {
+        return None;
+    }
+    let ticks_per_sec = ticks_per_sec as u64;
+    let since_boot = Duration::from_secs(ticks / ticks_per_sec)
+        + Duration::from_nanos((ticks % ticks_per_sec) * 1_000_000_000 / ticks_per_sec);
+    Some(boot_time + since_boot)
+}
+
+/// Reasons Claudia refuses to spawn an installation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpawnRefusal {
+    /// The installation is older than the minimum version a feature needs
+    VersionTooOld {
+        found: ClaudeVersion,
+        required: ClaudeVersion,
+    },
+    /// A minimum version is required but the installation's version is unknown
+    UnknownVersion,
+}
+
+impl std::fmt::Display for SpawnRefusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpawnRefusal::VersionTooOld { found, required } => write!(
+                f,
+                "Claude Code {} is too old; version {} or newer is required",
+                found, required
+            ),
+            SpawnRefusal::UnknownVersion => write!(
+                f,
+                "Could not determine the Claude Code version to check it against the required minimum"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SpawnRefusal {}
+
+/// Like `create_command_spec`, but refuses installations older than `min`
+/// rather than letting claude fail cryptically on an unsupported feature
+// Refusals are rare and returned once per spawn, so the larger error isn't worth boxing
+#[allow(clippy::result_large_err)]
+pub fn create_command_checked(
+    install: &ClaudeInstallation,
+    min: Option<ClaudeVersion>,
+) -> Result<CommandSpec, SpawnRefusal> {
+    if let Some(required) = min {
+        match &install.version {
+            Some(found) if *found < required => {
+                return Err(SpawnRefusal::VersionTooOld {
+                    found: found.clone(),
+                    required,
+                });
+            }
+            Some(_) => {}
+            None => return Err(SpawnRefusal::UnknownVersion),
+        }
+    }
+
+    Ok(install.build_command())
+}
+
+/// The `claude` a shell would run: the first match when walking the current PATH in order
+/// On Windows each directory is checked for every PATHEXT extension, like cmd does.
+/// Only the filesystem is inspected; aliases and functions are `detect_shell_shadowing`'s job.
+pub fn path_resolved_claude() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    which_in_path("claude", &path.to_string_lossy())
+}
+
+/// The file `command` resolves to in `path`: the first executable match, entries in order
+/// Uses discovery's rules: execute bits on Unix, PATHEXT extensions on Windows. Useful for
+/// diagnostics such as finding which directory a child's `node` comes from.
+pub fn which_in_path(command: &str, path: &str) -> Option<PathBuf> {
+    std::env::split_paths(path).find_map(|dir| {
+        if cfg!(windows) {
+            windows_executable_candidates(&dir, command).into_iter().next()
+        } else {
+            let candidate = dir.join(command);
+            crate::path_utils::is_executable_file(&candidate).then_some(candidate)
+        }
+    })
+}
+
+/// The claude a fresh terminal would run: `claude` resolved against the login shell's PATH
+/// Probed for its version, so the UI can show e.g. "your terminal uses /opt/homebrew/bin/claude
+/// (1.2.3)". `None` when there's no usable login shell or its PATH has no claude.
+pub fn default_shell_claude() -> Option<ClaudeInstallation> {
+    let shell_path = crate::path_utils::login_shell_path()?;
+    let binary = which_in_path("claude", &shell_path)?;
+    debug!("Login shell PATH resolves claude to {:?}", binary);
+
+    let mut installation = ClaudeInstallation {
+        path: binary.to_string_lossy().to_string(),
+        source: "which".to_string(),
+        install_type: installation_type_of_path(&binary),
+        discovered_via: DiscoveryMethod::Which,
+        ..Default::default()
+    };
+    installation.version = probe_installation_version(&installation);
+    installation.refresh_metadata();
+    Some(installation)
+}
+
+/// Whether `install` is the same binary the shell resolves from PATH
+/// Paths are compared after canonicalization, so symlinks to the same binary match. A bare
+/// `claude` is itself a PATH lookup and matches whenever PATH has one.
+pub fn installation_matches_shell(install: &ClaudeInstallation) -> bool {
+    let Some(resolved) = path_resolved_claude() else {
+        return false;
+    };
+    let install_path = std::path::Path::new(&install.path);
+    if install_path.components().count() == 1 {
+        return true;
+    }
+
+    let canonical = |path: &std::path::Path| {
+        crate::path_utils::canonical_cached(path).unwrap_or_else(|| path.to_path_buf())
+    };
+    canonical(install_path) == canonical(&resolved)
+}
+
+/// A shell alias or function named `claude` that points somewhere other than the selected binary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShadowWarning {
+    /// The rc file containing the definition
+    pub rc_file: PathBuf,
+    /// 1-based line number of the definition
+    pub line_number: usize,
+    /// The offending line, trimmed
+    pub line: String,
+}
+
+/// Best-effort check for a `claude` alias or function in the user's shell rc files
+/// that diverges from `binary_path` ("it works in my terminal but not in Claudia").
+/// Advisory only: the rc files are scanned textually, never sourced.
+pub fn detect_shell_shadowing(binary_path: &std::path::Path) -> Option<ShadowWarning> {
+    let home = dirs::home_dir()?;
+    [".zshrc", ".bashrc", ".bash_profile", ".zprofile"]
+        .iter()
+        .map(|name| home.join(name))
+        .find_map(|rc_file| find_shadowing_in_rc(&rc_file, binary_path, &home))
+}
+
+fn find_shadowing_in_rc(
+    rc_file: &std::path::Path,
+    binary_path: &std::path::Path,
+    home: &std::path::Path,
+) -> Option<ShadowWarning> {
+    let contents = std::fs::read_to_string(rc_file).ok()?;
+    let canonical_binary = binary_path
+        .canonicalize()
+        .unwrap_or_else(|_| binary_path.to_path_buf());
+
+    for (index, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim();
+        let shadows = if let Some(value) = line.strip_prefix("alias claude=") {
+            // The alias target is the first word of the (possibly quoted) alias body
+            let body = value.trim_matches(|c| c == '\'' || c == '"');
+            let mut words = body.split_whitespace().skip_while(|word| *word == "command");
+            let target = words.next().unwrap_or_default();
+            let target = match target.strip_prefix("~/") {
+                Some(rest) => home.join(rest),
+                None => PathBuf::from(target.replace("$HOME", &home.to_string_lossy())),
+            };
+            if target == std::path::Path::new("claude") {
+                // Aliases don't expand recursively: this just adds arguments to claude itself
+                false
+            } else {
+                // A bare name runs whatever PATH resolves it to
+                let target = match target.components().count() {
+                    1 => std::env::var("PATH")
+                        .ok()
+                        .and_then(|path| which_in_path(&target.to_string_lossy(), &path))
+                        .unwrap_or(target),
+                    _ => target,
+                };
+                let canonical_target = target.canonicalize().unwrap_or(target);
+                canonical_target != canonical_binary
+            }
+        } else {
+            // We can't know what a function runs, so any definition counts
+            line.starts_with("claude()")
+                || line.starts_with("claude ()")
+                || line
+                    .strip_prefix("function claude")
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(ends_function_name))
+        };
+
+        if shadows {
+            return Some(ShadowWarning {
+                rc_file: rc_file.to_path_buf(),
+                line_number: index + 1,
+                line: line.to_string(),
+            });
+        }
+    }
+
+    None
+}
+
+/// Whether `c` may follow a function name in `function name ...`, so `claude` isn't matched
+/// as a prefix of `claudia` or `claude_helper`
+fn ends_function_name(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == '{'
+}
+
+/// Runs a spec to completion and captures its stdout/stderr
+pub fn run_claude_capture(spec: &CommandSpec) -> std::io::Result<Output> {
+    spawn_captured(spec)?.wait_with_output()
+}
+
+/// Like `run_claude_capture`, but retries the spawn itself when it fails with a
+/// transient error (e.g. "resource temporarily unavailable" right after a fresh
+/// download on macOS). Delays double after each failed attempt.
+/// A process that launched and then exited non-zero is returned as-is, never retried.
+pub fn run_claude_capture_retry(
+    spec: &CommandSpec,
+    attempts: u32,
+    base_delay: Duration,
+) -> std::io::Result<Output> {
+    retry_transient_spawn(attempts, base_delay, || spawn_captured(spec))?.wait_with_output()
+}
+
+/// One event from claude's `--output-format stream-json` output
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClaudeEvent {
+    /// Session metadata such as `{"type":"system","subtype":"init",...}`
+    System(serde_json::Value),
+    /// A message from the assistant
+    Assistant(serde_json::Value),
+    /// A user turn, including tool results
+    User(serde_json::Value),
+    /// The final result summary of the run
+    Result(serde_json::Value),
+    /// Valid JSON with a type we don't model
+    Other(serde_json::Value),
+    /// A line that isn't valid JSON; kept so a bad line never aborts the stream
+    Unknown(String),
+}
+
+impl ClaudeEvent {
+    /// Decodes a single (non-empty) line of stream-json output
+    pub fn from_line(line: &str) -> Self {
+        let value: serde_json::Value = match serde_json::from_str(line) {
+            Ok(value) => value,
+            Err(_) => return ClaudeEvent::Unknown(line.to_string()),
+        };
+
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some("system") => ClaudeEvent::System(value),
+            Some("assistant") => ClaudeEvent::Assistant(value),
+            Some("user") => ClaudeEvent::User(value),
+            Some("result") => ClaudeEvent::Result(value),
+            _ => ClaudeEvent::Other(value),
+        }
+    }
+}
+
+/// Incremental parser for newline-delimited JSON events
+/// Bytes can be pushed in arbitrary chunks; events come out once their line is complete
+#[derive(Debug, Default)]
+pub struct ClaudeEventParser {
+    buffer: Vec<u8>,
+}
+
+impl ClaudeEventParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a chunk of output and returns every event completed by it
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<ClaudeEvent> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            if let Some(event) = Self::decode(&line[..line.len() - 1]) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flushes a trailing line that wasn't newline-terminated
+    pub fn finish(&mut self) -> Option<ClaudeEvent> {
+        let line = std::mem::take(&mut self.buffer);
+        Self::decode(&line)
+    }
+
+    fn decode(line: &[u8]) -> Option<ClaudeEvent> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        if line.is_empty() {
+            None
+        } else {
+            Some(ClaudeEvent::from_line(line))
+        }
+    }
+}
+
+/// Handle to a claude process started by `spawn_claude_streaming`
+/// On Unix the process leads its own process group, so cancelling also takes down
+/// the node subprocesses claude spawns instead of leaking them.
+#[derive(Debug)]
+pub struct ClaudeRunHandle {
+    child: Mutex<std::process::Child>,
+    pid: u32,
+    /// Set once the leader was reaped and its group found empty: from then on the id may
+    /// belong to an unrelated process group and must not be signalled
+    #[cfg_attr(not(unix), allow(dead_code))]
+    group_gone: std::sync::atomic::AtomicBool,
+}
+
+impl ClaudeRunHandle {
+    /// Process id of the claude process (and its process group on Unix)
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Kills the process and everything in its process group, then reaps it
+    ///
+    /// The group is signalled even when claude itself already exited, since processes it
+    /// started may still be running. Once the group was seen empty after the leader was
+    /// reaped, it is left alone: its id may have been reused by then.
+    pub fn cancel(&self) {
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        #[cfg(unix)]
+        if !self.group_gone.load(std::sync::atomic::Ordering::SeqCst) {
+            // SAFETY: killpg only sends a signal; the group id is our child's pid, and the
+            // group is still ours: either the leader is unreaped or members were left
+            let result = unsafe { libc::killpg(self.pid as libc::pid_t, libc::SIGKILL) };
+            if result != 0 {
+                debug!("killpg({}) failed: {}", self.pid, std::io::Error::last_os_error());
+            }
+        }
+
+        if !matches!(child.try_wait(), Ok(Some(_))) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.note_reaped();
+        info!("Cancelled claude process {}", self.pid);
+    }
+
+    /// Waits for the process to exit on its own
+    /// The child lock is only held while polling, so `cancel` works while someone waits.
+    pub fn wait(&self) -> std::io::Result<std::process::ExitStatus> {
+        loop {
+            let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(status) = child.try_wait()? {
+                self.note_reaped();
+                return Ok(status);
+            }
+            drop(child);
+            std::thread::sleep(RUN_WAIT_POLL_INTERVAL);
+        }
+    }
+
+    /// Records, right after the leader was reaped, whether anything is left in its group
+    /// Called with the child lock held, so `cancel` sees the result before it signals.
+    fn note_reaped(&self) {
+        #[cfg(unix)]
+        {
+            // SAFETY: signal 0 only checks whether the group exists
+            let result = unsafe { libc::killpg(self.pid as libc::pid_t, 0) };
+            if result != 0 {
+                self.group_gone.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// How often `ClaudeRunHandle::wait` checks whether the process has exited
+const RUN_WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Spawns a spec (expected to run claude with `--output-format stream-json`) and
+/// decodes its stdout on a background thread, delivering events as they arrive.
+/// The channel closes once the process closes its stdout.
+pub fn spawn_claude_streaming(
+    spec: &CommandSpec,
+) -> std::io::Result<(ClaudeRunHandle, std::sync::mpsc::Receiver<ClaudeEvent>)> {
+    use std::io::Read;
+
+    let mut cmd = spec.to_command();
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+
+    let mut child = cmd.spawn()?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| std::io::Error::other("child stdout was not captured"))?;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut parser = ClaudeEventParser::new();
+        let mut chunk = [0u8; 8192];
+        loop {
+            match stdout.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    for event in parser.push(&chunk[..n]) {
+                        if sender.send(event).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Failed to read claude output: {}", e);
+                    break;
+                }
+            }
+        }
+        if let Some(event) = parser.finish() {
+            let _ = sender.send(event);
+        }
+    });
+
+    let handle = ClaudeRunHandle {
+        pid: child.id(),
+        child: Mutex::new(child),
+        group_gone: std::sync::atomic::AtomicBool::new(false),
+    };
+    Ok((handle, receiver))
+}
+
+fn spawn_captured(spec: &CommandSpec) -> std::io::Result<std::process::Child> {
+    spec.to_command()
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+}
+
+/// Calls `spawn` up to `attempts` times, sleeping with exponential backoff between
+/// attempts that fail with a transient error. Returns the last error otherwise.
+fn retry_transient_spawn<T>(
+    attempts: u32,
+    base_delay: Duration,
+    mut spawn: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let attempts = attempts.max(1);
+    let mut delay = base_delay;
+    let mut attempt = 1;
+
+    loop {
+        match spawn() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < attempts && is_transient_spawn_error(&e) => {
+                warn!(
+                    "Transient spawn failure (attempt {}/{}): {}, retrying in {:?}",
+                    attempt, attempts, e, delay
+                );
+                std::thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Spawn errors that are worth retrying because they usually clear up on their own
+fn is_transient_spawn_error(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) {
+        return true;
+    }
+
+    // "Text file busy": the binary is still being written or signed
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::ETXTBSY) {
+        return true;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn test_retry_transient_spawn_recovers() {
+        let mut calls = 0;
+        let result = retry_transient_spawn(3, Duration::from_millis(1), || {
+            calls += 1;
+            if calls < 3 {
+                Err(Error::from(ErrorKind::WouldBlock))
+            } else {
+                Ok(calls)
+            }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_retry_transient_spawn_gives_up() {
+        let mut calls = 0;
+        let result: std::io::Result<()> = retry_transient_spawn(4, Duration::from_millis(1), || {
+            calls += 1;
+            Err(Error::from(ErrorKind::WouldBlock))
+        });
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn test_retry_transient_spawn_skips_permanent_errors() {
+        let mut calls = 0;
+        let result: std::io::Result<()> = retry_transient_spawn(5, Duration::from_millis(1), || {
+            calls += 1;
+            Err(Error::from(ErrorKind::NotFound))
+        });
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+    }
+
+    #[cfg(unix)]
+    fn write_script(path: &std::path::Path, contents: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_find_snap_installation() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let snap_bin = temp_dir.path().join("snap").join("bin");
+        write_script(&snap_bin.join("claude"), "#!/bin/sh\necho '1.0.40 (Claude Code)'");
+
+        let installation = find_snap_installation(&snap_bin).unwrap();
+        assert_eq!(installation.install_type, InstallationType::Snap);
+        assert_eq!(installation.path, snap_bin.join("claude").to_string_lossy());
+        assert_eq!(
+            probe_installation_version(&installation),
+            ClaudeVersion::parse("1.0.40")
+        );
+        assert!(installation.invocation.is_none());
+
+        assert!(find_snap_installation(&temp_dir.path().join("missing")).is_none());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_find_flatpak_installations() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let flatpak = temp_dir.path().join("flatpak");
+        write_script(
+            &flatpak,
+            "#!/bin/sh\ncase \"$1\" in\n  list) printf 'org.mozilla.firefox\\ncom.anthropic.ClaudeCode\\n' ;;\n  run) echo '1.0.41 (Claude Code)' ;;\nesac",
+        );
+        let flatpak = flatpak.to_string_lossy().to_string();
+
+        let installations = find_flatpak_installations(&flatpak);
+        assert_eq!(installations.len(), 1);
+
+        let installation = &installations[0];
+        assert_eq!(installation.install_type, InstallationType::Flatpak);
+        assert_eq!(installation.path, "com.anthropic.ClaudeCode");
+        assert_eq!(
+            probe_installation_version(installation),
+            ClaudeVersion::parse("1.0.41")
+        );
+        assert_eq!(
+            installation.invocation,
+            Some(vec![flatpak.clone(), "run".to_string(), "com.anthropic.ClaudeCode".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_find_app_bundle_installations() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cli = temp_dir.path().join("Claude Code.app/Contents/Resources/claude");
+        write_script(&cli, "#!/bin/sh\necho '1.0.42 (Claude Code)'");
+        // The Electron desktop app has no CLI under Resources, so it's never run
+        let desktop = temp_dir.path().join("Claude.app/Contents/MacOS/Claude");
+        write_script(&desktop, "#!/bin/sh\necho 0.9.3");
+        // A CLI-shaped bundle whose binary isn't Claude Code
+        let impostor = temp_dir.path().join("Claude Helper.app/Contents/Resources/claude");
+        write_script(&impostor, "#!/bin/sh\necho 0.9.3");
+        // Not a Claude bundle, so never looked at
+        std::fs::create_dir_all(temp_dir.path().join("Other.app").join("Contents")).unwrap();
+
+        let dirs = [temp_dir.path().to_path_buf()];
+        let installations = find_app_bundle_installations(&dirs, true);
+        assert_eq!(installations.len(), 1);
+        assert_eq!(installations[0].install_type, InstallationType::AppBundle);
+        assert_eq!(installations[0].path, cli.to_string_lossy());
+        assert_eq!(installations[0].version, ClaudeVersion::parse("1.0.42"));
+
+        // Without exec only the marker is checked
+        let mut unprobed: Vec<String> = find_app_bundle_installations(&dirs, false)
+            .into_iter()
+            .map(|install| install.path)
+            .collect();
+        unprobed.sort();
+        let mut expected = vec![
+            cli.to_string_lossy().to_string(),
+            impostor.to_string_lossy().to_string(),
+        ];
+        expected.sort();
+        assert_eq!(unprobed, expected);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_find_flatpak_installations_without_flatpak() {
+        assert!(find_flatpak_installations("/nonexistent/flatpak").is_empty());
+        assert!(parse_flatpak_app_ids("org.gnome.Maps\n\n").is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_version_probe_prefers_json() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let json = temp_dir.path().join("json").join("claude");
+        write_script(
+            &json,
+            "#!/bin/sh\nif [ \"$2\" = --output-format ]; then echo '{\"version\":\"1.0.80\",\"build\":\"abc\"}'; else echo '0.0.1 (Claude Code)'; fi",
+        );
+        assert_eq!(
+            get_claude_version(json.to_str().unwrap()).unwrap(),
+            ClaudeVersion::parse("1.0.80")
+        );
+
+        // Older builds reject the flag; the plain probe is used instead, and from then on
+        // straight away
+        let legacy = temp_dir.path().join("legacy").join("claude");
+        let runs = temp_dir.path().join("legacy-runs");
+        write_script(
+            &legacy,
+            &format!(
+                "#!/bin/sh\necho \"$*\" >> '{}'\n\
+                 if [ -n \"$2\" ]; then echo 'unknown option' >&2; exit 1; fi\n\
+                 echo '1.0.17 (Claude Code)'",
+                runs.display()
+            ),
+        );
+        let legacy = legacy.to_str().unwrap();
+        assert_eq!(get_claude_version(legacy).unwrap(), ClaudeVersion::parse("1.0.17"));
+        assert_eq!(
+            get_claude_version_with_timeout(legacy, Duration::from_secs(5)),
+            ClaudeVersion::parse("1.0.17")
+        );
+        assert_eq!(
+            std::fs::read_to_string(&runs).unwrap(),
+            "--version --output-format json\n--version\n--version\n"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_version_probe_alternate_flags() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let short_flag = temp_dir.path().join("short").join("claude");
+        write_script(
+            &short_flag,
+            "#!/bin/sh\nif [ \"$*\" = -v ]; then echo '1.0.33'; else exit 2; fi",
+        );
+        assert_eq!(
+            get_claude_version(short_flag.to_str().unwrap()).unwrap(),
+            ClaudeVersion::parse("1.0.33")
+        );
+
+        let subcommand = temp_dir.path().join("subcommand").join("claude");
+        write_script(
+            &subcommand,
+            "#!/bin/sh\nif [ \"$*\" = version ]; then echo '1.0.34'; else exit 2; fi",
+        );
+        assert_eq!(
+            get_claude_version(subcommand.to_str().unwrap()).unwrap(),
+            ClaudeVersion::parse("1.0.34")
+        );
+
+        // A build that doesn't know `--output-format` still answers the plain flag
+        let no_json = temp_dir.path().join("no-json").join("claude");
+        write_script(
+            &no_json,
+            "#!/bin/sh\ncase \"$*\" in\n  *--output-format*) echo 'usage: claude [options]' ;;\n  \
+             --version) echo '1.0.35 (Claude Code)' ;;\nesac",
+        );
+        assert_eq!(
+            get_claude_version(no_json.to_str().unwrap()).unwrap(),
+            ClaudeVersion::parse("1.0.35")
+        );
+
+        // Usage in reply to a plain flag isn't an answer, and ends the probe right there
+        let usage_log = temp_dir.path().join("usage.log");
+        let usage = temp_dir.path().join("usage").join("claude");
+        write_script(
+            &usage,
+            &format!(
+                "#!/bin/sh\necho \"$*\" >> '{}'\necho 'usage: claude [options]'",
+                usage_log.display()
+            ),
+        );
+        assert_eq!(
+            get_claude_version_with_timeout(usage.to_str().unwrap(), Duration::from_secs(5)),
+            None
+        );
+        assert_eq!(
+            std::fs::read_to_string(&usage_log).unwrap(),
+            "--version --output-format json\n--version\n"
+        );
+
+        // One budget covers every attempt, so a hung binary costs the timeout once
+        let hung = temp_dir.path().join("hung").join("claude");
+        write_script(&hung, "#!/bin/sh\nexec sleep 30");
+        let started = std::time::Instant::now();
+        assert_eq!(
+            get_claude_version_with_timeout(hung.to_str().unwrap(), Duration::from_millis(500)),
+            None
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // The first answer wins, so later spellings are never run
+        let log = temp_dir.path().join("calls.log");
+        let counted = temp_dir.path().join("counted").join("claude");
+        write_script(
+            &counted,
+            &format!("#!/bin/sh\necho \"$*\" >> '{}'\necho '1.0.5'", log.display()),
+        );
+        assert_eq!(
+            get_claude_version(counted.to_str().unwrap()).unwrap(),
+            ClaudeVersion::parse("1.0.5")
+        );
+        assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 1);
+
+        let silent = temp_dir.path().join("silent").join("claude");
+        write_script(&silent, "#!/bin/sh\nexit 1");
+        assert_eq!(get_claude_version(silent.to_str().unwrap()).unwrap(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "during no-exec discovery")]
+    fn test_exec_guard_panics_on_version_probe() {
+        let _guard = ExecGuard::forbid();
+        let _ = get_claude_version("/bin/true");
+    }
+
+    #[test]
+    fn test_exec_guard_restores_on_drop() {
+        {
+            let _guard = ExecGuard::forbid();
+            assert!(EXEC_FORBIDDEN.with(|forbidden| forbidden.get()));
+        }
+        assert!(!EXEC_FORBIDDEN.with(|forbidden| forbidden.get()));
+    }
+
+    #[test]
+    fn test_probe_versions_bounded_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+        let mut installations: Vec<ClaudeInstallation> = (0..12)
+            .map(|i| ClaudeInstallation {
+                path: format!("/nvm/{}/claude", i),
+                // Already-known versions are not probed again
+                version: (i == 0).then(|| ClaudeVersion::parse("0.9.0").unwrap()),
+                ..Default::default()
+            })
+            .collect();
+
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let probes = AtomicUsize::new(0);
+        probe_versions_with(&mut installations, 3, |install| {
+            let now = running.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+            peak.fetch_max(now, AtomicOrdering::SeqCst);
+            probes.fetch_add(1, AtomicOrdering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            running.fetch_sub(1, AtomicOrdering::SeqCst);
+            ClaudeVersion::parse(&format!("1.0.{}", install.path.split('/').nth(2)?))
+        });
+
+        assert_eq!(probes.load(AtomicOrdering::SeqCst), 11);
+        let peak = peak.load(AtomicOrdering::SeqCst);
+        assert!(peak <= 3, "{} probes ran at once", peak);
+        assert_eq!(installations[0].version, ClaudeVersion::parse("0.9.0"));
+        for (i, install) in installations.iter().enumerate().skip(1) {
+            assert_eq!(install.version, ClaudeVersion::parse(&format!("1.0.{}", i)));
+        }
+        assert_eq!(probe_worker_count(0), 1);
+        assert!(probe_worker_count(1000) <= 1000);
+    }
+
+    #[test]
+    fn test_posix_quote() {
+        assert_eq!(posix_quote("claude"), "claude");
+        assert_eq!(posix_quote(""), "''");
+        assert_eq!(posix_quote("it's"), r"'it'\''s'");
+        assert_eq!(posix_quote("/opt/My Tools/claude"), "'/opt/My Tools/claude'");
+        assert_eq!(posix_quote("$HOME;rm"), "'$HOME;rm'");
+    }
+
+    #[test]
+    fn test_cmd_quote() {
+        assert_eq!(cmd_quote(r"C:\tools\claude.exe"), r"C:\tools\claude.exe");
+        assert_eq!(cmd_quote(""), r#"^"^""#);
+        assert_eq!(cmd_quote("it's"), r#"^"it's^""#);
+        assert_eq!(
+            cmd_quote(r"C:\Program Files\claude.exe"),
+            r#"^"C:\Program Files\claude.exe^""#
+        );
+        // Embedded quotes are escaped for argv parsing, metacharacters for cmd itself
+        assert_eq!(cmd_quote(r#"say "hi" & %PATH%"#), r#"^"say \^"hi\^" ^& ^%PATH^%^""#);
+        // Trailing backslashes are doubled so they don't escape the closing quote
+        assert_eq!(cmd_quote(r"C:\My Dir\"), r#"^"C:\My Dir\\^""#);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_shell_quote_uses_posix_on_unix() {
+        assert_eq!(shell_quote("it's"), posix_quote("it's"));
+        assert_eq!(shell_quote(""), "''");
+
+        let spec = CommandSpec::new("/opt/My Tools/claude")
+            .arg("it's")
+            .wrap_in_login_shell("/bin/zsh");
+        assert_eq!(spec.program, "/bin/zsh");
+        assert_eq!(
+            spec.args,
+            vec![OsString::from("-lic"), OsString::from(r"'/opt/My Tools/claude' 'it'\''s'")]
+        );
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_shell_quote_uses_cmd_on_windows() {
+        assert_eq!(shell_quote("it's"), cmd_quote("it's"));
+        assert_eq!(shell_quote(""), r#"^"^""#);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_parse_ps_output() {
+        let fixture = "  PID COMM
+    1 /sbin/launchd
+  412 /usr/local/bin/claude
+  977 /Applications/My Tools/bin/claude
+ 1203 claude-helper
+ 1510 /Users/me/.nvm/versions/node/v20.11.0/bin/node
+ 1620 claude
+garbage line
+";
+        let instances = parse_ps_output(fixture);
+        let pids: Vec<u32> = instances.iter().map(|instance| instance.pid).collect();
+        assert_eq!(pids, vec![412, 977, 1620]);
+        assert_eq!(
+            instances[1].path,
+            PathBuf::from("/Applications/My Tools/bin/claude")
+        );
+        // A bare name is reported as is, never resolved against the current directory
+        assert_eq!(instances[2].path, PathBuf::from("claude"));
+        assert!(instances.iter().all(|instance| instance.started_at.is_none()));
+
+        // Claudia's own process is never reported
+        let own = format!("{} /usr/local/bin/claude\n", std::process::id());
+        assert!(parse_ps_output(&own).is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_proc_claude_path() {
+        use std::os::unix::fs::symlink;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let fake_proc = |name: &str, exe: &str, argv: &[&str]| {
+            let dir = temp_dir.path().join(name);
+            std::fs::create_dir(&dir).unwrap();
+            symlink(exe, dir.join("exe")).unwrap();
+            symlink("/home/me/project", dir.join("cwd")).unwrap();
+            let cmdline: Vec<u8> = argv
+                .iter()
+                .flat_map(|arg| [arg.as_bytes(), b"\0"].concat())
+                .collect();
+            std::fs::write(dir.join("cmdline"), cmdline).unwrap();
+            dir
+        };
+
+        let native = fake_proc("native", "/usr/local/bin/claude", &["claude"]);
+        assert_eq!(proc_claude_path(&native), Some(PathBuf::from("/usr/local/bin/claude")));
+
+        let nvm_claude = "/home/me/.nvm/versions/node/v20.11.0/bin/claude";
+        let shim = fake_proc(
+            "shim",
+            "/home/me/.nvm/versions/node/v20.11.0/bin/node",
+            &["node", nvm_claude, "--print"],
+        );
+        assert_eq!(proc_claude_path(&shim), Some(PathBuf::from(nvm_claude)));
+
+        let relative = fake_proc("relative", "/usr/bin/node", &["node", "bin/claude"]);
+        assert_eq!(
+            proc_claude_path(&relative),
+            Some(PathBuf::from("/home/me/project/bin/claude"))
+        );
+
+        let other_script = fake_proc("other", "/usr/bin/node", &["node", "/srv/server.js"]);
+        assert_eq!(proc_claude_path(&other_script), None);
+        let editor = fake_proc("editor", "/usr/bin/vim", &["vim", "claude"]);
+        assert_eq!(proc_claude_path(&editor), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_claude_capture_retry_does_not_retry_exit_status() {
+        let mut spec = CommandSpec::new("/bin/sh");
+        spec.args = vec!["-c".into(), "echo ran; exit 3".into()];
+
+        let output = run_claude_capture_retry(&spec, 3, Duration::from_millis(1)).unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "ran\n");
+    }
 }
@@ -1894,53 +1894,8 @@ pub async fn list_claude_installations(
 /// Helper function to create a tokio Command with proper environment variables
 /// This ensures commands like Claude can find Node.js and other dependencies
 fn create_command_with_env(program: &str) -> Command {
-    // Convert std::process::Command to tokio::process::Command
-    let _std_cmd = crate::claude_binary::create_command_with_env(program);
-
-    // Create a new tokio Command from the program path
-    let mut tokio_cmd = Command::new(program);
-
-    // Copy over all environment variables from the std::process::Command
-    // This is a workaround since we can't directly convert between the two types
-    for (key, value) in std::env::vars() {
-        if key == "PATH"
-            || key == "HOME"
-            || key == "USER"
-            || key == "SHELL"
-            || key == "LANG"
-            || key == "LC_ALL"
-            || key.starts_with("LC_")
-            || key == "NODE_PATH"
-            || key == "NVM_DIR"
-            || key == "NVM_BIN"
-            || key == "HOMEBREW_PREFIX"
-            || key == "HOMEBREW_CELLAR"
-        {
-            tokio_cmd.env(&key, &value);
-        }
-    }
-
-    // Add NVM support if the program is in an NVM directory
-    // Note: The std::process::Command from claude_binary already handles this,
-    // but we need to apply it to our tokio Command
-    if program.contains("/.nvm/versions/node/") {
-        if let Some(node_bin_dir) = std::path::Path::new(program).parent() {
-            let node_bin_str = node_bin_dir.to_string_lossy();
-            let enhanced_path = crate::path_utils::add_to_path_if_missing(&node_bin_str);
-            tokio_cmd.env("PATH", enhanced_path);
-        }
-    }
-
-    // Ensure PATH contains common Homebrew locations
-    let common_paths = vec!["/opt/homebrew/bin", "/usr/local/bin", "/usr/bin", "/bin"];
-    if let Some(enhanced_path) = crate::path_utils::enhance_path_for_common_locations(&common_paths) {
-        tokio_cmd.env("PATH", enhanced_path);
-    } else {
-        // If PATH is not set at all, use a reasonable default
-        tokio_cmd.env("PATH", std::env::var("PATH").unwrap_or_else(|_| "/opt/homebrew/bin:/usr/local/bin:/usr/bin:/bin".to_string()));
-    }
-
-    tokio_cmd
+    // Same program, environment and PATH as the std::process::Command
+    Command::from(crate::claude_binary::create_command_with_env(program))
 }
 
 /// Import an agent from JSON data
@@ -223,44 +223,8 @@ fn extract_first_user_message(jsonl_path: &PathBuf) -> (Option<String>, Option<S
 /// Helper function to create a tokio Command with proper environment variables
 /// This ensures commands like Claude can find Node.js and other dependencies
 fn create_command_with_env(program: &str) -> Command {
-    // Convert std::process::Command to tokio::process::Command
-    let _std_cmd = crate::claude_binary::create_command_with_env(program);
-
-    // Create a new tokio Command from the program path
-    let mut tokio_cmd = Command::new(program);
-
-    // Copy over all environment variables
-    for (key, value) in std::env::vars() {
-        if key == "PATH"
-            || key == "HOME"
-            || key == "USER"
-            || key == "SHELL"
-            || key == "LANG"
-            || key == "LC_ALL"
-            || key.starts_with("LC_")
-            || key == "NODE_PATH"
-            || key == "NVM_DIR"
-            || key == "NVM_BIN"
-            || key == "HOMEBREW_PREFIX"
-            || key == "HOMEBREW_CELLAR"
-        {
-            log::debug!("Inheriting env var: {}={}", key, value);
-            tokio_cmd.env(&key, &value);
-        }
-    }
-
-    // Add NVM support if the program is in an NVM directory
-    // Note: The std::process::Command from claude_binary already handles this,
-    // but we need to apply it to our tokio Command
-    if program.contains("/.nvm/versions/node/") {
-        if let Some(node_bin_dir) = std::path::Path::new(program).parent() {
-            let node_bin_str = node_bin_dir.to_string_lossy();
-            let enhanced_path = crate::path_utils::add_to_path_if_missing(&node_bin_str);
-            tokio_cmd.env("PATH", enhanced_path);
-        }
-    }
-
-    tokio_cmd
+    // Same program, environment and PATH as the std::process::Command
+    Command::from(crate::claude_binary::create_command_with_env(program))
 }
 
 /// Lists all projects in the ~/.claude/projects directory
@@ -1,9 +1,51 @@
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
-use std::path::Path;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Separator between PATH entries on this platform
+#[cfg(windows)]
+pub const PATH_SEPARATOR: char = ';';
+/// Separator between PATH entries on this platform
+#[cfg(not(windows))]
+pub const PATH_SEPARATOR: char = ':';
+
+/// Splits a PATH string into its raw entries, including empty segments
+#[cfg(not(windows))]
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split(PATH_SEPARATOR)
+}
+
+/// Splits a PATH string into its raw entries, including empty segments
+/// cmd.exe allows double-quoting an entry, so a `;` inside quotes doesn't split it.
+/// The quotes stay part of the returned entry so rejoining preserves them.
+#[cfg(windows)]
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    let mut entries = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+
+    for (i, c) in path.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            PATH_SEPARATOR if !in_quotes => {
+                entries.push(&path[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    entries.push(&path[start..]);
+
+    entries.into_iter()
+}
 
 /// Adds a directory to PATH if it doesn't already exist
 /// Returns the new PATH value
+/// Existing entries are never moved, so like `deduplicate_path` the first occurrence wins
 pub fn add_to_path_if_missing(dir: &str) -> String {
     let current_path = env::var("PATH").unwrap_or_default();
     
@@ -17,59 +59,921 @@ pub fn add_to_path_if_missing(dir: &str) -> String {
     let new_path = if current_path.is_empty() {
         dir.to_string()
     } else {
-        format!("{}:{}", dir, current_path)
+        format!("{}{}{}", dir, PATH_SEPARATOR, current_path)
     };
     
     log::info!("Added {} to PATH", dir);
     new_path
 }
 
+/// Like `add_to_path_if_missing`, but only adds directories that exist
+/// Returns the current PATH unchanged for a missing directory, so dead entries never pile up
+pub fn add_to_path_if_missing_existing(dir: &str) -> String {
+    if !Path::new(dir).exists() {
+        log::debug!("Directory {} does not exist, not adding it to PATH", dir);
+        return env::var("PATH").unwrap_or_default();
+    }
+    
+    add_to_path_if_missing(dir)
+}
+
+/// Where `add_all_to_path` puts the directories it adds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathPosition {
+    /// Ahead of the existing entries, so they take priority
+    Prepend,
+    /// After the existing entries, as fallbacks
+    Append,
+}
+
+/// Adds every directory in `dirs` that isn't already on `path`, in the caller's order
+/// Each directory is added at most once, wherever it's listed; the result is deduplicated
+/// like `deduplicate_path`, so empty segments are dropped too.
+pub fn add_all_to_path(path: &str, dirs: &[&str], position: PathPosition) -> String {
+    let mut present: HashSet<String> = split_path(path).map(normalize_path).collect();
+    let additions: Vec<&str> = dirs
+        .iter()
+        .copied()
+        .filter(|dir| !dir.is_empty() && present.insert(normalize_path(dir)))
+        .collect();
+
+    let additions = additions.join(&PATH_SEPARATOR.to_string());
+    let combined = match position {
+        PathPosition::Prepend => format!("{}{}{}", additions, PATH_SEPARATOR, path),
+        PathPosition::Append => format!("{}{}{}", path, PATH_SEPARATOR, additions),
+    };
+    deduplicate_path(&combined)
+}
+
+/// A parsed PATH for batches of membership checks and additions
+/// Each entry is normalized once up front, so adding many directories doesn't re-split and
+/// re-canonicalize the whole PATH per call. Additions behave exactly like repeated
+/// `add_to_path_if_missing` calls: new directories go to the front, existing ones are kept.
+#[derive(Debug, Clone, Default)]
+pub struct PathSet {
+    entries: VecDeque<String>,
+    keys: HashSet<String>,
+}
+
+impl PathSet {
+    /// Parses a PATH string, keeping its entries (empty ones included) verbatim
+    pub fn new(path: &str) -> Self {
+        let mut set = PathSet::default();
+        if path.is_empty() {
+            return set;
+        }
+        for entry in split_path(path) {
+            set.keys.insert(normalize_path(entry));
+            set.entries.push_back(entry.to_string());
+        }
+        set
+    }
+    
+    /// Whether `dir` is already on the PATH, compared in normalized form
+    pub fn contains(&self, dir: &str) -> bool {
+        self.keys.contains(&normalize_path(dir))
+    }
+    
+    /// Prepends `dir` unless it's already present; returns whether it was added
+    pub fn add_if_missing(&mut self, dir: &str) -> bool {
+        if !self.keys.insert(normalize_path(dir)) {
+            return false;
+        }
+        self.entries.push_front(dir.to_string());
+        true
+    }
+}
+
+impl std::fmt::Display for PathSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", PATH_SEPARATOR)?;
+            }
+            write!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why an entry built by `PathBuilder` is on the PATH
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryOrigin {
+    /// Already on the PATH Claudia inherited
+    Inherited,
+    /// The bin directory of an nvm-managed node version the program runs from
+    NvmResolution,
+    /// A well-known install location such as `/opt/homebrew/bin`
+    CommonLocation,
+    /// The program's own directory, or its node interpreter's, outside of nvm
+    ProgramDependency,
+    /// Added explicitly by the caller
+    Manual,
+}
+
+/// Builds a PATH entry by entry, remembering where each entry came from
+/// Entries are deduplicated in normalized form like `deduplicate_path`; the first spelling
+/// and origin of a directory win, and empty segments are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct PathBuilder {
+    entries: VecDeque<(String, EntryOrigin)>,
+    keys: HashSet<String>,
+}
+
+impl PathBuilder {
+    /// Starts from an inherited PATH string, marking its entries `Inherited`
+    pub fn inherited(path: &str) -> Self {
+        let mut builder = PathBuilder::default();
+        for entry in split_path(path) {
+            builder.append(entry, EntryOrigin::Inherited);
+        }
+        builder
+    }
+    
+    /// Puts `dir` in front unless it's already present; returns whether it was added
+    pub fn prepend(&mut self, dir: &str, origin: EntryOrigin) -> bool {
+        if !self.insert_key(dir) {
+            return false;
+        }
+        self.entries.push_front((dir.to_string(), origin));
+        true
+    }
+    
+    /// Puts `dir` at the end unless it's already present; returns whether it was added
+    pub fn append(&mut self, dir: &str, origin: EntryOrigin) -> bool {
+        if !self.insert_key(dir) {
+            return false;
+        }
+        self.entries.push_back((dir.to_string(), origin));
+        true
+    }
+    
+    fn insert_key(&mut self, dir: &str) -> bool {
+        let key = normalize_path(dir);
+        !key.is_empty() && self.keys.insert(key)
+    }
+    
+    /// Origin recorded for `dir`, compared in normalized form
+    pub fn origin_of(&self, dir: &str) -> Option<EntryOrigin> {
+        let key = normalize_path(dir);
+        self.entries
+            .iter()
+            .find(|(entry, _)| normalize_path(entry) == key)
+            .map(|(_, origin)| *origin)
+    }
+    
+    /// Entries with their origins, in PATH order
+    pub fn into_entries(self) -> Vec<(String, EntryOrigin)> {
+        self.entries.into()
+    }
+}
+
+impl std::fmt::Display for PathBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (entry, _)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", PATH_SEPARATOR)?;
+            }
+            write!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
 /// Checks if a PATH string contains a specific directory
-fn path_contains_dir(path: &str, dir: &str) -> bool {
+pub(crate) fn path_contains_dir(path: &str, dir: &str) -> bool {
     let normalized_dir = normalize_path(dir);
     
-    path.split(':')
+    split_path(path)
         .map(normalize_path)
         .any(|p| p == normalized_dir)
 }
 
 /// Normalizes a path for comparison (removes trailing slashes, resolves symlinks if possible)
 fn normalize_path(path: &str) -> String {
+    // Quoted entries compare equal to their unquoted form
+    #[cfg(windows)]
+    let path = path.trim_matches('"');
+
     let trimmed = path.trim_end_matches('/');
     
     // Try to canonicalize the path if it exists
-    if let Ok(canonical) = Path::new(trimmed).canonicalize() {
-        canonical.to_string_lossy().into_owned()
+    let normalized = match canonical_cached(Path::new(trimmed)) {
+        Some(canonical) => canonical.to_string_lossy().into_owned(),
+        None => trimmed.to_string(),
+    };
+
+    // Windows canonicalization yields `\\?\C:\...`, which must match the `C:\...` spelling
+    #[cfg(windows)]
+    let normalized = strip_extended_length_prefix(&normalized);
+
+    normalized
+}
+
+/// How many canonicalized paths `canonical_cached` remembers
+const CANONICAL_CACHE_CAPACITY: usize = 256;
+
+/// How long a cached canonicalization is trusted
+const CANONICAL_CACHE_TTL: Duration = Duration::from_secs(30);
+
+struct CanonicalEntry {
+    canonical: PathBuf,
+    resolved_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CanonicalCache {
+    entries: HashMap<PathBuf, CanonicalEntry>,
+    clock: u64,
+}
+
+static CANONICAL_CACHE: Mutex<Option<CanonicalCache>> = Mutex::new(None);
+
+/// `canonicalize`, remembered per process for `CANONICAL_CACHE_TTL`
+///
+/// Discovery and PATH normalization resolve the same few binaries and directories over and
+/// over; this does the filesystem work once. Only successes are cached, so a path that
+/// appears later is seen right away, and the cache lock is never held during the filesystem
+/// call. The least recently used entry is evicted beyond `CANONICAL_CACHE_CAPACITY` paths.
+pub fn canonical_cached(path: &Path) -> Option<PathBuf> {
+    {
+        let mut guard = CANONICAL_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        let cache = guard.get_or_insert_with(CanonicalCache::default);
+        cache.clock += 1;
+        let now = cache.clock;
+        if let Some(entry) = cache.entries.get_mut(path) {
+            if entry.resolved_at.elapsed() < CANONICAL_CACHE_TTL {
+                entry.last_used = now;
+                return Some(entry.canonical.clone());
+            }
+        }
+    }
+
+    let canonical = match path.canonicalize() {
+        Ok(canonical) => canonical,
+        Err(e) => {
+            if is_loop_error(&e) {
+                log::debug!("{} is a symlink loop", path.display());
+            }
+            return None;
+        }
+    };
+
+    let mut guard = CANONICAL_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let cache = guard.get_or_insert_with(CanonicalCache::default);
+    cache.clock += 1;
+    let now = cache.clock;
+    if cache.entries.len() >= CANONICAL_CACHE_CAPACITY && !cache.entries.contains_key(path) {
+        let oldest = cache
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            cache.entries.remove(&oldest);
+        }
+    }
+    cache.entries.insert(
+        path.to_path_buf(),
+        CanonicalEntry {
+            canonical: canonical.clone(),
+            resolved_at: Instant::now(),
+            last_used: now,
+        },
+    );
+    Some(canonical)
+}
+
+/// Forgets every cached canonicalization so the next lookups hit the filesystem
+pub fn clear_canonical_cache() {
+    *CANONICAL_CACHE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Turns `\\?\C:\dir` into `C:\dir` and `\\?\UNC\server\share` into `\\server\share`
+#[cfg(windows)]
+fn strip_extended_length_prefix(path: &str) -> String {
+    if let Some(unc) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", unc)
+    } else if let Some(local) = path.strip_prefix(r"\\?\") {
+        local.to_string()
     } else {
-        trimmed.to_string()
+        path.to_string()
+    }
+}
+
+/// Parses a PATH string into its entries, dropping empty segments and duplicates
+/// Duplicates are detected by normalized form; the first spelling of each entry is kept
+pub fn path_entries(path: &str) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+
+    split_path(path)
+        .filter(|p| !p.is_empty() && seen.insert(normalize_path(p)))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Lists PATH entries that aren't absolute paths, such as `bin` or `./bin`
+/// Empty segments are not reported here. On Windows only drive-qualified (`C:\bin`) and
+/// UNC paths count as absolute; `\bin` and `C:bin` depend on the current drive or directory.
+pub fn non_absolute_entries(path: &str) -> Vec<String> {
+    split_path(path)
+        .filter(|entry| {
+            #[cfg(windows)]
+            let entry = entry.trim_matches('"');
+            !entry.is_empty() && !Path::new(entry).is_absolute()
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `path` is, or runs through, a symlink loop that can never resolve
+/// Such entries make `canonicalize` fail with `ELOOP`, so `normalize_path` keeps their literal
+/// spelling and every lookup through them fails.
+pub fn is_symlink_loop(path: &str) -> bool {
+    match Path::new(path).canonicalize() {
+        Ok(_) => false,
+        Err(e) => is_loop_error(&e),
     }
 }
 
+fn is_loop_error(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        error.raw_os_error() == Some(libc::ELOOP)
+    }
+    #[cfg(windows)]
+    {
+        // ERROR_CANT_RESOLVE_FILENAME
+        error.raw_os_error() == Some(1921)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = error;
+        false
+    }
+}
+
+/// Problem entries found by `audit_path`, each bucket in PATH order
+/// An entry lands in at most one of `relative`, `symlink_loops`, `missing` and
+/// `not_directories`; repeats of an earlier entry are listed in `duplicates`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathAudit {
+    /// Entries that aren't absolute, such as `bin` or `./bin`
+    pub relative: Vec<String>,
+    /// Entries caught in a symlink loop
+    pub symlink_loops: Vec<String>,
+    /// Entries that don't exist
+    pub missing: Vec<String>,
+    /// Entries that exist but aren't directories
+    pub not_directories: Vec<String>,
+    /// Later occurrences of a directory that's already on PATH
+    pub duplicates: Vec<String>,
+}
+
+impl PathAudit {
+    /// Whether no problems were found
+    pub fn is_clean(&self) -> bool {
+        self.relative.is_empty()
+            && self.symlink_loops.is_empty()
+            && self.missing.is_empty()
+            && self.not_directories.is_empty()
+            && self.duplicates.is_empty()
+    }
+}
+
+/// Checks every entry of a PATH string for problems; empty segments are skipped
+pub fn audit_path(path: &str) -> PathAudit {
+    let relative: HashSet<String> = non_absolute_entries(path).into_iter().collect();
+    let mut audit = PathAudit::default();
+    let mut seen = HashSet::new();
+
+    for entry in split_path(path).filter(|entry| !entry.is_empty()) {
+        if !seen.insert(normalize_path(entry)) {
+            audit.duplicates.push(entry.to_string());
+            continue;
+        }
+
+        #[cfg(windows)]
+        let dir = entry.trim_matches('"');
+        #[cfg(not(windows))]
+        let dir = entry;
+
+        if relative.contains(entry) {
+            audit.relative.push(entry.to_string());
+        } else if is_symlink_loop(dir) {
+            audit.symlink_loops.push(entry.to_string());
+        } else if !Path::new(dir).exists() {
+            audit.missing.push(entry.to_string());
+        } else if !Path::new(dir).is_dir() {
+            audit.not_directories.push(entry.to_string());
+        }
+    }
+    audit
+}
+
+/// How one PATH value differs from another
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PathDiff {
+    pub before: String,
+    pub after: String,
+    /// Entries of `after` missing from `before`, in `after` order
+    pub added: Vec<String>,
+    /// Entries of `before` missing from `after`, in `before` order
+    pub removed: Vec<String>,
+}
+
+/// Compares two PATH strings entry by entry, in normalized form
+/// Reordering, dropped duplicates and empty segments don't count as changes.
+pub fn diff_paths(before: &str, after: &str) -> PathDiff {
+    let only_in = |path: &str, other: &str| {
+        let other: HashSet<String> = split_path(other).map(normalize_path).collect();
+        let mut seen = HashSet::new();
+        split_path(path)
+            .filter(|entry| {
+                let key = normalize_path(entry);
+                !key.is_empty() && !other.contains(&key) && seen.insert(key)
+            })
+            .map(str::to_string)
+            .collect()
+    };
+
+    PathDiff {
+        before: before.to_string(),
+        after: after.to_string(),
+        added: only_in(after, before),
+        removed: only_in(before, after),
+    }
+}
+
+/// Whether going from `before` to `after` changes which directories are on PATH
+/// Running sessions keep the PATH they started with, so a significant change means they
+/// need a restart to see it. Pure reordering and dropped duplicates don't count.
+pub fn path_change_is_significant(before: &str, after: &str) -> bool {
+    let diff = diff_paths(before, after);
+    !diff.added.is_empty() || !diff.removed.is_empty()
+}
+
+/// Whether two PATHs hold the same directories once entries under `ignore_prefixes` are
+/// left out, e.g. per-session temp bin dirs that change on every run
+/// Prefixes match whole path components in normalized form, so `/tmp/claudia` covers
+/// `/tmp/claudia/123/bin` but not `/tmp/claudia-old`.
+pub fn paths_equivalent_ignoring(before: &str, after: &str, ignore_prefixes: &[&str]) -> bool {
+    let ignored: Vec<PathBuf> = ignore_prefixes
+        .iter()
+        .map(|prefix| normalize_path(prefix))
+        .filter(|prefix| !prefix.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    let is_ignored = |entry: &String| {
+        let entry = normalize_path(entry);
+        ignored.iter().any(|prefix| Path::new(&entry).starts_with(prefix))
+    };
+
+    let diff = diff_paths(before, after);
+    diff.added.iter().chain(&diff.removed).all(is_ignored)
+}
+
+/// Size and shape of a PATH value, for diagnostics
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathStats {
+    /// Length of the whole PATH string in bytes
+    pub total_len: usize,
+    /// Number of non-empty entries, duplicates included
+    pub entry_count: usize,
+    /// Number of distinct entries after normalization
+    pub unique_count: usize,
+    /// Number of empty segments (e.g. from `::` or a trailing separator)
+    pub empty_segments: usize,
+    /// The longest entry, if there is any
+    pub longest_entry: Option<String>,
+}
+
+impl std::fmt::Display for PathStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PATH: {} chars, {} entries ({} unique), {} empty",
+            self.total_len, self.entry_count, self.unique_count, self.empty_segments
+        )?;
+        if let Some(longest) = &self.longest_entry {
+            write!(f, ", longest {} chars", longest.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes `PathStats` for a PATH string, splitting it like the rest of this module
+pub fn path_stats(path: &str) -> PathStats {
+    let mut stats = PathStats {
+        total_len: path.len(),
+        ..Default::default()
+    };
+    if path.is_empty() {
+        return stats;
+    }
+    
+    let mut seen = HashSet::new();
+    for entry in split_path(path) {
+        if entry.is_empty() {
+            stats.empty_segments += 1;
+            continue;
+        }
+        
+        stats.entry_count += 1;
+        if seen.insert(normalize_path(entry)) {
+            stats.unique_count += 1;
+        }
+        if stats.longest_entry.as_ref().is_none_or(|longest| entry.len() > longest.len()) {
+            stats.longest_entry = Some(entry.to_string());
+        }
+    }
+    
+    stats
+}
+
+/// Joins PATH entries back into a single string using the platform separator
+pub fn join_path_entries(entries: &[PathBuf]) -> String {
+    entries
+        .iter()
+        .map(|p| p.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(&PATH_SEPARATOR.to_string())
+}
+
+/// Normalizes a path for comparison, resolving relative entries (`./bin`, `../bin`)
+/// against `base` first. Absolute entries are normalized as-is.
+pub fn normalize_path_against(path: &str, base: &Path) -> String {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() || Path::new(trimmed).is_absolute() {
+        return normalize_path(path);
+    }
+
+    let joined = lexically_normalize(&base.join(trimmed));
+    normalize_path(&joined.to_string_lossy())
+}
+
+/// Resolves `.` and `..` components without touching the filesystem,
+/// so entries that don't exist yet still compare sensibly
+fn lexically_normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other.as_os_str()),
+        }
+    }
+    result
+}
+
+/// Bounds PATH to at most `max_entries` entries, keeping earlier (higher-priority) ones
+/// The first occurrence of each `keep` directory always survives, ahead of any other entry
+/// when space is short; entries keep their relative order and empty segments are dropped.
+pub fn trim_path(path: &str, max_entries: usize, keep: &[&str]) -> String {
+    let entries: Vec<&str> = split_path(path).filter(|entry| !entry.is_empty()).collect();
+    let mut essential: HashSet<String> = keep.iter().map(|dir| normalize_path(dir)).collect();
+    let is_essential: Vec<bool> = entries
+        .iter()
+        .map(|entry| essential.remove(&normalize_path(entry)))
+        .collect();
+
+    let mut selected = vec![false; entries.len()];
+    let mut budget = max_entries;
+    for wanted in [true, false] {
+        for (i, _) in is_essential.iter().enumerate().filter(|(_, e)| **e == wanted) {
+            if budget == 0 {
+                break;
+            }
+            selected[i] = true;
+            budget -= 1;
+        }
+    }
+
+    entries
+        .iter()
+        .zip(selected)
+        .filter(|(_, selected)| *selected)
+        .map(|(entry, _)| *entry)
+        .collect::<Vec<_>>()
+        .join(&PATH_SEPARATOR.to_string())
+}
+
+/// Moves the `priority` directories present in PATH to its front, in `priority` order
+/// Each moved directory appears once, spelled as in PATH; the other entries follow in their
+/// original order. Priority dirs that aren't on PATH are not added, and empty segments are
+/// dropped.
+pub fn prioritize_path(path: &str, priority: &[&str]) -> String {
+    let entries: Vec<&str> = split_path(path).filter(|entry| !entry.is_empty()).collect();
+    let keys: Vec<String> = entries.iter().map(|entry| normalize_path(entry)).collect();
+
+    let mut front: Vec<&str> = Vec::new();
+    let mut moved = HashSet::new();
+    for dir in priority {
+        let key = normalize_path(dir);
+        if moved.contains(&key) {
+            continue;
+        }
+        if let Some(i) = keys.iter().position(|entry_key| *entry_key == key) {
+            front.push(entries[i]);
+            moved.insert(key);
+        }
+    }
+
+    let rest = entries
+        .iter()
+        .zip(&keys)
+        .filter(|(_, key)| !moved.contains(*key))
+        .map(|(entry, _)| *entry);
+    front
+        .into_iter()
+        .chain(rest)
+        .collect::<Vec<_>>()
+        .join(&PATH_SEPARATOR.to_string())
+}
+
 /// Deduplicates entries in a PATH string
 pub fn deduplicate_path(path: &str) -> String {
     let mut seen = HashSet::new();
     let mut unique_paths = Vec::new();
     
-    for p in path.split(':') {
+    for p in split_path(path) {
         let normalized = normalize_path(p);
         if !normalized.is_empty() && seen.insert(normalized.clone()) {
             unique_paths.push(p.to_string());
         }
     }
     
-    unique_paths.join(":")
+    unique_paths.join(&PATH_SEPARATOR.to_string())
+}
+
+/// How `deduplicate_path_with` treats a symlink and the directory it points at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Entries that canonicalize to the same directory are duplicates, as in `deduplicate_path`
+    Collapse,
+    /// Only identical spellings (ignoring trailing slashes) are duplicates, so a symlink the
+    /// user put on PATH next to its target stays
+    #[default]
+    Keep,
+}
+
+/// Deduplicates entries in a PATH string, comparing them as `policy` says
+/// The original literal of each kept entry is preserved.
+pub fn deduplicate_path_with(path: &str, policy: SymlinkPolicy) -> String {
+    if policy == SymlinkPolicy::Collapse {
+        return deduplicate_path(path);
+    }
+
+    let mut seen = HashSet::new();
+    let mut unique_paths = Vec::new();
+
+    for p in split_path(path) {
+        // Trailing slashes don't make a different directory, but `/` itself stays `/`
+        let key = match p.trim_end_matches('/') {
+            "" if !p.is_empty() => "/",
+            trimmed => trimmed,
+        };
+        if !key.is_empty() && seen.insert(key) {
+            unique_paths.push(p);
+        }
+    }
+
+    unique_paths.join(&PATH_SEPARATOR.to_string())
+}
+
+/// Deduplicates entries in a PATH string, writing each kept entry without trailing slashes
+/// Unlike `deduplicate_path`, `/usr/bin/:/opt/bin//` comes out as `/usr/bin:/opt/bin`.
+pub fn deduplicate_path_canonical(path: &str) -> String {
+    let mut seen = HashSet::new();
+    let mut unique_paths = Vec::new();
+
+    for p in split_path(path) {
+        let normalized = normalize_path(p);
+        if !normalized.is_empty() && seen.insert(normalized) {
+            unique_paths.push(p.trim_end_matches('/'));
+        }
+    }
+
+    unique_paths.join(&PATH_SEPARATOR.to_string())
+}
+
+/// Deduplicates entries in a PATH string, keeping the last occurrence of each directory
+/// This mirrors shell "last wins" overrides: `/a:/b:/a:/c` becomes `/b:/a:/c`
+pub fn deduplicate_path_keep_last(path: &str) -> String {
+    let mut seen = HashSet::new();
+    let mut unique_paths: Vec<&str> = split_path(path)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .filter(|p| {
+            let normalized = normalize_path(p);
+            !normalized.is_empty() && seen.insert(normalized)
+        })
+        .collect();
+    unique_paths.reverse();
+
+    unique_paths.join(&PATH_SEPARATOR.to_string())
+}
+
+/// Deduplicates entries in a PATH string, treating relative entries as relative to `base`
+/// The original literal of each kept entry is preserved
+pub fn deduplicate_path_against(path: &str, base: &Path) -> String {
+    let mut seen = HashSet::new();
+    let mut unique_paths = Vec::new();
+
+    for p in split_path(path) {
+        let normalized = normalize_path_against(p, base);
+        if !normalized.is_empty() && seen.insert(normalized) {
+            unique_paths.push(p.to_string());
+        }
+    }
+
+    unique_paths.join(&PATH_SEPARATOR.to_string())
+}
+
+/// Checks whether a path is a regular file the current user can execute
+/// On Unix this looks at the mode bits; elsewhere any existing file qualifies
+pub fn is_executable_file(path: &Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+    if !metadata.is_file() {
+        return false;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        true
+    }
+}
+
+/// Why `sanitize_binary_path` rejected a user-supplied path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "path", rename_all = "snake_case")]
+pub enum PathError {
+    /// Nothing was left after trimming whitespace and quotes
+    Empty,
+    /// The path starts with `~` but the home directory is unknown
+    NoHomeDir,
+    /// Nothing exists at the path
+    Missing(PathBuf),
+    /// The path exists but is a directory or other non-file
+    NotAFile(PathBuf),
+    /// The file lacks execute permission
+    NotExecutable(PathBuf),
+}
+
+impl std::fmt::Display for PathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "No path given"),
+            PathError::NoHomeDir => write!(f, "Cannot expand ~: home directory is unknown"),
+            PathError::Missing(path) => write!(f, "{} does not exist", path.display()),
+            PathError::NotAFile(path) => write!(f, "{} is not a file", path.display()),
+            PathError::NotExecutable(path) => write!(f, "{} is not executable", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// Cleans up a binary path pasted by the user and checks it points at an executable file
+/// Trims whitespace and surrounding quotes, strips a `file://` prefix (decoding `%XX`
+/// escapes), expands a leading `~` and resolves symlinks.
+pub fn sanitize_binary_path(input: &str) -> Result<PathBuf, PathError> {
+    let mut cleaned = input.trim();
+    for quote in ['"', '\''] {
+        if cleaned.len() >= 2 && cleaned.starts_with(quote) && cleaned.ends_with(quote) {
+            cleaned = cleaned[1..cleaned.len() - 1].trim();
+        }
+    }
+    let cleaned = match cleaned.strip_prefix("file://") {
+        Some(url_path) => percent_decode(url_path),
+        None => cleaned.to_string(),
+    };
+    if cleaned.is_empty() {
+        return Err(PathError::Empty);
+    }
+
+    let path = match cleaned.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            let home = dirs::home_dir().ok_or(PathError::NoHomeDir)?;
+            home.join(rest.trim_start_matches(['/', '\\']))
+        }
+        _ => PathBuf::from(cleaned),
+    };
+
+    let resolved = std::fs::canonicalize(&path).map_err(|_| PathError::Missing(path))?;
+    // canonicalize returns `\\?\` paths on Windows, which many programs can't run
+    #[cfg(windows)]
+    let resolved = PathBuf::from(strip_extended_length_prefix(&resolved.to_string_lossy()));
+    if !resolved.is_file() {
+        Err(PathError::NotAFile(resolved))
+    } else if !is_executable_file(&resolved) {
+        Err(PathError::NotExecutable(resolved))
+    } else {
+        Ok(resolved)
+    }
+}
+
+/// Decodes `%XX` escapes from a URL path; malformed escapes are kept as they are
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| input.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Serializes every change this module makes to the process PATH
+static PROCESS_PATH_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock_process_path() -> MutexGuard<'static, ()> {
+    // A panic while holding the lock can't leave PATH half-written, so poisoning is harmless
+    PROCESS_PATH_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Deduplicates `new_path` and sets it as the process PATH
+pub fn set_process_path_deduplicated(new_path: &str) {
+    update_process_path(|_| new_path.to_string());
+}
+
+/// Atomically reads the process PATH, applies `update`, deduplicates the result and stores it
+/// Concurrent callers never lose each other's changes. Returns the new PATH.
+pub fn update_process_path(update: impl FnOnce(&str) -> String) -> String {
+    let _lock = lock_process_path();
+    let current = env::var("PATH").unwrap_or_default();
+    let new_path = deduplicate_path(&update(&current));
+    env::set_var("PATH", &new_path);
+    new_path
+}
+
+/// Sets PATH to `path` while `f` runs, restoring the previous value afterwards, even on panic
+pub fn with_temp_path<T>(path: &str, f: impl FnOnce() -> T) -> T {
+    struct RestorePath(Option<OsString>);
+
+    impl Drop for RestorePath {
+        fn drop(&mut self) {
+            let _lock = lock_process_path();
+            match self.0.take() {
+                Some(original) => env::set_var("PATH", original),
+                None => env::remove_var("PATH"),
+            }
+        }
+    }
+
+    let _restore = {
+        let _lock = lock_process_path();
+        let original = env::var_os("PATH");
+        env::set_var("PATH", path);
+        RestorePath(original)
+    };
+
+    f()
+}
+
+/// Env var that turns off every PATH enhancement for reproducible runs (CI, sandboxes)
+pub const NO_PATH_ENHANCE_VAR: &str = "CLAUDIA_NO_PATH_ENHANCE";
+
+/// Whether `CLAUDIA_NO_PATH_ENHANCE` is set to `1` or `true`
+pub fn path_enhancement_disabled() -> bool {
+    env::var(NO_PATH_ENHANCE_VAR)
+        .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
 }
 
 /// Enhances PATH with common directories if they exist and aren't already present
+/// Added directories go ahead of the existing PATH in exactly the order the caller listed
+/// them; a directory listed twice keeps its first position.
 pub fn enhance_path_for_common_locations(paths: &[&str]) -> Option<String> {
-    let current_path = env::var("PATH").unwrap_or_default();
-    let mut new_paths = Vec::new();
-    
-    for path in paths {
-        if Path::new(path).exists() && !path_contains_dir(&current_path, path) {
-            new_paths.push(path.to_string());
-        }
+    if path_enhancement_disabled() {
+        log::debug!("{} is set, not enhancing PATH", NO_PATH_ENHANCE_VAR);
+        return None;
     }
+    let current_path = env::var("PATH").unwrap_or_default();
+    let mut new_paths = common_locations_to_add(paths, &current_path);
     
     if new_paths.is_empty() {
         return None;
@@ -77,18 +981,225 @@ pub fn enhance_path_for_common_locations(paths: &[&str]) -> Option<String> {
     
     // Combine new paths with existing PATH
     new_paths.push(current_path);
-    let enhanced_path = new_paths.join(":");
+    let enhanced_path = new_paths.join(&PATH_SEPARATOR.to_string());
     
     // Deduplicate the final PATH
     Some(deduplicate_path(&enhanced_path))
 }
 
+/// The existing `paths` missing from `current_path`, each directory accepted once
+/// Two spellings of one directory (`/opt/bin`, `/opt/bin/`) only yield the first, without
+/// relying on the final deduplication.
+fn common_locations_to_add(paths: &[&str], current_path: &str) -> Vec<String> {
+    let mut new_paths: Vec<String> = Vec::new();
+    let mut accepted = HashSet::new();
+
+    for path in paths {
+        if Path::new(path).exists()
+            && !path_contains_dir(current_path, path)
+            && accepted.insert(normalize_path(path))
+        {
+            new_paths.push(path.to_string());
+        }
+    }
+
+    new_paths
+}
+
+/// How long `login_shell_path` waits for the user's shell startup files to run
+const LOGIN_SHELL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Markers around the PATH printed by the login shell, so rc-file output can't be mistaken for it
+const LOGIN_PATH_START: &str = "__CLAUDIA_PATH_START__";
+const LOGIN_PATH_END: &str = "__CLAUDIA_PATH_END__";
+
+/// Asks the user's login shell for the PATH a Terminal session would have
+/// Apps launched from the dock or Finder inherit a minimal PATH without Homebrew or nvm;
+/// merging this in fixes that. Returns `None` when `$SHELL` is unset, the shell fails or
+/// doesn't finish within a few seconds, or it prints no PATH.
+pub fn login_shell_path() -> Option<String> {
+    let shell = env::var("SHELL").ok().filter(|shell| !shell.trim().is_empty())?;
+    let script = format!("echo \"{}${{PATH}}{}\"", LOGIN_PATH_START, LOGIN_PATH_END);
+
+    let mut child = std::process::Command::new(&shell)
+        .arg("-lic")
+        .arg(script)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map_err(|e| log::warn!("Failed to start login shell {}: {}", shell, e))
+        .ok()?;
+
+    // Read stdout while waiting, so a chatty rc file can't fill the pipe and stall the shell
+    let stdout = read_in_background(child.stdout.take());
+    let deadline = Instant::now() + LOGIN_SHELL_TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            _ => {
+                log::warn!("Login shell {} did not report PATH in time", shell);
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    };
+    if !status.success() {
+        log::warn!("Login shell {} exited with {}", shell, status);
+        return None;
+    }
+
+    // A background process the rc file started may hold stdout open; don't wait past the deadline
+    let stdout = stdout
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&stdout);
+    let start = stdout.rfind(LOGIN_PATH_START)? + LOGIN_PATH_START.len();
+    let end = start + stdout[start..].find(LOGIN_PATH_END)?;
+    let path = stdout[start..end].trim();
+    (!path.is_empty()).then(|| path.to_string())
+}
+
+/// Reads `pipe` to the end on its own thread; the receiver gets everything read
+/// A missing pipe yields an empty buffer right away.
+pub(crate) fn read_in_background<R>(pipe: Option<R>) -> std::sync::mpsc::Receiver<Vec<u8>>
+where
+    R: std::io::Read + Send + 'static,
+{
+    let (tx, rx) = std::sync::mpsc::channel();
+    match pipe {
+        Some(mut pipe) => {
+            std::thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = pipe.read_to_end(&mut buf);
+                let _ = tx.send(buf);
+            });
+        }
+        None => {
+            let _ = tx.send(Vec::new());
+        }
+    }
+    rx
+}
+
+/// Combines the PATH a GUI launch inherited with the one from `login_shell_path`
+/// The shell's entries come first, in its order, since that's what the user configured;
+/// GUI-only entries follow. Every directory appears once.
+pub fn merge_shell_path(gui_path: &str, shell_path: &str) -> String {
+    deduplicate_path(&format!("{}{}{}", shell_path, PATH_SEPARATOR, gui_path))
+}
+
+/// Startup files, relative to the home directory, a PATH change for `shell` could go in
+/// The first is the one to create when none of them exist yet.
+fn shell_rc_candidates(shell: &str) -> &'static [&'static str] {
+    match Path::new(shell).file_name().and_then(|name| name.to_str()) {
+        Some("zsh") => &[".zshrc", ".zprofile"],
+        Some("bash") => &[".bashrc", ".bash_profile", ".profile"],
+        Some("fish") => &[".config/fish/config.fish"],
+        _ => &[".profile"],
+    }
+}
+
+/// The login shell's rc files, each with whether Claudia could write a PATH change to it
+/// Existing files are listed with their own writability; when none exists, the file that
+/// would be created is listed with its directory's. Nothing is created or modified.
+/// Empty when no home directory is known.
+pub fn shell_rc_writable() -> Vec<(PathBuf, bool)> {
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    let shell = env::var("SHELL").unwrap_or_default();
+    let candidates: Vec<PathBuf> = shell_rc_candidates(&shell)
+        .iter()
+        .map(|name| home.join(name))
+        .collect();
+
+    let existing: Vec<(PathBuf, bool)> = candidates
+        .iter()
+        .filter(|rc_file| rc_file.is_file())
+        .map(|rc_file| (rc_file.clone(), is_writable_file(rc_file)))
+        .collect();
+    if !existing.is_empty() {
+        return existing;
+    }
+
+    // A missing file can be created if its nearest existing ancestor is writable
+    let first = candidates[0].clone();
+    let writable = first
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.exists())
+        .is_some_and(is_writable_dir);
+    vec![(first, writable)]
+}
+
+/// Whether a new entry can be created in `dir`. The read-only attribute says nothing about
+/// directories, so ask the OS (`access(W_OK)`) or, elsewhere, create and remove a probe file.
+fn is_writable_dir(dir: &Path) -> bool {
+    if !dir.is_dir() {
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let Ok(c_path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+            return false;
+        };
+        // SAFETY: `c_path` is a valid NUL-terminated string that outlives the call
+        unsafe { libc::access(c_path.as_ptr(), libc::W_OK) == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        let probe = dir.join(format!(".claudia-write-test-{}", std::process::id()));
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+            Ok(_) => std::fs::remove_file(&probe).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Whether `path` can be opened for appending; the mode bits are checked first so a
+/// read-only file counts as such even for root. Opening doesn't touch the contents.
+fn is_writable_file(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| !metadata.permissions().readonly())
+        && std::fs::OpenOptions::new().append(true).open(path).is_ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serial_test::serial;
+    use std::ffi::OsStr;
     use std::fs;
     use tempfile::TempDir;
     
+    /// Sets an environment variable until dropped, then restores the old value even if the
+    /// test panics
+    struct EnvVarGuard {
+        key: &'static str,
+        original: Option<OsString>,
+    }
+    
+    impl EnvVarGuard {
+        fn set(key: &'static str, value: impl AsRef<OsStr>) -> Self {
+            let original = env::var_os(key);
+            env::set_var(key, value);
+            EnvVarGuard { key, original }
+        }
+    }
+    
+    impl Drop for EnvVarGuard {
+        fn drop(&mut self) {
+            match &self.original {
+                Some(value) => env::set_var(self.key, value),
+                None => env::remove_var(self.key),
+            }
+        }
+    }
+    
     #[test]
     fn test_path_contains_dir() {
         assert!(path_contains_dir("/usr/bin:/usr/local/bin", "/usr/bin"));
@@ -147,6 +1258,7 @@ mod tests {
     }
     
     #[test]
+    #[serial]
     fn test_add_to_path_if_missing() {
         // Test with existing PATH
         env::set_var("PATH", "/usr/bin:/usr/local/bin");
@@ -171,6 +1283,7 @@ mod tests {
     }
     
     #[test]
+    #[serial]
     fn test_enhance_path_for_common_locations() {
         // Create temp directories for testing
         let temp_dir = TempDir::new().unwrap();
@@ -229,4 +1342,756 @@ mod tests {
         let normalized = normalize_path(real_dir.to_str().unwrap());
         assert!(normalized.contains("real"));
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_normalize_path_against_base() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path().join("project");
+        fs::create_dir(&base).unwrap();
+        fs::create_dir(base.join("bin")).unwrap();
+        fs::create_dir(temp_dir.path().join("bin")).unwrap();
+        
+        let abs_bin = base.join("bin");
+        let parent_bin = temp_dir.path().join("bin");
+        
+        assert_eq!(
+            normalize_path_against("./bin", &base),
+            normalize_path(abs_bin.to_str().unwrap())
+        );
+        assert_eq!(
+            normalize_path_against("../bin", &base),
+            normalize_path(parent_bin.to_str().unwrap())
+        );
+        
+        // Absolute entries are untouched by the base
+        assert_eq!(normalize_path_against("/usr/bin/", &base), "/usr/bin");
+        
+        // Relative entries that don't exist are still resolved lexically
+        assert_eq!(
+            normalize_path_against("./missing/../other", Path::new("/base/dir")),
+            "/base/dir/other"
+        );
+    }
+    
+    #[test]
+    fn test_deduplicate_path_against_base() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path().join("project");
+        fs::create_dir_all(base.join("bin")).unwrap();
+        fs::create_dir(temp_dir.path().join("bin")).unwrap();
+        
+        let abs_bin = base.join("bin");
+        let parent_bin = temp_dir.path().join("bin");
+        let path = format!(
+            "{}:./bin:{}:../bin:/usr/bin",
+            abs_bin.to_str().unwrap(),
+            parent_bin.to_str().unwrap()
+        );
+        
+        let deduped = deduplicate_path_against(&path, &base);
+        assert_eq!(
+            deduped,
+            format!(
+                "{}:{}:/usr/bin",
+                abs_bin.to_str().unwrap(),
+                parent_bin.to_str().unwrap()
+            )
+        );
+    }
+    
+    #[test]
+    fn test_path_entries_round_trip() {
+        let path = "/usr/bin::/opt/bin:/usr/bin/:/usr/local/bin::/opt/bin";
+        let entries = path_entries(path);
+        assert_eq!(
+            entries,
+            vec![
+                PathBuf::from("/usr/bin"),
+                PathBuf::from("/opt/bin"),
+                PathBuf::from("/usr/local/bin"),
+            ]
+        );
+        
+        let joined = join_path_entries(&entries);
+        assert_eq!(joined, "/usr/bin:/opt/bin:/usr/local/bin");
+        assert_eq!(joined, deduplicate_path(path));
+        
+        // Parsing the joined form again is a no-op
+        assert_eq!(path_entries(&joined), entries);
+        
+        assert!(path_entries("").is_empty());
+        assert_eq!(join_path_entries(&[]), "");
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_add_to_path_if_missing_existing() {
+        let temp_dir = TempDir::new().unwrap();
+        let existing = temp_dir.path().to_str().unwrap().to_string();
+        let missing = temp_dir.path().join("missing").to_str().unwrap().to_string();
+        
+        with_temp_path("/usr/bin:/bin", || {
+            let result = add_to_path_if_missing_existing(&existing);
+            assert_eq!(result, format!("{}:/usr/bin:/bin", existing));
+            
+            // A directory that doesn't exist leaves PATH as it was
+            let result = add_to_path_if_missing_existing(&missing);
+            assert_eq!(result, "/usr/bin:/bin");
+            
+            // The permissive variant still pre-seeds it
+            let result = add_to_path_if_missing(&missing);
+            assert_eq!(result, format!("{}:/usr/bin:/bin", missing));
+        });
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_common_locations_accepts_each_dir_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let opt_bin = temp_dir.path().join("opt").join("bin");
+        fs::create_dir_all(&opt_bin).unwrap();
+        let plain = opt_bin.to_str().unwrap().to_string();
+        let slashed = format!("{}/", plain);
+        
+        let added = common_locations_to_add(&[&plain, &slashed], "/usr/bin");
+        assert_eq!(added, vec![plain.clone()]);
+        
+        // Already on PATH in either spelling, so neither is accepted
+        assert!(common_locations_to_add(&[&slashed, &plain], &plain).is_empty());
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_enhance_path_preserves_caller_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let dirs: Vec<String> = ["zeta", "alpha", "mid"]
+            .iter()
+            .map(|name| {
+                let dir = temp_dir.path().join(name);
+                fs::create_dir(&dir).unwrap();
+                dir.to_str().unwrap().to_string()
+            })
+            .collect();
+        
+        // Repeats (including a trailing-slash spelling) keep the first position
+        let zeta_slash = format!("{}/", dirs[0]);
+        let requested = [
+            dirs[0].as_str(),
+            dirs[1].as_str(),
+            zeta_slash.as_str(),
+            dirs[2].as_str(),
+            dirs[1].as_str(),
+        ];
+        
+        let enhanced = with_temp_path("/usr/bin:/usr/local/bin", || {
+            enhance_path_for_common_locations(&requested)
+        })
+        .unwrap();
+        assert_eq!(
+            enhanced,
+            format!("{}:{}:{}:/usr/bin:/usr/local/bin", dirs[0], dirs[1], dirs[2])
+        );
+    }
+    
+    #[cfg(unix)]
+    fn write_mock_shell(dir: &Path, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let shell = dir.join("mock-shell");
+        fs::write(&shell, format!("#!/bin/sh\n{}", body)).unwrap();
+        fs::set_permissions(&shell, fs::Permissions::from_mode(0o755)).unwrap();
+        shell.to_str().unwrap().to_string()
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_merge_shell_path() {
+        // Overlapping: shell order wins, GUI-only entries trail, nothing repeats
+        let gui = "/usr/bin:/opt/gui/bin:/Applications/Tool.app/bin";
+        let shell = "/opt/homebrew/bin:/usr/local/bin:/usr/bin/:/opt/homebrew/bin";
+        assert_eq!(
+            merge_shell_path(gui, shell),
+            "/opt/homebrew/bin:/usr/local/bin:/usr/bin/:/opt/gui/bin:/Applications/Tool.app/bin"
+        );
+        
+        // Disjoint: simply shell then GUI
+        assert_eq!(merge_shell_path("/a:/b", "/c:/d"), "/c:/d:/a:/b");
+        assert_eq!(merge_shell_path("/a", ""), "/a");
+        assert_eq!(merge_shell_path("", "/c"), "/c");
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_shell_rc_writable() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let zshrc = temp_dir.path().join(".zshrc");
+        let zprofile = temp_dir.path().join(".zprofile");
+        fs::write(&zshrc, "export PATH=/opt/bin:$PATH\n").unwrap();
+        fs::write(&zprofile, "").unwrap();
+        fs::set_permissions(&zshrc, fs::Permissions::from_mode(0o444)).unwrap();
+        
+        let home_var = EnvVarGuard::set("HOME", temp_dir.path());
+        let shell_var = EnvVarGuard::set("SHELL", "/bin/zsh");
+        let zsh = shell_rc_writable();
+        drop(shell_var);
+        // No bash rc files yet: the one that would be created is reported
+        let _shell = EnvVarGuard::set("SHELL", "/usr/local/bin/bash");
+        let bash = shell_rc_writable();
+        drop(home_var);
+        // A home that doesn't exist yet is judged by its nearest existing ancestor
+        let _home = EnvVarGuard::set("HOME", temp_dir.path().join("new/home"));
+        let missing_home = shell_rc_writable();
+        
+        assert_eq!(zsh, vec![(zshrc.clone(), false), (zprofile, true)]);
+        assert_eq!(bash, vec![(temp_dir.path().join(".bashrc"), true)]);
+        assert_eq!(missing_home, vec![(temp_dir.path().join("new/home/.bashrc"), true)]);
+        assert_eq!(fs::read_to_string(&zshrc).unwrap(), "export PATH=/opt/bin:$PATH\n");
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_login_shell_path() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // rc-file noise before and after the PATH is ignored
+        let shell = write_mock_shell(
+            temp_dir.path(),
+            "echo 'Welcome back!'\nPATH=/opt/homebrew/bin:/usr/bin:/bin\neval \"$2\"\necho bye",
+        );
+        // Changed below as well; the guard restores the original when the test ends
+        let _shell = EnvVarGuard::set("SHELL", &shell);
+        assert_eq!(
+            login_shell_path(),
+            Some("/opt/homebrew/bin:/usr/bin:/bin".to_string())
+        );
+        
+        // More output than a pipe buffer holds doesn't stall the shell
+        let chatty = write_mock_shell(
+            temp_dir.path(),
+            "head -c 200000 /dev/zero | tr '\\0' x\necho\nPATH=/usr/bin:/bin\neval \"$2\"",
+        );
+        env::set_var("SHELL", &chatty);
+        assert_eq!(login_shell_path(), Some("/usr/bin:/bin".to_string()));
+        
+        let failing = write_mock_shell(temp_dir.path(), "eval \"$2\"\nexit 1");
+        env::set_var("SHELL", &failing);
+        assert_eq!(login_shell_path(), None);
+        
+        env::remove_var("SHELL");
+        assert_eq!(login_shell_path(), None);
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_path_stats() {
+        let stats = path_stats("/usr/bin::/opt/homebrew/bin:/usr/bin/:/usr/local/bin:");
+        assert_eq!(
+            stats,
+            PathStats {
+                total_len: 53,
+                entry_count: 4,
+                unique_count: 3,
+                empty_segments: 2,
+                longest_entry: Some("/opt/homebrew/bin".to_string()),
+            }
+        );
+        assert!(stats.unique_count < stats.entry_count);
+        assert_eq!(
+            stats.to_string(),
+            "PATH: 53 chars, 4 entries (3 unique), 2 empty, longest 17 chars"
+        );
+        
+        assert_eq!(path_stats(""), PathStats::default());
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_path_builder_records_origins() {
+        let nvm_bin = "/home/me/.nvm/versions/node/v20.11.0/bin";
+        let mut builder = PathBuilder::inherited("/usr/bin::/usr/local/bin:/usr/bin/");
+        assert!(builder.prepend(nvm_bin, EntryOrigin::NvmResolution));
+        assert!(builder.append("/opt/homebrew/bin", EntryOrigin::CommonLocation));
+        assert!(builder.append("/opt/tools", EntryOrigin::Manual));
+        
+        // A directory that's already present keeps its first origin
+        assert!(!builder.prepend("/usr/bin/", EntryOrigin::CommonLocation));
+        assert_eq!(builder.origin_of("/usr/bin/"), Some(EntryOrigin::Inherited));
+        assert_eq!(builder.origin_of("/nowhere"), None);
+        
+        assert_eq!(
+            builder.to_string(),
+            format!("{}:/usr/bin:/usr/local/bin:/opt/homebrew/bin:/opt/tools", nvm_bin)
+        );
+        assert_eq!(
+            builder.into_entries(),
+            vec![
+                (nvm_bin.to_string(), EntryOrigin::NvmResolution),
+                ("/usr/bin".to_string(), EntryOrigin::Inherited),
+                ("/usr/local/bin".to_string(), EntryOrigin::Inherited),
+                ("/opt/homebrew/bin".to_string(), EntryOrigin::CommonLocation),
+                ("/opt/tools".to_string(), EntryOrigin::Manual),
+            ]
+        );
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_symlink_loop_detection() {
+        let temp_dir = TempDir::new().unwrap();
+        let a = temp_dir.path().join("loop-a");
+        let b = temp_dir.path().join("loop-b");
+        std::os::unix::fs::symlink(&b, &a).unwrap();
+        std::os::unix::fs::symlink(&a, &b).unwrap();
+        let real = temp_dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        let file = temp_dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let missing = temp_dir.path().join("missing");
+        
+        let (a, real, file, missing) = (
+            a.to_str().unwrap(),
+            real.to_str().unwrap(),
+            file.to_str().unwrap(),
+            missing.to_str().unwrap(),
+        );
+        assert!(is_symlink_loop(a));
+        assert!(is_symlink_loop(&format!("{}/bin", a)));
+        assert!(!is_symlink_loop(real));
+        assert!(!is_symlink_loop(missing));
+        
+        let path = [a, real, "bin", missing, file, real].join(":");
+        let audit = audit_path(&path);
+        assert_eq!(
+            audit,
+            PathAudit {
+                relative: vec!["bin".to_string()],
+                symlink_loops: vec![a.to_string()],
+                missing: vec![missing.to_string()],
+                not_directories: vec![file.to_string()],
+                duplicates: vec![real.to_string()],
+            }
+        );
+        assert!(!audit.is_clean());
+        assert!(audit_path(real).is_clean());
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_add_all_to_path() {
+        let path = "/usr/bin:/usr/local/bin:/usr/bin";
+        let dirs = ["/opt/b", "/usr/local/bin/", "/opt/a", "/opt/b", "", "/usr/bin"];
+        
+        assert_eq!(
+            add_all_to_path(path, &dirs, PathPosition::Prepend),
+            "/opt/b:/opt/a:/usr/bin:/usr/local/bin"
+        );
+        assert_eq!(
+            add_all_to_path(path, &dirs, PathPosition::Append),
+            "/usr/bin:/usr/local/bin:/opt/b:/opt/a"
+        );
+        
+        // Nothing to add still yields a deduplicated PATH
+        assert_eq!(
+            add_all_to_path(path, &["/usr/bin"], PathPosition::Prepend),
+            "/usr/bin:/usr/local/bin"
+        );
+        assert_eq!(add_all_to_path("", &["/opt/a"], PathPosition::Append), "/opt/a");
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_diff_paths() {
+        let diff = diff_paths(
+            "/usr/bin:/bin/old::/usr/local/bin",
+            "/opt/a:/usr/local/bin/:/usr/bin:/opt/a",
+        );
+        assert_eq!(diff.added, vec!["/opt/a".to_string()]);
+        assert_eq!(diff.removed, vec!["/bin/old".to_string()]);
+        assert_eq!(diff.before, "/usr/bin:/bin/old::/usr/local/bin");
+        
+        let unchanged = diff_paths("/usr/bin:/usr/bin", "/usr/bin");
+        assert!(unchanged.added.is_empty() && unchanged.removed.is_empty());
+    }
+    
+    #[test]
+    fn test_path_change_is_significant() {
+        let before = "/usr/local/bin:/usr/bin:/opt/node/bin";
+        assert!(!path_change_is_significant(before, before));
+        assert!(!path_change_is_significant(before, "/opt/node/bin:/usr/local/bin:/usr/bin"));
+        
+        let added = format!("/opt/claude/bin:{}", before);
+        assert!(path_change_is_significant(before, &added));
+        assert!(path_change_is_significant(before, "/usr/local/bin:/usr/bin"));
+    }
+    
+    #[test]
+    fn test_paths_equivalent_ignoring() {
+        let ignore = ["/tmp/claudia-session"];
+        let before = "/usr/local/bin:/tmp/claudia-session/41/bin:/usr/bin";
+        
+        // Only the session dir changed
+        let after = "/usr/bin:/tmp/claudia-session/42/bin:/usr/local/bin";
+        assert!(paths_equivalent_ignoring(before, after, &ignore));
+        assert!(paths_equivalent_ignoring(before, "/usr/local/bin:/usr/bin", &ignore));
+        assert!(!paths_equivalent_ignoring(before, after, &[]));
+        
+        // Real differences still count, including lookalikes of the ignored prefix
+        let added = "/usr/local/bin:/tmp/claudia-session/42/bin:/usr/bin:/opt/claude/bin";
+        assert!(!paths_equivalent_ignoring(before, added, &ignore));
+        assert!(!paths_equivalent_ignoring(before, "/usr/bin", &ignore));
+        let lookalike = "/usr/local/bin:/usr/bin:/tmp/claudia-session-old/bin";
+        assert!(!paths_equivalent_ignoring(before, lookalike, &ignore));
+    }
+    
+    #[test]
+    #[serial]
+    fn test_canonical_cached() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("later");
+        
+        let existing = canonical_cached(temp_dir.path());
+        assert_eq!(existing, Some(temp_dir.path().canonicalize().unwrap()));
+        assert_eq!(canonical_cached(temp_dir.path()), existing);
+        
+        // Misses aren't remembered, so a directory created afterwards is seen at once
+        assert_eq!(canonical_cached(&dir), None);
+        fs::create_dir(&dir).unwrap();
+        assert_eq!(canonical_cached(&dir), Some(dir.canonicalize().unwrap()));
+        
+        // A hit is served even after the link moves; clearing resolves it again
+        #[cfg(unix)]
+        {
+            let link = temp_dir.path().join("link");
+            std::os::unix::fs::symlink(&dir, &link).unwrap();
+            let before = canonical_cached(&link);
+            assert_eq!(before, Some(dir.canonicalize().unwrap()));
+            fs::remove_file(&link).unwrap();
+            std::os::unix::fs::symlink(temp_dir.path(), &link).unwrap();
+            assert_eq!(canonical_cached(&link), before);
+            clear_canonical_cache();
+            assert_eq!(canonical_cached(&link), existing);
+        }
+    }
+    
+    #[test]
+    #[serial]
+    fn test_canonical_cache_is_bounded() {
+        let temp_dir = TempDir::new().unwrap();
+        clear_canonical_cache();
+        for i in 0..CANONICAL_CACHE_CAPACITY + 10 {
+            let dir = temp_dir.path().join(i.to_string());
+            fs::create_dir(&dir).unwrap();
+            assert!(canonical_cached(&dir).is_some());
+        }
+        let guard = CANONICAL_CACHE.lock().unwrap();
+        let cache = guard.as_ref().unwrap();
+        assert_eq!(cache.entries.len(), CANONICAL_CACHE_CAPACITY);
+        // The oldest entries went first
+        assert!(!cache.entries.contains_key(&temp_dir.path().join("0")));
+        let newest = (CANONICAL_CACHE_CAPACITY + 9).to_string();
+        assert!(cache.entries.contains_key(&temp_dir.path().join(newest)));
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_prioritize_path() {
+        let path = "/usr/bin:/opt/node/bin:/a:/opt/claude/bin/:/b:/opt/node/bin";
+        
+        // Present priority dirs move to the front in priority order, once each
+        assert_eq!(
+            prioritize_path(path, &["/opt/claude/bin", "/opt/node/bin"]),
+            "/opt/claude/bin/:/opt/node/bin:/usr/bin:/a:/b"
+        );
+        
+        // Absent priority dirs are not added
+        assert_eq!(
+            prioritize_path(path, &["/missing/bin", "/a", "/a/"]),
+            "/a:/usr/bin:/opt/node/bin:/opt/claude/bin/:/b:/opt/node/bin"
+        );
+        assert_eq!(prioritize_path("/x::/y", &[]), "/x:/y");
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_trim_path() {
+        let path = "/a:/b::/c:/usr/bin:/d:/usr/local/bin:/e";
+        
+        // Kept dirs survive even though they sit past the cut-off
+        let trimmed = trim_path(path, 4, &["/usr/local/bin", "/usr/bin/"]);
+        assert_eq!(trimmed, "/a:/b:/usr/bin:/usr/local/bin");
+        
+        // Without kept dirs the lowest-priority tail is dropped
+        assert_eq!(trim_path(path, 3, &[]), "/a:/b:/c");
+        
+        // Kept dirs that aren't on PATH aren't invented
+        assert_eq!(trim_path(path, 2, &["/opt/homebrew/bin"]), "/a:/b");
+        
+        // Never more than max_entries, even when more dirs are marked essential
+        for max in 0..8 {
+            let trimmed = trim_path(path, max, &["/e", "/d", "/c"]);
+            let count = trimmed.split(':').filter(|entry| !entry.is_empty()).count();
+            assert!(count <= max, "{} entries for a limit of {}", count, max);
+        }
+        assert_eq!(trim_path(path, 2, &["/e", "/d", "/c"]), "/c:/d");
+        
+        // Short PATHs pass through untouched apart from empty segments
+        assert_eq!(trim_path(path, 100, &["/e"]), "/a:/b:/c:/usr/bin:/d:/usr/local/bin:/e");
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_path_set_matches_add_to_path_if_missing() {
+        let temp_dir = TempDir::new().unwrap();
+        let real = temp_dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        let real = real.to_str().unwrap().to_string();
+        let real_slash = format!("{}/", real);
+        
+        let additions = [
+            "/opt/bin",
+            "/usr/bin",
+            "/opt/bin/",
+            real.as_str(),
+            "/x",
+            real_slash.as_str(),
+        ];
+        for initial in ["", "/usr/bin:/usr/local/bin", "/usr/bin::/sbin/"] {
+            let expected = with_temp_path(initial, || {
+                for dir in additions {
+                    let updated = add_to_path_if_missing(dir);
+                    env::set_var("PATH", updated);
+                }
+                env::var("PATH").unwrap()
+            });
+            
+            let mut set = PathSet::new(initial);
+            for dir in additions {
+                set.add_if_missing(dir);
+            }
+            assert_eq!(set.to_string(), expected, "starting from {:?}", initial);
+        }
+        
+        let set = PathSet::new("/usr/bin:/usr/local/bin/");
+        assert!(set.contains("/usr/local/bin"));
+        assert!(!set.contains("/opt/bin"));
+    }
+    
+    #[test]
+    fn test_non_absolute_entries() {
+        let absolute = if cfg!(windows) { r"C:\tools" } else { "/usr/bin" };
+        let path = [absolute, "bin", "", "./bin", absolute, "../lib/bin"]
+            .join(&PATH_SEPARATOR.to_string());
+        
+        assert_eq!(non_absolute_entries(&path), vec!["bin", "./bin", "../lib/bin"]);
+        assert!(non_absolute_entries(absolute).is_empty());
+        assert!(non_absolute_entries("").is_empty());
+    }
+    
+    #[test]
+    #[cfg(windows)]
+    fn test_non_absolute_entries_windows() {
+        let path = r#"C:\bin;bin;\bin;C:bin;\\server\share\bin;"C:\Program Files\x""#;
+        assert_eq!(non_absolute_entries(path), vec!["bin", r"\bin", "C:bin"]);
+    }
+    
+    #[test]
+    #[cfg(windows)]
+    fn test_split_path_respects_quotes() {
+        let path = r#""C:\Program Files\x;y";C:\bin;;C:\tools"#;
+        let entries: Vec<&str> = split_path(path).collect();
+        assert_eq!(
+            entries,
+            vec![r#""C:\Program Files\x;y""#, r"C:\bin", "", r"C:\tools"]
+        );
+    }
+    
+    #[test]
+    #[cfg(windows)]
+    fn test_deduplicate_path_with_quoted_entries() {
+        // The quoted entry survives intact and its repeat is dropped
+        let path = r#""C:\Program Files\x;y";C:\bin;"C:\Program Files\x;y""#;
+        let deduped = deduplicate_path(path);
+        assert_eq!(deduped, r#""C:\Program Files\x;y";C:\bin"#);
+        
+        // Unquoted, the same text splits into two separate entries
+        let path = r#""C:\Program Files\x;y";C:\bin;C:\Program Files\x;y;C:\bin"#;
+        let deduped = deduplicate_path(path);
+        assert_eq!(deduped, r#""C:\Program Files\x;y";C:\bin;C:\Program Files\x;y"#);
+    }
+    
+    #[test]
+    fn test_deduplicate_path_keep_last() {
+        // Keep-first and keep-last differ only in which occurrence survives
+        assert_eq!(deduplicate_path("/a:/b:/a:/c"), "/a:/b:/c");
+        assert_eq!(deduplicate_path_keep_last("/a:/b:/a:/c"), "/b:/a:/c");
+        
+        // Normalized duplicates and empty entries are handled the same way
+        assert_eq!(deduplicate_path_keep_last("/a/::/b:/a"), "/b:/a");
+        assert_eq!(deduplicate_path_keep_last(""), "");
+        assert_eq!(deduplicate_path_keep_last("/a:/b:/c"), "/a:/b:/c");
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_deduplicate_path_symlink_policy() {
+        let temp_dir = TempDir::new().unwrap();
+        let real = temp_dir.path().join("real");
+        let link = temp_dir.path().join("link");
+        fs::create_dir(&real).unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let (real, link) = (real.to_str().unwrap(), link.to_str().unwrap());
+        let path = format!("{}:{}:{}/", real, link, real);
+        
+        assert_eq!(deduplicate_path_with(&path, SymlinkPolicy::Collapse), real);
+        assert_eq!(
+            deduplicate_path_with(&path, SymlinkPolicy::Keep),
+            format!("{}:{}", real, link)
+        );
+        // The root directory is an entry like any other
+        assert_eq!(deduplicate_path_with("/:/usr/bin://", SymlinkPolicy::Keep), "/:/usr/bin");
+        assert_eq!(SymlinkPolicy::default(), SymlinkPolicy::Keep);
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_deduplicate_path_canonical() {
+        // The literal variant keeps trailing slashes, the canonical one trims them
+        let path = "/usr/local/bin/:/opt/bin//:/usr/local/bin";
+        assert_eq!(deduplicate_path(path), "/usr/local/bin/:/opt/bin//");
+        assert_eq!(deduplicate_path_canonical(path), "/usr/local/bin:/opt/bin");
+        
+        // Empty entries are dropped as before
+        assert_eq!(deduplicate_path_canonical("/a/::/a"), "/a");
+        assert_eq!(deduplicate_path_canonical(""), "");
+    }
+    
+    #[test]
+    #[serial]
+    fn test_update_process_path_concurrently() {
+        let original = env::var("PATH").unwrap_or_default();
+        env::set_var("PATH", "/usr/bin");
+        
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let dir = format!("/concurrent/bin{}", i);
+                    // Every thread adds its own dir plus one shared dir
+                    update_process_path(|current| format!("{}:/shared/bin:{}", dir, current));
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        
+        let result = env::var("PATH").unwrap();
+        env::set_var("PATH", original);
+        
+        let entries: Vec<&str> = result.split(':').collect();
+        for i in 0..8 {
+            let dir = format!("/concurrent/bin{}", i);
+            assert_eq!(entries.iter().filter(|e| **e == dir).count(), 1);
+        }
+        assert_eq!(entries.iter().filter(|e| **e == "/shared/bin").count(), 1);
+        assert_eq!(entries.iter().filter(|e| **e == "/usr/bin").count(), 1);
+        assert_eq!(entries.len(), 10);
+    }
+    
+    #[test]
+    #[serial]
+    fn test_set_process_path_deduplicated() {
+        let original = env::var("PATH").unwrap_or_default();
+        set_process_path_deduplicated("/usr/bin:/opt/bin:/usr/bin/");
+        let result = env::var("PATH").unwrap();
+        env::set_var("PATH", original);
+        
+        assert_eq!(result, "/usr/bin:/opt/bin");
+    }
+    
+    #[test]
+    #[serial]
+    fn test_with_temp_path_restores_after_panic() {
+        let original = env::var("PATH").unwrap_or_default();
+        
+        let seen = with_temp_path("/temp/bin", || env::var("PATH").unwrap());
+        assert_eq!(seen, "/temp/bin");
+        assert_eq!(env::var("PATH").unwrap_or_default(), original);
+        
+        let result = std::panic::catch_unwind(|| {
+            with_temp_path("/temp/bin", || panic!("boom"));
+        });
+        assert!(result.is_err());
+        assert_eq!(env::var("PATH").unwrap_or_default(), original);
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_sanitize_binary_path() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path().join("home");
+        let bin_dir = home.join("my tools");
+        fs::create_dir_all(&bin_dir).unwrap();
+        let claude = bin_dir.join("claude");
+        fs::write(&claude, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+        let expected = fs::canonicalize(&claude).unwrap();
+        
+        let home_var = EnvVarGuard::set("HOME", &home);
+        let quoted_tilde = sanitize_binary_path("  \"~/my tools/claude\"  ");
+        drop(home_var);
+        assert_eq!(quoted_tilde, Ok(expected.clone()));
+        
+        let url = format!("file://{}", claude.display()).replace(' ', "%20");
+        assert_eq!(sanitize_binary_path(&url), Ok(expected.clone()));
+        assert_eq!(
+            sanitize_binary_path(&format!("{}\t\n", claude.display())),
+            Ok(expected.clone())
+        );
+        
+        let missing = temp_dir.path().join("nope").join("claude");
+        assert_eq!(
+            sanitize_binary_path(&missing.to_string_lossy()),
+            Err(PathError::Missing(missing))
+        );
+        assert!(matches!(
+            sanitize_binary_path(&bin_dir.to_string_lossy()),
+            Err(PathError::NotAFile(_))
+        ));
+        assert_eq!(sanitize_binary_path(" '' "), Err(PathError::Empty));
+    }
+    
+    #[test]
+    #[cfg(windows)]
+    fn test_strip_extended_length_prefix() {
+        assert_eq!(strip_extended_length_prefix(r"\\?\C:\tools"), r"C:\tools");
+        assert_eq!(
+            strip_extended_length_prefix(r"\\?\UNC\server\share\bin"),
+            r"\\server\share\bin"
+        );
+        assert_eq!(strip_extended_length_prefix(r"C:\tools"), r"C:\tools");
+    }
+    
+    #[test]
+    #[cfg(windows)]
+    fn test_extended_length_paths_dedup_together() {
+        let temp_dir = TempDir::new().unwrap();
+        let plain = temp_dir.path().to_str().unwrap().to_string();
+        let extended = format!(r"\\?\{}", plain);
+        
+        assert_eq!(normalize_path(&plain), normalize_path(&extended));
+        
+        // The literal spelling that came first is kept in the output
+        let deduped = deduplicate_path(&format!("{};{};C:\\other", plain, extended));
+        assert_eq!(deduped, format!("{};C:\\other", plain));
+    }
+}
@@ -1,22 +1,63 @@
 use claudia_lib::claude_binary::*;
+use claudia_lib::path_utils::EntryOrigin;
+use serial_test::serial;
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
 use tempfile::TempDir;
 
+/// Sets an environment variable for the rest of a test; the old value is restored on drop,
+/// even when the test panics
+struct EnvVarGuard {
+    key: &'static str,
+    original: Option<OsString>,
+}
+
+impl EnvVarGuard {
+    fn set(key: &'static str, value: impl AsRef<OsStr>) -> Self {
+        let guard = EnvVarGuard {
+            key,
+            original: env::var_os(key),
+        };
+        env::set_var(key, value);
+        guard
+    }
+
+    fn remove(key: &'static str) -> Self {
+        let guard = EnvVarGuard {
+            key,
+            original: env::var_os(key),
+        };
+        env::remove_var(key);
+        guard
+    }
+}
+
+impl Drop for EnvVarGuard {
+    fn drop(&mut self) {
+        match &self.original {
+            Some(value) => env::set_var(self.key, value),
+            None => env::remove_var(self.key),
+        }
+    }
+}
+
 #[test]
+#[serial]
 fn test_create_command_with_env() {
     // Save original PATH
     let original_path = env::var("PATH").unwrap_or_default();
     
     // Test 1: Normal binary path
-    let cmd = create_command_with_env("/usr/bin/claude");
+    let _cmd = create_command_with_env("/usr/bin/claude");
     // The command should have PATH set
     // Note: We can't easily inspect Command's env vars, but we can verify it doesn't panic
     
     // Test 2: NVM directory path
     let nvm_path = "/home/user/.nvm/versions/node/v18.0.0/bin/claude";
-    let cmd = create_command_with_env(nvm_path);
+    let _cmd = create_command_with_env(nvm_path);
     // Should add the NVM bin directory to PATH
     
     // Restore original PATH
@@ -24,6 +65,7 @@ fn test_create_command_with_env() {
 }
 
 #[test]
+#[serial]
 fn test_create_command_with_nvm_path() {
     let original_path = env::var("PATH").unwrap_or_default();
     
@@ -38,20 +80,61 @@ fn test_create_command_with_nvm_path() {
     // The PATH should now include the NVM bin directory
     // Since we can't inspect the Command's env directly, we verify through the path_utils
     let expected_dir = "/Users/test/.nvm/versions/node/v20.0.0/bin";
-    let new_path = crate::path_utils::add_to_path_if_missing(expected_dir);
+    let new_path = claudia_lib::path_utils::add_to_path_if_missing(expected_dir);
     assert!(new_path.contains(expected_dir));
     
     env::set_var("PATH", original_path);
 }
 
+/// Runs a tiny shell command through `spec`'s environment and returns the PATH the child saw
+/// Proves what actually reaches the subprocess, which a `Command` doesn't let us inspect.
+fn spawn_echo_path(spec: &CommandSpec) -> String {
+    let mut probe = spec.clone();
+    if cfg!(windows) {
+        probe.program = "cmd".to_string();
+        probe.args = vec!["/c".into(), "echo %PATH%".into()];
+    } else {
+        probe.program = "sh".to_string();
+        probe.args = vec!["-c".into(), "echo $PATH".into()];
+    }
+    let output = probe.to_command().output().expect("failed to spawn the PATH probe");
+    assert!(output.status.success(), "PATH probe failed: {:?}", output);
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_nvm_bin_dir_reaches_child_path() {
+    let temp_dir = TempDir::new().unwrap();
+    let nvm_bin = temp_dir.path().join(".nvm/versions/node/v20.0.0/bin");
+    fs::create_dir_all(&nvm_bin).unwrap();
+    let nvm_claude = nvm_bin.join("claude");
+    fs::write(&nvm_claude, "#!/bin/sh\n").unwrap();
+    let original_path = env::var("PATH").unwrap_or_default();
+    env::set_var("PATH", "/usr/bin:/usr/local/bin");
+    
+    let spec = create_command_spec(&nvm_claude.to_string_lossy());
+    let child_path = spawn_echo_path(&spec);
+    
+    env::set_var("PATH", original_path);
+    
+    let nvm_bin = nvm_bin.to_string_lossy().to_string();
+    assert_eq!(child_path.split(':').next(), Some(nvm_bin.as_str()));
+    assert!(child_path.split(':').any(|entry| entry == "/usr/local/bin"));
+    // The parent's PATH was never touched
+    assert!(!env::var("PATH").unwrap_or_default().contains(&nvm_bin));
+}
+
 #[test]
+#[serial]
 fn test_create_command_env_inheritance() {
     // Set some test environment variables
     env::set_var("TEST_HOME", "/test/home");
     env::set_var("TEST_LANG", "en_US.UTF-8");
     env::set_var("TEST_RANDOM", "should_not_inherit");
     
-    let cmd = create_command_with_env("/usr/bin/claude");
+    let _cmd = create_command_with_env("/usr/bin/claude");
     
     // Clean up
     env::remove_var("TEST_HOME");
@@ -63,6 +146,522 @@ fn test_create_command_env_inheritance() {
 }
 
 #[test]
+fn test_create_command_with_env_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    
+    let missing = temp_dir.path().join("missing").join("claude");
+    let err = create_command_with_env_validated(missing.to_str().unwrap()).unwrap_err();
+    assert_eq!(
+        err,
+        BinaryNotRunnable { path: missing.clone(), reason: NotRunnableReason::Missing }
+    );
+    assert!(err.to_string().contains("does not exist"));
+    
+    let err = create_command_with_env_validated(temp_dir.path().to_str().unwrap()).unwrap_err();
+    assert_eq!(err.reason, NotRunnableReason::NotAFile);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_create_command_with_env_validated_checks_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+    
+    let temp_dir = TempDir::new().unwrap();
+    let claude = temp_dir.path().join("claude");
+    fs::write(&claude, "#!/bin/sh\necho '1.0.0 (Claude Code)'").unwrap();
+    
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o644)).unwrap();
+    let err = create_command_with_env_validated(claude.to_str().unwrap()).unwrap_err();
+    assert_eq!(err.reason, NotRunnableReason::NotExecutable);
+    
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+    let cmd = create_command_with_env_validated(claude.to_str().unwrap()).unwrap();
+    assert_eq!(cmd.get_program(), claude.as_os_str());
+}
+
+#[test]
+#[serial]
+fn test_effective_path_for_nvm_binary() {
+    let original_path = env::var("PATH").unwrap_or_default();
+    let nvm_claude = "/Users/test/.nvm/versions/node/v20.0.0/bin/claude";
+    let nvm_bin = "/Users/test/.nvm/versions/node/v20.0.0/bin";
+    
+    // Missing from PATH: added in front, inherited duplicates collapsed; whichever common
+    // locations exist on this machine and are missing go between the two
+    env::set_var("PATH", "/usr/bin:/usr/local/bin:/usr/bin");
+    let spec = create_command_spec(nvm_claude);
+    assert!(spec.effective_path().starts_with(&format!("{}:", nvm_bin)));
+    assert!(spec.effective_path().ends_with(":/usr/bin:/usr/local/bin"));
+    assert_eq!(spec.effective_path().matches("/usr/local/bin").count(), 1);
+    
+    // Already present (twice): kept once, in its original position
+    env::set_var("PATH", format!("/usr/bin:{}:/opt/bin:{}", nvm_bin, nvm_bin));
+    let spec = create_command_spec(nvm_claude);
+    assert!(spec
+        .effective_path()
+        .ends_with(&format!("/usr/bin:{}:/opt/bin", nvm_bin)));
+    assert_eq!(spec.effective_path().matches(nvm_bin).count(), 1);
+    
+    // Each entry records why it's there
+    env::set_var("PATH", "/usr/bin:/usr/local/bin");
+    let spec = create_command_spec(nvm_claude);
+    let origins = &spec.path_origins;
+    assert_eq!(origins[0], (nvm_bin.to_string(), EntryOrigin::NvmResolution));
+    assert_eq!(
+        origins[origins.len() - 2..],
+        [
+            ("/usr/bin".to_string(), EntryOrigin::Inherited),
+            ("/usr/local/bin".to_string(), EntryOrigin::Inherited),
+        ]
+    );
+    assert!(origins[1..origins.len() - 2]
+        .iter()
+        .all(|(_, origin)| *origin == EntryOrigin::CommonLocation));
+    
+    env::set_var("PATH", original_path);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_ensure_claude_path_puts_common_locations_ahead_of_inherited() {
+    let temp_dir = TempDir::new().unwrap();
+    let inherited = temp_dir.path().to_string_lossy().to_string();
+    let path = ensure_claude_path(std::path::Path::new("claude"), &inherited);
+    
+    let entries: Vec<&str> = path.split(':').collect();
+    assert_eq!(entries.last(), Some(&inherited.as_str()));
+    for location in ["/opt/homebrew/bin", "/usr/local/bin"] {
+        if std::path::Path::new(location).is_dir() {
+            assert!(entries.contains(&location));
+        }
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_ensure_claude_path_for_nvm_shim() {
+    use std::collections::HashSet;
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let node_dir = temp_dir.path().join(".nvm/versions/node/v20.11.0/bin");
+    let bin_dir = temp_dir.path().join("npm-global").join("bin");
+    fs::create_dir_all(&node_dir).unwrap();
+    fs::create_dir_all(&bin_dir).unwrap();
+    let node = node_dir.join("node");
+    fs::write(&node, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&node, fs::Permissions::from_mode(0o755)).unwrap();
+    let claude = bin_dir.join("claude");
+    fs::write(&claude, format!("#!{}\nconsole.log('hi')\n", node.display())).unwrap();
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let current = format!("/usr/bin::{}:/usr/bin/:", node_dir.display());
+    let path = ensure_claude_path(&claude, &current);
+    
+    let entries: Vec<&str> = path.split(':').collect();
+    assert_eq!(entries[0], bin_dir.to_string_lossy());
+    assert!(path.ends_with(&format!(":/usr/bin:{}", node_dir.display())));
+    assert!(entries.iter().all(|entry| !entry.is_empty()));
+    let unique: HashSet<String> = entries
+        .iter()
+        .map(|entry| fs::canonicalize(entry).unwrap().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(unique.len(), entries.len());
+    assert_eq!(ensure_claude_path(&claude, &path), path);
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_command_spec_path_is_child_only() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let node_dir = temp_dir.path().join("node").join("bin");
+    let bin_dir = temp_dir.path().join("claude").join("bin");
+    fs::create_dir_all(&node_dir).unwrap();
+    fs::create_dir_all(&bin_dir).unwrap();
+    let node = node_dir.join("node");
+    fs::write(&node, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&node, fs::Permissions::from_mode(0o755)).unwrap();
+    let claude = bin_dir.join("claude");
+    fs::write(&claude, format!("#!{}\nconsole.log('hi')\n", node.display())).unwrap();
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let original_path = env::var("PATH").ok();
+    env::set_var("PATH", "/usr/bin:/usr/local/bin");
+    let parent_path = env::var("PATH").unwrap();
+
+    let spec = create_command_spec(&claude.to_string_lossy());
+    let built = ClaudeCommandBuilder::new(&claude.to_string_lossy()).build().unwrap();
+    let after = env::var("PATH").unwrap();
+
+    match original_path {
+        Some(path) => env::set_var("PATH", path),
+        None => env::remove_var("PATH"),
+    }
+
+    // The parent keeps its PATH; only the child sees the additions
+    assert_eq!(after, parent_path);
+    let expected = format!(
+        "{}:{}:/usr/bin:/usr/local/bin",
+        bin_dir.display(),
+        node_dir.display()
+    );
+    assert_eq!(spec.effective_path(), expected);
+    assert_eq!(built.effective_path(), expected);
+}
+
+#[test]
+#[serial]
+fn test_binary_dir_on_path() {
+    let nvm_claude = std::path::Path::new("/Users/test/.nvm/versions/node/v20.0.0/bin/claude");
+    
+    assert!(binary_dir_on_path(
+        nvm_claude,
+        "/usr/bin:/Users/test/.nvm/versions/node/v20.0.0/bin/"
+    ));
+    assert!(!binary_dir_on_path(nvm_claude, "/usr/bin:/usr/local/bin"));
+    assert!(!binary_dir_on_path(std::path::Path::new("claude"), "/usr/bin"));
+    
+    // Absent from the inherited PATH, present once enhancement has run
+    let original_path = env::var("PATH").ok();
+    env::set_var("PATH", "/usr/bin:/usr/local/bin");
+    let spec = create_command_spec(&nvm_claude.to_string_lossy());
+    let inherited = env::var("PATH").unwrap();
+    match original_path {
+        Some(path) => env::set_var("PATH", path),
+        None => env::remove_var("PATH"),
+    }
+    
+    assert!(!binary_dir_on_path(nvm_claude, &inherited));
+    assert!(binary_dir_on_path(nvm_claude, spec.effective_path()));
+}
+
+#[test]
+#[serial]
+fn test_preview_path_enhancement_for_nvm_binary() {
+    let original_path = env::var("PATH").ok();
+    env::set_var("PATH", "/usr/bin:/usr/local/bin:/usr/bin");
+    let diff = preview_path_enhancement("/Users/test/.nvm/versions/node/v20.0.0/bin/claude");
+    let after = env::var("PATH").unwrap();
+    match original_path {
+        Some(path) => env::set_var("PATH", path),
+        None => env::remove_var("PATH"),
+    }
+    
+    assert_eq!(after, "/usr/bin:/usr/local/bin:/usr/bin");
+    assert_eq!(diff.added[0], "/Users/test/.nvm/versions/node/v20.0.0/bin");
+    assert!(diff.removed.is_empty());
+    assert_eq!(diff.before, after);
+    assert!(diff.after.starts_with("/Users/test/.nvm/versions/node/v20.0.0/bin:"));
+    assert!(diff.after.ends_with(":/usr/bin:/usr/local/bin"));
+}
+
+#[test]
+#[serial]
+fn test_no_path_enhance_passes_inherited_path_through() {
+    let original_path = env::var("PATH").ok();
+    let nvm_claude = "/Users/test/.nvm/versions/node/v20.0.0/bin/claude";
+    
+    env::set_var("PATH", "/usr/bin::/usr/local/bin:/usr/bin:");
+    env::set_var("CLAUDIA_NO_PATH_ENHANCE", "1");
+    let spec = create_command_spec(nvm_claude);
+    env::remove_var("CLAUDIA_NO_PATH_ENHANCE");
+    let enhanced = create_command_spec(nvm_claude);
+    
+    match original_path {
+        Some(path) => env::set_var("PATH", path),
+        None => env::remove_var("PATH"),
+    }
+    
+    assert_eq!(spec.effective_path(), "/usr/bin:/usr/local/bin");
+    assert!(spec.path_origins.iter().all(|(_, origin)| *origin == EntryOrigin::Inherited));
+    assert!(enhanced.effective_path().starts_with("/Users/test/.nvm/versions/node/v20.0.0/bin:"));
+}
+
+#[test]
+fn test_write_env_dump_redacts_secrets() {
+    let temp_dir = TempDir::new().unwrap();
+    let dump = temp_dir.path().join("claudia.env");
+    let mut spec = CommandSpec::new("/usr/local/bin/claude");
+    spec.env.insert("PATH".to_string(), "/usr/local/bin:/usr/bin".to_string());
+    spec.env.insert("ANTHROPIC_API_KEY".to_string(), "sk-ant-secret".to_string());
+    spec.env.insert("LANG".to_string(), "en_US.UTF-8".to_string());
+    
+    spec.write_env_dump(&dump).unwrap();
+    
+    let contents = fs::read_to_string(&dump).unwrap();
+    assert_eq!(
+        contents,
+        format!(
+            "ANTHROPIC_API_KEY={}\nLANG=en_US.UTF-8\nPATH=/usr/local/bin:/usr/bin\n",
+            REDACTED_VALUE
+        )
+    );
+    assert!(!contents.contains("sk-ant-secret"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_reproduction_snippet() {
+    let mut spec = CommandSpec::new("/usr/local/bin/claude").args(["-p", "hello world"]);
+    spec.current_dir = Some(PathBuf::from("/tmp/my project"));
+    spec.env.insert("LANG".to_string(), "en_US.UTF-8".to_string());
+    spec.env.insert("ANTHROPIC_API_KEY".to_string(), "sk-ant-secret".to_string());
+    
+    let snippet = spec.reproduction_snippet();
+    
+    assert_eq!(
+        snippet,
+        format!(
+            "export ANTHROPIC_API_KEY='{}'\n\
+             export LANG=en_US.UTF-8\n\
+             cd '/tmp/my project'\n\
+             /usr/local/bin/claude -p 'hello world'",
+            REDACTED_VALUE
+        )
+    );
+    assert!(snippet.ends_with(&spec.to_shell_string()));
+    assert!(!snippet.contains("sk-ant-secret"));
+    
+    // A cleared environment only has the spec's variables, so they go on `env -i`
+    spec.clear_env = true;
+    assert_eq!(
+        spec.reproduction_snippet(),
+        format!(
+            "cd '/tmp/my project'\n\
+             env -i ANTHROPIC_API_KEY='{}' LANG=en_US.UTF-8 /usr/local/bin/claude -p 'hello world'",
+            REDACTED_VALUE
+        )
+    );
+}
+
+#[test]
+#[serial]
+fn test_builder_config_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let inherited = temp_dir.path().join("default-profile");
+    let work = temp_dir.path().join("profiles").join("work");
+    let original = env::var("CLAUDE_CONFIG_DIR").ok();
+    env::set_var("CLAUDE_CONFIG_DIR", &inherited);
+    
+    let spec = ClaudeCommandBuilder::new("/usr/bin/claude")
+        .config_dir(&work)
+        .build()
+        .unwrap();
+    let default_spec = ClaudeCommandBuilder::new("/usr/bin/claude").build().unwrap();
+    
+    match original {
+        Some(value) => env::set_var("CLAUDE_CONFIG_DIR", value),
+        None => env::remove_var("CLAUDE_CONFIG_DIR"),
+    }
+    
+    // The explicit directory wins over the inherited one and is created on build
+    assert_eq!(
+        spec.env.get("CLAUDE_CONFIG_DIR"),
+        Some(&work.to_string_lossy().to_string())
+    );
+    assert!(work.is_dir());
+    
+    // Without it, the inherited value passes through untouched
+    assert_eq!(
+        default_spec.env.get("CLAUDE_CONFIG_DIR"),
+        Some(&inherited.to_string_lossy().to_string())
+    );
+    assert!(!inherited.exists());
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_probe_config_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let profile = temp_dir.path().join("profile");
+    fs::create_dir_all(&profile).unwrap();
+    
+    let config_var = EnvVarGuard::set("CLAUDE_CONFIG_DIR", &profile);
+    let explicit = probe_config_dir();
+    drop(config_var);
+    let _no_config = EnvVarGuard::remove("CLAUDE_CONFIG_DIR");
+    let _home = EnvVarGuard::set("HOME", temp_dir.path());
+    let default_missing = probe_config_dir();
+    fs::create_dir_all(temp_dir.path().join(".claude")).unwrap();
+    let default_present = probe_config_dir();
+    
+    assert_eq!(explicit.config_dir, Some(profile));
+    assert!(explicit.exists);
+    let default_dir = temp_dir.path().join(".claude");
+    assert_eq!(default_missing.config_dir, Some(default_dir.clone()));
+    assert!(!default_missing.exists);
+    assert_eq!(default_present.config_dir, Some(default_dir));
+    assert!(default_present.exists);
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_builder_minimal_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let claude_bin = temp_dir.path().join("claude").join("bin");
+    let node_bin = temp_dir.path().join("node").join("bin");
+    let unrelated = temp_dir.path().join("random-tools");
+    for dir in [&claude_bin, &node_bin, &unrelated] {
+        fs::create_dir_all(dir).unwrap();
+    }
+    let node = node_bin.join("node");
+    fs::write(&node, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&node, fs::Permissions::from_mode(0o755)).unwrap();
+    let claude = claude_bin.join("claude");
+    fs::write(&claude, format!("#!{}\n", node.display())).unwrap();
+    
+    let original_path = env::var("PATH").unwrap_or_default();
+    env::set_var("PATH", format!("{}:{}", unrelated.display(), original_path));
+    let spec = ClaudeCommandBuilder::new(&claude.to_string_lossy())
+        .minimal_path(&claude)
+        .build()
+        .unwrap();
+    env::set_var("PATH", original_path);
+    
+    let entries: Vec<&str> = spec.effective_path().split(':').collect();
+    let (claude_bin, node_bin) = (claude_bin.to_string_lossy(), node_bin.to_string_lossy());
+    assert_eq!(&entries[..3], &[&*claude_bin, &*node_bin, "/usr/bin"]);
+    // `/bin` may be folded into `/usr/bin` where one links to the other
+    assert!(entries[3..].iter().all(|entry| *entry == "/bin"));
+    assert!(!spec.effective_path().contains(&*unrelated.to_string_lossy()));
+    assert_eq!(spec.path_origins[2].1, EntryOrigin::CommonLocation);
+}
+
+#[test]
+#[serial]
+fn test_builder_node_max_old_space() {
+    let original = env::var("NODE_OPTIONS").ok();
+    env::set_var("NODE_OPTIONS", "--enable-source-maps");
+    
+    let spec = ClaudeCommandBuilder::new("/usr/bin/claude")
+        .node_max_old_space_mb(8192)
+        .build()
+        .unwrap();
+    let default_spec = ClaudeCommandBuilder::new("/usr/bin/claude").build().unwrap();
+    
+    match original {
+        Some(value) => env::set_var("NODE_OPTIONS", value),
+        None => env::remove_var("NODE_OPTIONS"),
+    }
+    
+    // The memory flag is merged after the inherited options rather than replacing them
+    assert_eq!(
+        spec.env.get("NODE_OPTIONS").map(String::as_str),
+        Some("--enable-source-maps --max-old-space-size=8192")
+    );
+    // Without it, the child just inherits NODE_OPTIONS from the parent
+    assert!(!default_spec.env.contains_key("NODE_OPTIONS"));
+}
+
+#[test]
+fn test_env_policy_apply() {
+    use std::collections::HashMap;
+    
+    let parent: HashMap<String, String> = [
+        ("PATH", "/usr/bin"),
+        ("HOME", "/home/me"),
+        ("CLAUDE_CONFIG_DIR", "/home/me/.claude"),
+        ("CLAUDE_API_TOKEN", "secret"),
+        ("AWS_SECRET_ACCESS_KEY", "secret"),
+        ("EDITOR", "vim"),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect();
+    
+    let policy = EnvPolicy {
+        always: vec!["PATH".to_string(), "HOME".to_string(), "CLAUDE_API_TOKEN".to_string()],
+        never: vec!["CLAUDE_API_TOKEN".to_string(), "HOME".to_string()],
+        glob_allow: vec!["CLAUDE_*".to_string()],
+    };
+    let applied = policy.apply(&parent);
+    
+    let mut keys: Vec<&str> = applied.keys().map(String::as_str).collect();
+    keys.sort();
+    // `never` beats both `always` and the glob
+    assert_eq!(keys, vec!["CLAUDE_CONFIG_DIR", "PATH"]);
+    assert_eq!(applied["CLAUDE_CONFIG_DIR"], "/home/me/.claude");
+    
+    assert!(EnvPolicy::default().apply(&parent).is_empty());
+}
+
+#[test]
+#[serial]
+fn test_builder_env_policy_clears_parent_env() {
+    env::set_var("CLAUDIA_TEST_SECRET", "secret");
+    env::set_var("CLAUDE_TEST_FLAG", "1");
+    let spec = ClaudeCommandBuilder::new("/usr/bin/claude")
+        .env_policy(EnvPolicy {
+            always: vec!["PATH".to_string()],
+            glob_allow: vec!["CLAUDE_*".to_string()],
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    env::remove_var("CLAUDIA_TEST_SECRET");
+    env::remove_var("CLAUDE_TEST_FLAG");
+    
+    assert!(spec.clear_env);
+    assert_eq!(spec.env.get("CLAUDE_TEST_FLAG").map(String::as_str), Some("1"));
+    assert!(spec.env.contains_key("PATH"));
+    assert!(!spec.env.contains_key("CLAUDIA_TEST_SECRET"));
+    assert!(!spec.env.contains_key("HOME"));
+}
+
+#[test]
+fn test_validate_args_policy() {
+    use std::ffi::OsString;
+    
+    let args = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+    let policy = ArgPolicy {
+        denied: vec!["--dangerously-skip-permissions".to_string()],
+        allowed: None,
+    };
+    
+    let benign = args(&["--model", "opus", "-p", "summarize the repo"]);
+    assert!(validate_args(&benign, &policy).is_ok());
+    assert_eq!(
+        validate_args(
+            &args(&["--model", "opus", "--dangerously-skip-permissions", "--verbose"]),
+            &policy
+        ),
+        Err(DisallowedArg {
+            arg: "--dangerously-skip-permissions".to_string()
+        })
+    );
+    // The flag is matched by name, with or without a value
+    assert!(validate_args(&args(&["--dangerously-skip-permissions=true"]), &policy).is_err());
+    // After `--` it's just text
+    assert!(validate_args(&args(&["-p", "--", "--dangerously-skip-permissions"]), &policy).is_ok());
+    
+    let allowlist = ArgPolicy {
+        allowed: Some(vec!["--model".to_string(), "--output-format".to_string()]),
+        ..policy
+    };
+    let allowed = args(&["--model=sonnet", "--output-format", "json"]);
+    assert!(validate_args(&allowed, &allowlist).is_ok());
+    assert!(validate_args(&args(&["--model", "opus", "--resume"]), &allowlist).is_err());
+    
+    // The builder checks caller args only, not the launcher prefix
+    let install = ClaudeInstallation {
+        path: "/home/me/.local/bin/claude".to_string(),
+        install_type: InstallationType::Wsl,
+        ..Default::default()
+    };
+    let builder = ClaudeCommandBuilder::for_installation(&install).args(["--model", "opus"]);
+    assert!(builder.validate(&allowlist).is_ok());
+    assert!(builder.arg("--resume").validate(&allowlist).is_err());
+}
+
+#[test]
+#[serial]
 fn test_path_modification_idempotence() {
     let original_path = env::var("PATH").unwrap_or_default();
     
@@ -82,7 +681,7 @@ fn test_path_modification_idempotence() {
     
     // Verify using path_utils that the directory won't be added twice
     let path_with_nvm = format!("{}:/usr/bin:/usr/local/bin", expected_dir);
-    let result = crate::path_utils::add_to_path_if_missing(expected_dir);
+    let result = claudia_lib::path_utils::add_to_path_if_missing(expected_dir);
     assert_eq!(result, path_with_nvm);
     
     env::set_var("PATH", original_path);
@@ -90,8 +689,10 @@ fn test_path_modification_idempotence() {
 
 #[cfg(test)]
 mod version_tests {
-    use super::*;
-    use claudia_lib::claude_binary::ClaudeVersion;
+    use claudia_lib::claude_binary::{
+        best_installation, filter_by_requirement, is_valid_version_str, Channel,
+        ClaudeInstallation, ClaudeVersion, VersionReq,
+    };
     
     #[test]
     fn test_claude_version_parsing() {
@@ -111,21 +712,209 @@ mod version_tests {
         assert!(ClaudeVersion::from_str("claude version: invalid").is_none());
     }
     
+    #[test]
+    fn test_is_valid_version_str() {
+        let inputs = [
+            "1.2.3", "v2", "1.2.3-beta", "1.2.3.4+build.5", "abc", "", "1..2", "1.2.3.4.5",
+        ];
+        for input in inputs {
+            assert_eq!(
+                is_valid_version_str(input),
+                ClaudeVersion::parse(input).is_some(),
+                "validator and parser disagree on {:?}",
+                input
+            );
+        }
+        
+        assert!(is_valid_version_str("1.2.3"));
+        assert!(is_valid_version_str("v2"));
+        assert!(is_valid_version_str("1.2.3-beta"));
+        assert!(!is_valid_version_str("abc"));
+        assert!(!is_valid_version_str(""));
+        assert!(!is_valid_version_str("1..2"));
+    }
+    
     #[test]
     fn test_claude_version_comparison() {
-        let v1 = ClaudeVersion { major: 1, minor: 0, patch: 0 };
-        let v2 = ClaudeVersion { major: 1, minor: 0, patch: 1 };
-        let v3 = ClaudeVersion { major: 1, minor: 1, patch: 0 };
-        let v4 = ClaudeVersion { major: 2, minor: 0, patch: 0 };
+        let v1 = ClaudeVersion { major: 1, minor: 0, patch: 0, revision: None, prerelease: None };
+        let v2 = ClaudeVersion { major: 1, minor: 0, patch: 1, revision: None, prerelease: None };
+        let v3 = ClaudeVersion { major: 1, minor: 1, patch: 0, revision: None, prerelease: None };
+        let v4 = ClaudeVersion { major: 2, minor: 0, patch: 0, revision: None, prerelease: None };
         
         assert!(v1 < v2);
         assert!(v2 < v3);
         assert!(v3 < v4);
         assert!(v1 < v4);
         
-        let v5 = ClaudeVersion { major: 1, minor: 0, patch: 0 };
+        let v5 = ClaudeVersion { major: 1, minor: 0, patch: 0, revision: None, prerelease: None };
         assert_eq!(v1, v5);
     }
+    
+    #[test]
+    fn test_claude_version_four_components() {
+        let v = ClaudeVersion::from_str("claude version: 1.2.3.456").unwrap();
+        assert_eq!((v.major, v.minor, v.patch), (1, 2, 3));
+        assert_eq!(v.revision, Some(456));
+        assert_eq!(v.to_string(), "1.2.3.456");
+        
+        // Five components is not a version we understand
+        assert!(ClaudeVersion::parse("1.2.3.4.5").is_none());
+    }
+    
+    #[test]
+    fn test_claude_version_date_based() {
+        let v = ClaudeVersion::from_str("2024.6.1").unwrap();
+        assert_eq!((v.major, v.minor, v.patch), (2024, 6, 1));
+        assert_eq!(v.revision, None);
+        
+        // Zero-padded date components parse the same way
+        assert_eq!(ClaudeVersion::parse("2024.06.01"), Some(v));
+    }
+    
+    #[test]
+    fn test_claude_version_revision_ordering() {
+        let without = ClaudeVersion::parse("1.2.3").unwrap();
+        let with = ClaudeVersion::parse("1.2.3.1").unwrap();
+        assert!(without < with);
+        assert!(with < ClaudeVersion::parse("1.2.4").unwrap());
+        
+        // Non-numeric garbage is still rejected
+        assert!(ClaudeVersion::parse("1.2.x").is_none());
+        assert!(ClaudeVersion::parse("1.2.3.abc").is_none());
+    }
+    
+    #[test]
+    fn test_version_req_matching() {
+        let v = |s: &str| ClaudeVersion::parse(s).unwrap();
+        
+        let range = VersionReq::parse(">=1.2, <2").unwrap();
+        assert!(v("1.2.0").satisfies(&range));
+        assert!(v("1.9.9").satisfies(&range));
+        assert!(!v("1.1.9").satisfies(&range));
+        assert!(!v("2.0.0").satisfies(&range));
+        
+        let caret = VersionReq::parse("^1.2").unwrap();
+        assert!(v("1.5.0").satisfies(&caret));
+        assert!(!v("2.0.0").satisfies(&caret));
+        let tilde = VersionReq::parse("~1.2").unwrap();
+        assert!(v("1.2.7").satisfies(&tilde));
+        assert!(!v("1.3.0").satisfies(&tilde));
+        
+        // A bare version is a caret requirement
+        assert_eq!(VersionReq::parse("1.2"), Some(caret.clone()));
+        assert_eq!(caret.to_string(), "^1.2");
+        assert_eq!(range.to_string(), ">=1.2, <2");
+        
+        assert!(VersionReq::parse("").is_none());
+        assert!(VersionReq::parse(">=1.x").is_none());
+        assert!(VersionReq::parse(">=1.2,").is_none());
+    }
+    
+    #[test]
+    fn test_filter_by_requirement() {
+        let install = |path: &str, version: Option<&str>| ClaudeInstallation {
+            path: path.to_string(),
+            version: version.and_then(ClaudeVersion::parse),
+            ..Default::default()
+        };
+        let installs = vec![
+            install("/old/claude", Some("1.1.0")),
+            install("/new/claude", Some("1.4.2")),
+            install("/unknown/claude", None),
+            install("/exact/claude", Some("1.2.0")),
+        ];
+        
+        let req = VersionReq::parse(">=1.2.0").unwrap();
+        let paths: Vec<String> = filter_by_requirement(&installs, &req)
+            .into_iter()
+            .map(|install| install.path)
+            .collect();
+        assert_eq!(paths, vec!["/new/claude", "/exact/claude"]);
+    }
+    
+    #[test]
+    fn test_claude_version_from_json() {
+        let blob = r#"{ "version": "1.2.3", "commit": "abc123", "channel": "stable" }"#;
+        assert_eq!(ClaudeVersion::from_json(blob), ClaudeVersion::parse("1.2.3"));
+        
+        // Missing or non-string version field
+        assert_eq!(ClaudeVersion::from_json(r#"{ "commit": "abc123" }"#), None);
+        assert_eq!(ClaudeVersion::from_json(r#"{ "version": 1 }"#), None);
+        
+        // Text output isn't JSON, so callers fall back to text parsing
+        assert_eq!(ClaudeVersion::from_json("1.0.44 (Claude Code)"), None);
+        assert_eq!(ClaudeVersion::from_json(r#"{ "version": "1.2"#), None);
+    }
+    
+    #[test]
+    fn test_claude_version_from_multiline_banner_noise() {
+        let output = "Welcome to Claude Code!\nRun with --help for usage\n\n1.0.44 (Claude Code)\n";
+        assert_eq!(ClaudeVersion::from_multiline(output), ClaudeVersion::parse("1.0.44"));
+        assert_eq!(ClaudeVersion::from_multiline("no version here\n"), None);
+    }
+    
+    #[test]
+    fn test_claude_version_from_multiline_skips_update_notice() {
+        let output = "A new version (1.9.0) is available\n1.0.44 (Claude Code)\n";
+        assert_eq!(ClaudeVersion::from_multiline(output), ClaudeVersion::parse("1.0.44"));
+        
+        // The notice may share the version's line
+        let output = "1.0.3 (Claude Code) - update available\n";
+        assert_eq!(ClaudeVersion::from_multiline(output), ClaudeVersion::parse("1.0.3"));
+        let output = "Update available: 1.9.0\n";
+        assert_eq!(ClaudeVersion::from_multiline(output), None);
+    }
+    
+    #[test]
+    fn test_claude_version_from_multiline_prefers_labeled_line() {
+        let output = "build 3.1.4 (node 20.11.0)\nA new version (1.9.0) is available\nclaude version: 1.0.44\n";
+        assert_eq!(ClaudeVersion::from_multiline(output), ClaudeVersion::parse("1.0.44"));
+    }
+    
+    #[test]
+    fn test_prerelease_versions() {
+        let beta = ClaudeVersion::parse("2.0.0-beta.2").unwrap();
+        assert!(beta.is_prerelease());
+        assert_eq!(beta.prerelease.as_deref(), Some("beta.2"));
+        assert_eq!(beta.to_string(), "2.0.0-beta.2");
+        assert_eq!(ClaudeVersion::parse("2.0.0+build.5").unwrap().to_string(), "2.0.0");
+        assert_eq!(ClaudeVersion::parse("2.0.0-beta..1"), None);
+        
+        // A pre-release sorts below its release but above earlier releases
+        let ordered = ["1.9.0", "2.0.0-alpha", "2.0.0-alpha.1", "2.0.0-beta", "2.0.0-beta.2",
+            "2.0.0-beta.11", "2.0.0-rc.1", "2.0.0"];
+        for pair in ordered.windows(2) {
+            let (lower, higher) = (ClaudeVersion::parse(pair[0]), ClaudeVersion::parse(pair[1]));
+            assert!(lower < higher, "{} should sort below {}", pair[0], pair[1]);
+        }
+    }
+    
+    #[test]
+    fn test_best_installation_by_channel() {
+        let install = |path: &str, version: &str| ClaudeInstallation {
+            path: path.to_string(),
+            version: ClaudeVersion::parse(version),
+            ..Default::default()
+        };
+        let installs = vec![
+            install("/stable/old", "1.8.2"),
+            install("/beta/claude", "2.0.0-beta.1"),
+            install("/stable/claude", "1.9.0"),
+            install("/beta/newer", "2.1.0-alpha"),
+        ];
+        let best = |channel| best_installation(installs.clone(), channel).map(|i| i.path);
+        
+        assert_eq!(best(Channel::Stable).as_deref(), Some("/stable/claude"));
+        assert_eq!(best(Channel::Prerelease).as_deref(), Some("/beta/newer"));
+        assert_eq!(best(Channel::Any).as_deref(), Some("/beta/newer"));
+        
+        // With no stable version available, the stable channel falls back to pre-releases
+        let prereleases_only = installs[1..2].to_vec();
+        assert_eq!(
+            best_installation(prereleases_only, Channel::Stable).map(|i| i.path).as_deref(),
+            Some("/beta/claude")
+        );
+    }
 }
 
 #[cfg(test)]
@@ -135,6 +924,7 @@ mod installation_tests {
     
     #[test]
     #[cfg(unix)]
+    #[serial]
     fn test_discover_claude_installations() {
         // Create a temporary directory structure
         let temp_dir = TempDir::new().unwrap();
@@ -176,6 +966,130 @@ mod installation_tests {
         }
     }
     
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_discover_filtered_to_nvm() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path().join("home");
+        let prefix = temp_dir.path().join("brew-prefix");
+        let brew_dir = temp_dir.path().join("brew-bin");
+        
+        // One NVM install; a Homebrew one under a prefix the fake `brew` reports, a
+        // system-style one on PATH and a user-local one must all be filtered out
+        let mut planted = vec![(brew_dir.join("brew"), format!("echo '{}'", prefix.display()))];
+        for path in [
+            home.join(".nvm/versions/node/v20.1.0/bin/claude"),
+            home.join(".local/bin/claude"),
+            prefix.join("bin/claude"),
+            brew_dir.join("claude"),
+        ] {
+            planted.push((path, "echo 'claude version: 1.0.0'".to_string()));
+        }
+        for (path, body) in &planted {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("#!/bin/sh\n{}", body)).unwrap();
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        
+        let _home = EnvVarGuard::set("HOME", &home);
+        let _path = EnvVarGuard::set("PATH", &brew_dir);
+        let installations = discover_claude_installations_filtered(&[InstallationType::Nvm]);
+        let homebrew = discover_claude_installations_filtered(&[InstallationType::Homebrew]);
+        
+        // The Homebrew mock is discoverable, just not as an nvm install
+        assert!(homebrew
+            .iter()
+            .any(|i| i.path == prefix.join("bin/claude").to_string_lossy()));
+        assert!(!installations
+            .iter()
+            .any(|i| i.path.starts_with(&*prefix.to_string_lossy())
+                || i.path.starts_with(&*brew_dir.to_string_lossy())));
+        assert_eq!(installations.len(), 1);
+        assert_eq!(installations[0].install_type, InstallationType::Nvm);
+        assert!(installations[0].path.contains("v20.1.0"));
+        assert!(installations
+            .iter()
+            .all(|i| i.install_type == InstallationType::Nvm));
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_discover_standalone_binaries() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths = [".local/bin/claude", "bin/claude", ".cargo/bin/claude"];
+        for path in paths {
+            let full_path = temp_dir.path().join(path);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(&full_path, "#!/bin/sh\necho '1.0.95 (Claude Code)'").unwrap();
+            fs::set_permissions(&full_path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        
+        let home = EnvVarGuard::set("HOME", temp_dir.path());
+        let installations = discover_claude_installations_filtered(&[InstallationType::Standalone]);
+        // They used to be `Direct`; asking for that alone no longer returns them
+        let direct = discover_claude_installations_filtered(&[InstallationType::Direct]);
+        drop(home);
+        
+        assert!(!direct
+            .iter()
+            .any(|install| install.path.starts_with(&*temp_dir.path().to_string_lossy())));
+        assert_eq!(installations.len(), paths.len());
+        for path in paths {
+            let expected = temp_dir.path().join(path).to_string_lossy().to_string();
+            let found = installations
+                .iter()
+                .find(|install| install.path == expected)
+                .unwrap_or_else(|| panic!("{} not discovered", expected));
+            assert_eq!(found.install_type, InstallationType::Standalone);
+            assert_eq!(found.version, ClaudeVersion::parse("1.0.95"));
+        }
+    }
+    
+    #[test]
+    #[cfg(unix)]
+    #[serial]
+    fn test_homebrew_prefix_from_brew() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix = temp_dir.path().join("brew-prefix");
+        let brew_dir = temp_dir.path().join("brew-bin");
+        let home = temp_dir.path().join("home");
+        fs::create_dir_all(&home).unwrap();
+        
+        let brew = brew_dir.join("brew");
+        let claude = prefix.join("bin").join("claude");
+        for (path, script) in [
+            (&brew, format!("#!/bin/sh\necho '{}'", prefix.display())),
+            (&claude, "#!/bin/sh\necho '1.0.70 (Claude Code)'".to_string()),
+        ] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, script).unwrap();
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        
+        let saved = (env::var("HOME").ok(), env::var("PATH").ok());
+        env::set_var("HOME", &home);
+        env::set_var("PATH", &brew_dir);
+        let prefixes = homebrew_prefixes();
+        let installations = discover_claude_installations_filtered(&[InstallationType::Homebrew]);
+        for (key, value) in [("HOME", saved.0), ("PATH", saved.1)] {
+            match value {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+        
+        assert_eq!(prefixes, vec![prefix.clone()]);
+        let found = installations
+            .iter()
+            .find(|i| i.path == claude.to_string_lossy())
+            .expect("claude under the brew prefix should be discovered");
+        assert_eq!(found.install_type, InstallationType::Homebrew);
+        assert_eq!(found.source, "homebrew");
+        assert_eq!(found.version, ClaudeVersion::parse("1.0.70"));
+    }
+    
     #[test]
     fn test_claude_installation_info() {
         let temp_dir = TempDir::new().unwrap();
@@ -194,8 +1108,15 @@ mod installation_tests {
         // Test getting installation info
         let info = ClaudeInstallation {
             path: claude_path.to_string_lossy().to_string(),
-            version: Some(ClaudeVersion { major: 1, minor: 2, patch: 3 }),
+            version: Some(ClaudeVersion {
+                major: 1,
+                minor: 2,
+                patch: 3,
+                revision: None,
+                prerelease: None,
+            }),
             install_type: InstallationType::Direct,
+            ..Default::default()
         };
         
         assert_eq!(info.path, claude_path.to_string_lossy().to_string());
@@ -205,6 +1126,7 @@ mod installation_tests {
 }
 
 #[test]
+#[serial]
 fn test_path_deduplication_in_create_command() {
     let original_path = env::var("PATH").unwrap_or_default();
     
@@ -215,8 +1137,2016 @@ fn test_path_deduplication_in_create_command() {
     let _cmd = create_command_with_env("/opt/bin/claude");
     
     // Verify through path_utils that duplicates are handled
-    let deduped = crate::path_utils::deduplicate_path("/usr/bin:/usr/local/bin:/usr/bin:/opt/bin");
+    let deduped = claudia_lib::path_utils::deduplicate_path("/usr/bin:/usr/local/bin:/usr/bin:/opt/bin");
     assert_eq!(deduped, "/usr/bin:/usr/local/bin:/opt/bin");
     
     env::set_var("PATH", original_path);
-}
\ No newline at end of file
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_first_usable_claude_skips_broken_shim() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let original_path = env::var("PATH").unwrap_or_default();
+    let temp_dir = TempDir::new().unwrap();
+
+    // A shim that exits with an error comes first on PATH, a working binary second
+    let scripts = [
+        ("broken", "#!/bin/sh\nexit 1"),
+        ("working", "#!/bin/sh\necho 'claude version: 1.4.2'"),
+    ];
+    for (dir, script) in &scripts {
+        let bin = temp_dir.path().join(dir).join("claude");
+        fs::create_dir_all(bin.parent().unwrap()).unwrap();
+        fs::write(&bin, script).unwrap();
+        let mut perms = fs::metadata(&bin).unwrap().permissions();
+        perms.set_mode(0o755);
+        fs::set_permissions(&bin, perms).unwrap();
+    }
+
+    env::set_var(
+        "PATH",
+        format!(
+            "{}:{}:/usr/bin:/bin",
+            temp_dir.path().join("broken").display(),
+            temp_dir.path().join("working").display()
+        ),
+    );
+
+    let found = first_usable_claude(Duration::from_secs(5));
+
+    env::set_var("PATH", original_path);
+
+    let found = found.expect("working claude should be found");
+    assert_eq!(
+        PathBuf::from(&found.path),
+        temp_dir.path().join("working").join("claude")
+    );
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_discovered_binary_reports_metadata() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let original_path = env::var("PATH").unwrap_or_default();
+    let temp_dir = TempDir::new().unwrap();
+    let script = "#!/bin/sh\necho 'claude version: 2.0.1'\n";
+    let bin = temp_dir.path().join("claude");
+    fs::write(&bin, script).unwrap();
+    fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+
+    env::set_var("PATH", temp_dir.path());
+    let found = first_usable_claude(Duration::from_secs(5));
+    env::set_var("PATH", original_path);
+
+    let found = found.expect("mock claude should be found");
+    assert_eq!(found.size_bytes, Some(script.len() as u64));
+    let age = found.modified.unwrap().elapsed().unwrap_or_default();
+    assert!(age < Duration::from_secs(60));
+
+    // A path that can't be stat'ed reports no metadata rather than panicking
+    let mut missing = ClaudeInstallation {
+        path: "/nonexistent/claude".to_string(),
+        ..Default::default()
+    };
+    missing.refresh_metadata();
+    assert!(missing.modified.is_none());
+    assert!(missing.size_bytes.is_none());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_symlinked_installation_reports_target() {
+    let temp_dir = TempDir::new().unwrap();
+    let cellar = temp_dir.path().join("Cellar").join("claude").join("1.0.0").join("claude");
+    fs::create_dir_all(cellar.parent().unwrap()).unwrap();
+    fs::write(&cellar, "#!/bin/sh\necho '1.0.0 (Claude Code)'").unwrap();
+    let link = temp_dir.path().join("bin").join("claude");
+    fs::create_dir_all(link.parent().unwrap()).unwrap();
+    std::os::unix::fs::symlink("../Cellar/claude/1.0.0/claude", &link).unwrap();
+
+    let mut installation = ClaudeInstallation {
+        path: link.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    installation.refresh_metadata();
+    assert!(installation.is_symlink);
+    assert_eq!(installation.resolved_target, Some(fs::canonicalize(&cellar).unwrap()));
+    // The literal discovered path is kept
+    assert_eq!(installation.path, link.to_string_lossy());
+
+    let mut direct = ClaudeInstallation {
+        path: cellar.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    direct.refresh_metadata();
+    assert!(!direct.is_symlink);
+    assert_eq!(direct.resolved_target, None);
+}
+
+#[test]
+fn test_command_exists() {
+    let temp_dir = TempDir::new().unwrap();
+    let name = if cfg!(windows) { "claude.cmd" } else { "claude" };
+    let path = temp_dir.path().join(name);
+    fs::write(&path, "#!/bin/sh\necho '1.0.0 (Claude Code)'").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let installation = ClaudeInstallation {
+        path: path.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    assert!(installation.command_exists());
+
+    fs::remove_file(&path).unwrap();
+    assert!(!installation.command_exists());
+
+    let dir = ClaudeInstallation {
+        path: temp_dir.path().to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    assert!(!dir.command_exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_command_exists_requires_execute_bit() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("claude");
+    fs::write(&path, "#!/bin/sh\necho '1.0.0 (Claude Code)'").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+    let installation = ClaudeInstallation {
+        path: path.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    assert!(!installation.command_exists());
+}
+
+#[test]
+fn test_installation_display_summary() {
+    let mut installation = ClaudeInstallation {
+        path: "/Users/me/.nvm/versions/node/v20.11.0/bin/claude".to_string(),
+        version: ClaudeVersion::parse("1.2.3"),
+        source: "nvm-active".to_string(),
+        install_type: InstallationType::Nvm,
+        ..Default::default()
+    };
+    assert_eq!(
+        installation.to_string(),
+        "claude 1.2.3 (nvm) at /Users/me/.nvm/versions/node/v20.11.0/bin/claude"
+    );
+
+    installation.version = None;
+    installation.install_type = InstallationType::Homebrew;
+    installation.path = "/opt/homebrew/bin/claude".to_string();
+    assert_eq!(
+        installation.to_string(),
+        "claude unknown (homebrew) at /opt/homebrew/bin/claude"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_installation_stable_id() {
+    let temp_dir = TempDir::new().unwrap();
+    let bin = temp_dir.path().join("bin").join("claude");
+    fs::create_dir_all(bin.parent().unwrap()).unwrap();
+    fs::write(&bin, "#!/bin/sh\n").unwrap();
+    let link = temp_dir.path().join("claude-link");
+    std::os::unix::fs::symlink(&bin, &link).unwrap();
+    
+    let first = ClaudeInstallation {
+        path: bin.to_string_lossy().to_string(),
+        modified: Some(std::time::SystemTime::UNIX_EPOCH),
+        size_bytes: Some(10),
+        ..Default::default()
+    };
+    let mut rediscovered = ClaudeInstallation {
+        path: bin.to_string_lossy().to_string(),
+        modified: Some(std::time::SystemTime::now()),
+        size_bytes: Some(20),
+        ..Default::default()
+    };
+    rediscovered.set_label(Some("work account".to_string()));
+    let id = first.stable_id();
+    assert_eq!(id.len(), 16);
+    assert_eq!(rediscovered.stable_id(), id);
+    
+    // A symlink to the same binary is the same installation
+    let via_link = ClaudeInstallation {
+        path: link.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    assert_eq!(via_link.stable_id(), id);
+    
+    let elsewhere = ClaudeInstallation {
+        path: temp_dir.path().join("other").join("claude").to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    assert_ne!(elsewhere.stable_id(), id);
+    
+    // Flatpak installs differ by launcher as well
+    let flatpak = |launcher: &str| ClaudeInstallation {
+        path: "com.anthropic.ClaudeCode".to_string(),
+        install_type: InstallationType::Flatpak,
+        invocation: Some(vec![launcher.to_string(), "run".to_string()]),
+        ..Default::default()
+    };
+    assert_ne!(flatpak("flatpak").stable_id(), flatpak("/opt/flatpak").stable_id());
+    
+    // So do WSL installs at the same path in different distributions
+    let wsl = |distro: &str| ClaudeInstallation {
+        path: "/home/me/.local/bin/claude".to_string(),
+        install_type: InstallationType::Wsl,
+        wsl_distro: Some(distro.to_string()),
+        ..Default::default()
+    };
+    assert_ne!(wsl("Ubuntu").stable_id(), wsl("Debian").stable_id());
+}
+
+#[test]
+fn test_diff_installations() {
+    let install = |path: &str, version: &str| ClaudeInstallation {
+        path: path.to_string(),
+        version: ClaudeVersion::parse(version),
+        source: "system".to_string(),
+        ..Default::default()
+    };
+    let old = vec![
+        install("/usr/local/bin/claude", "1.0.30"),
+        install("/opt/homebrew/bin/claude", "1.0.40"),
+        install("/home/me/.local/bin/claude", "1.0.50"),
+    ];
+    let mut relabeled = old[2].clone();
+    relabeled.set_label(Some("beta testing".to_string()));
+    let new = vec![
+        install("/opt/homebrew/bin/claude", "1.0.41"),
+        relabeled,
+        install("/home/me/.nvm/versions/node/v20.11.0/bin/claude", "1.0.60"),
+    ];
+
+    let diff = diff_installations(&old, &new);
+    let paths = |installs: &[ClaudeInstallation]| {
+        installs.iter().map(|install| install.path.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(
+        paths(&diff.added),
+        vec!["/home/me/.nvm/versions/node/v20.11.0/bin/claude"]
+    );
+    assert_eq!(paths(&diff.removed), vec!["/usr/local/bin/claude"]);
+    // A label alone is not a change
+    assert_eq!(paths(&diff.changed), vec!["/opt/homebrew/bin/claude"]);
+    assert_eq!(diff.changed[0].version, ClaudeVersion::parse("1.0.41"));
+
+    assert!(diff_installations(&new, &new).is_empty());
+}
+
+#[test]
+fn test_installation_labels() {
+    let mut work = ClaudeInstallation {
+        path: "/opt/homebrew/bin/claude".to_string(),
+        version: ClaudeVersion::parse("1.2.3"),
+        source: "homebrew".to_string(),
+        install_type: InstallationType::Homebrew,
+        ..Default::default()
+    };
+    work.set_label(Some("  work account ".to_string()));
+    assert_eq!(work.label(), Some("work account"));
+    assert_eq!(
+        work.to_string(),
+        "claude 1.2.3 (homebrew) at /opt/homebrew/bin/claude [work account]"
+    );
+
+    // The label survives a serde round trip; unlabeled installs don't serialize one
+    let json = serde_json::to_string(&work).unwrap();
+    let restored: ClaudeInstallation = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.label(), Some("work account"));
+    let unlabeled = ClaudeInstallation {
+        path: "/usr/local/bin/claude".to_string(),
+        ..Default::default()
+    };
+    assert!(!serde_json::to_string(&unlabeled).unwrap().contains("label"));
+
+    let installs = vec![unlabeled, restored];
+    assert_eq!(
+        find_by_label(&installs, "work account").map(|install| install.path.as_str()),
+        Some("/opt/homebrew/bin/claude")
+    );
+    assert!(find_by_label(&installs, "beta testing").is_none());
+
+    let mut cleared = installs[1].clone();
+    cleared.set_label(Some("   ".to_string()));
+    assert_eq!(cleared.label(), None);
+}
+
+#[cfg(test)]
+mod version_gate_tests {
+    use claudia_lib::claude_binary::*;
+
+    fn installation(version: Option<&str>) -> ClaudeInstallation {
+        ClaudeInstallation {
+            path: "/usr/local/bin/claude".to_string(),
+            version: version.and_then(ClaudeVersion::parse),
+            source: "system".to_string(),
+            install_type: InstallationType::System,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_create_command_checked_below_minimum() {
+        let min = ClaudeVersion::parse("1.2.0");
+        let result = create_command_checked(&installation(Some("1.1.9")), min);
+        assert_eq!(
+            result.unwrap_err(),
+            SpawnRefusal::VersionTooOld {
+                found: ClaudeVersion::parse("1.1.9").unwrap(),
+                required: ClaudeVersion::parse("1.2.0").unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_create_command_checked_at_or_above_minimum() {
+        let min = ClaudeVersion::parse("1.2.0");
+
+        let spec = create_command_checked(&installation(Some("1.2.0")), min.clone()).unwrap();
+        assert_eq!(spec.program, "/usr/local/bin/claude");
+
+        assert!(create_command_checked(&installation(Some("1.3.0")), min).is_ok());
+    }
+
+    #[test]
+    fn test_create_command_checked_unknown_version() {
+        let min = ClaudeVersion::parse("1.2.0");
+        assert_eq!(
+            create_command_checked(&installation(None), min).unwrap_err(),
+            SpawnRefusal::UnknownVersion
+        );
+
+        // Without a minimum, an unknown version is fine
+        assert!(create_command_checked(&installation(None), None).is_ok());
+    }
+}
+
+#[cfg(all(test, unix))]
+mod resolve_tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn write_mock_claude(dir: &std::path::Path, version: &str) -> PathBuf {
+        let bin = dir.join("claude");
+        fs::create_dir_all(dir).unwrap();
+        fs::write(&bin, format!("#!/bin/sh\necho '{} (Claude Code)'", version)).unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+        bin
+    }
+
+    /// Points HOME and PATH at empty temp dirs so only the mocks are discovered, until the
+    /// returned guards are dropped
+    fn isolate_env(temp_dir: &TempDir) -> [EnvVarGuard; 2] {
+        let empty = temp_dir.path().join("empty");
+        fs::create_dir_all(&empty).unwrap();
+        [EnvVarGuard::set("HOME", &empty), EnvVarGuard::set("PATH", &empty)]
+    }
+
+    #[test]
+    #[serial]
+    fn test_resolve_claude_honors_preference() {
+        let temp_dir = TempDir::new().unwrap();
+        let preferred = write_mock_claude(&temp_dir.path().join("pinned"), "1.0.0");
+        let extra = temp_dir.path().join("extra");
+        write_mock_claude(&extra, "999.0.0");
+
+        let isolated = isolate_env(&temp_dir);
+        let outcome = resolve_claude(ResolveOptions {
+            preferred_path: Some(preferred.to_string_lossy().to_string()),
+            extra_dirs: vec![extra],
+            ..Default::default()
+        });
+        drop(isolated);
+
+        let selected = outcome.selected.unwrap();
+        assert_eq!(PathBuf::from(&selected.path), preferred);
+        assert_eq!(selected.version, ClaudeVersion::parse("1.0.0"));
+        assert!(outcome.warnings.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_resolve_claude_reads_pinned_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let pinned = write_mock_claude(&temp_dir.path().join("pinned"), "1.0.0");
+        let extra = temp_dir.path().join("extra");
+        write_mock_claude(&extra, "999.0.0");
+        let config = temp_dir.path().join("settings.json");
+        fs::write(
+            &config,
+            serde_json::json!({ "claude_path": pinned.to_string_lossy() }).to_string(),
+        )
+        .unwrap();
+
+        let isolated = isolate_env(&temp_dir);
+        let outcome = resolve_claude(ResolveOptions {
+            pinned_config: Some(config),
+            extra_dirs: vec![extra],
+            ..Default::default()
+        });
+        drop(isolated);
+
+        assert_eq!(PathBuf::from(outcome.selected.unwrap().path), pinned);
+    }
+
+    #[test]
+    #[serial]
+    fn test_resolve_claude_invalid_preference_falls_back() {
+        let temp_dir = TempDir::new().unwrap();
+        let extra = temp_dir.path().join("extra");
+        let fallback = write_mock_claude(&extra, "999.0.0");
+
+        let isolated = isolate_env(&temp_dir);
+        let outcome = resolve_claude(ResolveOptions {
+            preferred_path: Some("/nonexistent/claude".to_string()),
+            extra_dirs: vec![extra],
+            ..Default::default()
+        });
+        drop(isolated);
+
+        assert_eq!(PathBuf::from(&outcome.selected.unwrap().path), fallback);
+        assert_eq!(outcome.warnings.len(), 1);
+        assert!(outcome.warnings[0].contains("no longer exists"));
+        assert!(!outcome.candidates.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_resolve_claude_nothing_found() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let isolated = isolate_env(&temp_dir);
+        let outcome = resolve_claude(ResolveOptions {
+            extra_dirs: vec![temp_dir.path().join("empty")],
+            min_version: ClaudeVersion::parse("999.0.0"),
+            ..Default::default()
+        });
+        drop(isolated);
+
+        assert!(outcome.selected.is_none());
+        assert!(outcome
+            .warnings
+            .iter()
+            .any(|w| w.contains("No usable Claude installation")));
+    }
+
+    /// One mock claude per version, each in its own directory for `extra_dirs`
+    fn versioned_mocks(temp_dir: &TempDir, versions: &[&str]) -> Vec<PathBuf> {
+        versions
+            .iter()
+            .map(|version| {
+                let dir = temp_dir.path().join(format!("v{}", version));
+                write_mock_claude(&dir, version);
+                dir
+            })
+            .collect()
+    }
+
+    #[test]
+    #[serial]
+    fn test_resolve_claude_version_requirement() {
+        let temp_dir = TempDir::new().unwrap();
+        let extra_dirs = versioned_mocks(&temp_dir, &["1.1.0", "1.4.0", "2.0.0"]);
+
+        let isolated = isolate_env(&temp_dir);
+        let outcome = resolve_claude(ResolveOptions {
+            extra_dirs,
+            version_requirement: VersionReq::parse(">=1.2, <2"),
+            ..Default::default()
+        });
+        drop(isolated);
+
+        let selected = outcome.selected.unwrap();
+        assert_eq!(selected.version, ClaudeVersion::parse("1.4.0"));
+        assert!(outcome.unsatisfied.is_none());
+        assert!(outcome.warnings.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_resolve_claude_version_requirement_skips_preferred() {
+        let temp_dir = TempDir::new().unwrap();
+        let extra_dirs = versioned_mocks(&temp_dir, &["1.1.0", "1.4.0", "2.0.0"]);
+        let preferred = extra_dirs[2].join("claude");
+
+        let isolated = isolate_env(&temp_dir);
+        let outcome = resolve_claude(ResolveOptions {
+            preferred_path: Some(preferred.to_string_lossy().to_string()),
+            extra_dirs,
+            version_requirement: VersionReq::parse("~1.1"),
+            ..Default::default()
+        });
+        drop(isolated);
+
+        assert_eq!(outcome.selected.unwrap().version, ClaudeVersion::parse("1.1.0"));
+        assert_eq!(
+            outcome.warnings,
+            vec![format!(
+                "Ignored preferred path {}: version 2.0.0 does not satisfy ~1.1",
+                preferred.display()
+            )]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_resolve_claude_version_requirement_unsatisfiable() {
+        let temp_dir = TempDir::new().unwrap();
+        let extra_dirs = versioned_mocks(&temp_dir, &["1.1.0", "2.0.0"]);
+
+        let isolated = isolate_env(&temp_dir);
+        let outcome = resolve_claude(ResolveOptions {
+            extra_dirs,
+            version_requirement: VersionReq::parse(">=3"),
+            ..Default::default()
+        });
+        drop(isolated);
+
+        assert!(outcome.selected.is_none());
+        let error = outcome.unsatisfied.unwrap();
+        // System-wide installations may show up as well
+        for version in ["2.0.0", "1.1.0"] {
+            assert!(error.available.contains(&ClaudeVersion::parse(version).unwrap()));
+        }
+        assert!(error.available.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(error
+            .to_string()
+            .starts_with("No Claude installation satisfies >=3 (available: "));
+    }
+
+    #[test]
+    #[serial]
+    fn test_require_claude_lists_search_locations() {
+        let temp_dir = TempDir::new().unwrap();
+        let isolated = isolate_env(&temp_dir);
+        let home = temp_dir.path().join("home");
+        env::set_var("HOME", &home);
+
+        // A claude that isn't executable is found but rejected
+        let broken = home.join(".local").join("bin").join("claude");
+        fs::create_dir_all(broken.parent().unwrap()).unwrap();
+        fs::write(&broken, "not a program").unwrap();
+
+        // System locations are out of the test's control, so only look at user installs
+        let result = require_claude_filtered(&[
+            InstallationType::Direct,
+            InstallationType::Nvm,
+            InstallationType::Standalone,
+        ]);
+        drop(isolated);
+
+        let error = result.unwrap_err();
+        for expected in [
+            temp_dir.path().join("empty"),
+            home.join(".nvm").join("versions").join("node"),
+            home.join(".local").join("bin"),
+            home.join(".claude").join("local"),
+            home.join(".bun").join("bin"),
+        ] {
+            assert!(
+                error.searched.contains(&expected),
+                "{:?} missing from {:?}",
+                expected,
+                error.searched
+            );
+        }
+        assert!(!error.searched.contains(&PathBuf::from("/usr/local/bin")));
+        assert_eq!(
+            error.rejected,
+            vec![RejectedCandidate {
+                path: broken.to_string_lossy().to_string(),
+                reason: "not executable".to_string(),
+            }]
+        );
+        assert!(error.to_string().starts_with("No Claude Code installation found"));
+    }
+
+    /// Mock claude that records every execution in `<bin>.runs`
+    fn write_counting_mock(bin: &std::path::Path, version: &str) {
+        fs::create_dir_all(bin.parent().unwrap()).unwrap();
+        fs::write(
+            bin,
+            format!(
+                "#!/bin/sh\necho run >> '{}.runs'\necho '{} (Claude Code)'",
+                bin.display(),
+                version
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(bin, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn run_count(bin: &std::path::Path) -> usize {
+        fs::read_to_string(format!("{}.runs", bin.display()))
+            .map(|runs| runs.lines().count())
+            .unwrap_or(0)
+    }
+
+    #[test]
+    #[serial]
+    fn test_cached_discovery_reprobes_only_changed_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let isolated = isolate_env(&temp_dir);
+        let home = temp_dir.path().join("home");
+        env::set_var("HOME", &home);
+
+        let local_bin = home.join(".local").join("bin").join("claude");
+        let bun_bin = home.join(".bun").join("bin").join("claude");
+        write_counting_mock(&local_bin, "1.0.60");
+        write_counting_mock(&bun_bin, "1.0.61");
+
+        let ttl = Duration::from_secs(3600);
+        clear_discovery_cache();
+        let first = discover_claude_installations_cached(ttl);
+        assert_eq!((run_count(&local_bin), run_count(&bun_bin)), (1, 1));
+
+        // Nothing changed: everything comes from the cache
+        let second = discover_claude_installations_cached(ttl);
+        assert_eq!((run_count(&local_bin), run_count(&bun_bin)), (1, 1));
+        assert_eq!(first.len(), second.len());
+
+        // Upgrade one binary in place and bump its mtime
+        write_counting_mock(&bun_bin, "1.0.62");
+        fs::File::options()
+            .write(true)
+            .open(&bun_bin)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+
+        let third = discover_claude_installations_cached(ttl);
+        clear_discovery_cache();
+        drop(isolated);
+
+        assert_eq!((run_count(&local_bin), run_count(&bun_bin)), (1, 2));
+        let bun = third
+            .iter()
+            .find(|install| install.path == bun_bin.to_string_lossy())
+            .unwrap();
+        assert_eq!(bun.version, ClaudeVersion::parse("1.0.62"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_discovery_stream_yields_every_installation() {
+        use futures::StreamExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let isolated = isolate_env(&temp_dir);
+        let home = temp_dir.path().join("home");
+        env::set_var("HOME", &home);
+
+        let mocks = [
+            (home.join(".local").join("bin").join("claude"), "1.0.60"),
+            (home.join(".bun").join("bin").join("claude"), "1.0.61"),
+            (
+                home.join(".nvm/versions/node/v20.11.0/bin").join("claude"),
+                "1.0.62",
+            ),
+        ];
+        for (bin, version) in &mocks {
+            write_counting_mock(bin, version);
+        }
+
+        let streamed: Vec<ClaudeInstallation> =
+            discover_claude_installations_stream().collect().await;
+        drop(isolated);
+
+        for (bin, version) in &mocks {
+            let found = streamed
+                .iter()
+                .find(|install| install.path == bin.to_string_lossy())
+                .unwrap_or_else(|| panic!("{} missing from the stream", bin.display()));
+            assert_eq!(found.version, ClaudeVersion::parse(version));
+            assert_eq!(run_count(bin), 1);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_cached_discovery_invalidated_by_path_change() {
+        let temp_dir = TempDir::new().unwrap();
+        let isolated = isolate_env(&temp_dir);
+        let home = temp_dir.path().join("home");
+        env::set_var("HOME", &home);
+
+        let local_bin = home.join(".local").join("bin").join("claude");
+        let path_bin = temp_dir.path().join("tools").join("claude");
+        write_counting_mock(&local_bin, "1.0.60");
+        write_counting_mock(&path_bin, "1.0.70");
+
+        let ttl = Duration::from_secs(3600);
+        clear_discovery_cache();
+        let first = discover_claude_installations_cached(ttl);
+        assert_eq!(run_count(&local_bin), 1);
+
+        // Well within the TTL, but PATH now leads to another claude
+        env::set_var("PATH", path_bin.parent().unwrap());
+        let second = discover_claude_installations_cached(ttl);
+        clear_discovery_cache();
+        drop(isolated);
+
+        assert_eq!((run_count(&local_bin), run_count(&path_bin)), (2, 1));
+        assert!(!first.iter().any(|install| install.version == ClaudeVersion::parse("1.0.70")));
+        assert!(second
+            .iter()
+            .any(|install| install.version == ClaudeVersion::parse("1.0.70")));
+    }
+
+    #[test]
+    #[serial]
+    fn test_cached_discovery_invalidated_by_search_paths_change() {
+        let temp_dir = TempDir::new().unwrap();
+        let isolated = isolate_env(&temp_dir);
+        let _search_paths = EnvVarGuard::remove(SEARCH_PATHS_VAR);
+
+        let extra = temp_dir.path().join("extra").join("claude");
+        write_counting_mock(&extra, "1.0.80");
+
+        let ttl = Duration::from_secs(3600);
+        clear_discovery_cache();
+        let first = discover_claude_installations_cached(ttl);
+
+        // Well within the TTL, but the extra search directories now include another claude
+        let _extra = EnvVarGuard::set(SEARCH_PATHS_VAR, extra.parent().unwrap());
+        let second = discover_claude_installations_cached(ttl);
+        clear_discovery_cache();
+        drop(isolated);
+
+        assert_eq!(run_count(&extra), 1);
+        assert!(!first.iter().any(|install| install.version == ClaudeVersion::parse("1.0.80")));
+        assert!(second
+            .iter()
+            .any(|install| install.version == ClaudeVersion::parse("1.0.80")));
+    }
+}
+
+#[test]
+#[cfg(windows)]
+#[serial]
+fn test_windows_executable_candidates_follow_pathext() {
+    let temp_dir = TempDir::new().unwrap();
+    for name in ["claude.cmd", "claude.exe", "claude.bat"] {
+        fs::write(temp_dir.path().join(name), "").unwrap();
+    }
+
+    let original_pathext = env::var("PATHEXT").ok();
+    env::set_var("PATHEXT", ".EXE;.CMD");
+    let candidates = windows_executable_candidates(temp_dir.path(), "claude");
+
+    // Unset PATHEXT falls back to the Windows defaults, which include .BAT
+    env::remove_var("PATHEXT");
+    let defaults = windows_executable_candidates(temp_dir.path(), "claude");
+
+    match original_pathext {
+        Some(value) => env::set_var("PATHEXT", value),
+        None => env::remove_var("PATHEXT"),
+    }
+
+    assert_eq!(
+        candidates,
+        vec![
+            temp_dir.path().join("claude.exe"),
+            temp_dir.path().join("claude.cmd"),
+        ]
+    );
+    assert_eq!(defaults.len(), 3);
+}
+
+#[cfg(all(test, unix))]
+mod shadowing_tests {
+    use super::*;
+
+    /// Runs `detect_shell_shadowing` with HOME pointed at a temp dir holding `zshrc`
+    fn detect_with_zshrc(zshrc: Option<&str>, binary: &std::path::Path) -> Option<ShadowWarning> {
+        let home = TempDir::new().unwrap();
+        if let Some(contents) = zshrc {
+            fs::write(home.path().join(".zshrc"), contents).unwrap();
+        }
+
+        let _home = EnvVarGuard::set("HOME", home.path());
+        detect_shell_shadowing(binary)
+    }
+
+    #[test]
+    #[serial]
+    fn test_matching_alias_is_not_shadowing() {
+        let binary = std::path::Path::new("/opt/homebrew/bin/claude");
+        let rc = "export PATH=/usr/bin\nalias claude='/opt/homebrew/bin/claude --verbose'\n";
+        assert!(detect_with_zshrc(Some(rc), binary).is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn test_alias_reinvoking_claude_is_not_shadowing() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let binary = std::path::Path::new("/opt/homebrew/bin/claude");
+        for rc in [
+            "alias claude='claude --dangerously-skip-permissions'\n",
+            "alias claude=\"command claude --verbose\"\n",
+        ] {
+            assert!(detect_with_zshrc(Some(rc), binary).is_none(), "{}", rc);
+        }
+
+        // Other bare names are looked up on PATH
+        let temp_dir = TempDir::new().unwrap();
+        let wrapper = temp_dir.path().join("claude-wrapper");
+        fs::write(&wrapper, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+        let _path = EnvVarGuard::set("PATH", temp_dir.path());
+        let rc = "alias claude='claude-wrapper --fast'\n";
+        assert!(detect_with_zshrc(Some(rc), &wrapper).is_none());
+        assert!(detect_with_zshrc(Some(rc), binary).is_some());
+    }
+
+    #[test]
+    #[serial]
+    fn test_similarly_named_functions_are_not_shadowing() {
+        let binary = std::path::Path::new("/opt/homebrew/bin/claude");
+        let rc = "function claudia { open -a Claudia; }\nfunction claude_helper() {\n  :\n}\n";
+        assert!(detect_with_zshrc(Some(rc), binary).is_none());
+        for rc in ["function claude {\n", "function claude() {\n", "function claude\n"] {
+            assert!(detect_with_zshrc(Some(rc), binary).is_some(), "{}", rc);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_diverging_alias_is_reported() {
+        let binary = std::path::Path::new("/opt/homebrew/bin/claude");
+        let rc = "# my aliases\nalias ll='ls -l'\nalias claude=\"~/.claude/local/claude\"\n";
+
+        let warning = detect_with_zshrc(Some(rc), binary).unwrap();
+        assert_eq!(warning.line_number, 3);
+        assert_eq!(warning.line, "alias claude=\"~/.claude/local/claude\"");
+        assert!(warning.rc_file.ends_with(".zshrc"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_no_alias_or_rc_file() {
+        let binary = std::path::Path::new("/opt/homebrew/bin/claude");
+        assert!(detect_with_zshrc(Some("alias ll='ls -l'\n"), binary).is_none());
+        assert!(detect_with_zshrc(None, binary).is_none());
+
+        // Functions can't be resolved, so they're always reported
+        assert!(detect_with_zshrc(Some("claude() {\n  command claude \"$@\"\n}\n"), binary).is_some());
+    }
+
+    fn write_claude(dir: &std::path::Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::create_dir_all(dir).unwrap();
+        let bin = dir.join("claude");
+        fs::write(&bin, "#!/bin/sh\necho '1.0.0 (Claude Code)'").unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+        bin
+    }
+
+    #[test]
+    #[serial]
+    fn test_path_resolved_claude_and_shell_match() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = write_claude(&temp_dir.path().join("first"));
+        let second = write_claude(&temp_dir.path().join("second"));
+        let link = temp_dir.path().join("claude-link");
+        std::os::unix::fs::symlink(&first, &link).unwrap();
+        // Not executable, so the shell would skip it
+        let skipped = temp_dir.path().join("skipped");
+        fs::create_dir_all(&skipped).unwrap();
+        fs::write(skipped.join("claude"), "not a program").unwrap();
+
+        let install = |path: &std::path::Path| ClaudeInstallation {
+            path: path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        let original_path = env::var("PATH").ok();
+        let dirs = [skipped, temp_dir.path().join("first"), temp_dir.path().join("second")];
+        env::set_var("PATH", env::join_paths(&dirs).unwrap());
+        let resolved = path_resolved_claude();
+        let matches_first = installation_matches_shell(&install(&first));
+        let matches_link = installation_matches_shell(&install(&link));
+        let matches_second = installation_matches_shell(&install(&second));
+        env::set_var("PATH", temp_dir.path().join("empty"));
+        let resolved_without_claude = path_resolved_claude();
+        let matches_without_claude = installation_matches_shell(&install(&first));
+        match original_path {
+            Some(value) => env::set_var("PATH", value),
+            None => env::remove_var("PATH"),
+        }
+
+        assert_eq!(resolved, Some(first.clone()));
+        assert!(matches_first);
+        assert!(matches_link);
+        assert!(!matches_second);
+        assert_eq!(resolved_without_claude, None);
+        assert!(!matches_without_claude);
+    }
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_no_exec_discovery_uses_path_hints() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let home = temp_dir.path().join("home");
+    let bin = home.join(".nvm/versions/node/v20.11.0/bin/claude");
+    let marker = temp_dir.path().join("executed");
+    fs::create_dir_all(bin.parent().unwrap()).unwrap();
+    fs::write(
+        &bin,
+        format!("#!/bin/sh\ntouch '{}'\necho '1.0.50 (Claude Code)'", marker.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let saved = (env::var("HOME").ok(), env::var("PATH").ok());
+    env::set_var("HOME", &home);
+    env::set_var("PATH", &home);
+
+    // Discovery panics if it tries to spawn anything
+    let installations = discover_claude_installations_no_exec();
+
+    for (key, value) in [("HOME", saved.0), ("PATH", saved.1)] {
+        match value {
+            Some(value) => env::set_var(key, value),
+            None => env::remove_var(key),
+        }
+    }
+
+    let nvm = installations
+        .iter()
+        .find(|install| install.install_type == InstallationType::Nvm)
+        .expect("nvm install should be found by scanning");
+    assert_eq!(nvm.path, bin.to_string_lossy());
+    assert_eq!(nvm.version, None);
+    assert_eq!(nvm.version_hint.as_deref(), Some("node v20.11.0"));
+    assert!(installations.iter().all(|install| install.version.is_none()));
+    assert!(!marker.exists(), "no-exec discovery ran the binary");
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_discovers_pnpm_global_install() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let tools = temp_dir.path().join("tools");
+    let bin_dir = temp_dir.path().join("pnpm-bin");
+    fs::create_dir_all(&tools).unwrap();
+
+    // pnpm keeps the package in its store and links it into the global node_modules
+    let store_pkg = bin_dir
+        .join("global/5/node_modules/.pnpm/@anthropic-ai+claude-code@1.0.60")
+        .join("node_modules/@anthropic-ai/claude-code");
+    fs::create_dir_all(&store_pkg).unwrap();
+    fs::write(store_pkg.join("cli.js"), "console.log('1.0.60 (Claude Code)')").unwrap();
+    let linked = bin_dir.join("global/5/node_modules/@anthropic-ai/claude-code");
+    fs::create_dir_all(linked.parent().unwrap()).unwrap();
+    std::os::unix::fs::symlink(&store_pkg, &linked).unwrap();
+
+    let shim = bin_dir.join("claude");
+    fs::write(
+        &shim,
+        "#!/bin/sh\nbasedir=$(dirname \"$0\")\n\
+         exec node \"$basedir/global/5/node_modules/@anthropic-ai/claude-code/cli.js\" \"$@\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let pnpm = tools.join("pnpm");
+    fs::write(&pnpm, format!("#!/bin/sh\necho '{}'", bin_dir.display())).unwrap();
+    fs::set_permissions(&pnpm, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let saved = (env::var("HOME").ok(), env::var("PATH").ok());
+    env::set_var("HOME", temp_dir.path());
+    env::set_var("PATH", &tools);
+
+    let installations = discover_claude_installations_filtered(&[InstallationType::Pnpm]);
+
+    for (key, value) in [("HOME", saved.0), ("PATH", saved.1)] {
+        match value {
+            Some(value) => env::set_var(key, value),
+            None => env::remove_var(key),
+        }
+    }
+
+    assert_eq!(installations.len(), 1);
+    let install = &installations[0];
+    assert_eq!(install.path, shim.to_string_lossy());
+    assert_eq!(install.install_type, InstallationType::Pnpm);
+    assert_eq!(install.source, "pnpm");
+    assert_eq!(
+        install.resolved_target,
+        Some(fs::canonicalize(store_pkg.join("cli.js")).unwrap())
+    );
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_discovers_npm_prefix_install() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let tools = temp_dir.path().join("tools");
+    let prefix = temp_dir.path().join("npm-global");
+    fs::create_dir_all(&tools).unwrap();
+    fs::create_dir_all(prefix.join("bin")).unwrap();
+
+    let claude = prefix.join("bin").join("claude");
+    fs::write(&claude, "#!/bin/sh\necho '1.0.61 (Claude Code)'").unwrap();
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+    let npm = tools.join("npm");
+    let script = format!(
+        "#!/bin/sh\n[ \"$*\" = 'config get prefix' ] && echo '{}'",
+        prefix.display()
+    );
+    fs::write(&npm, script).unwrap();
+    fs::set_permissions(&npm, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let keys = ["HOME", "PATH", "npm_config_prefix", "NPM_CONFIG_PREFIX"];
+    let saved: Vec<Option<String>> = keys.iter().map(|key| env::var(key).ok()).collect();
+    env::set_var("HOME", temp_dir.path());
+    env::remove_var("npm_config_prefix");
+    env::remove_var("NPM_CONFIG_PREFIX");
+
+    env::set_var("PATH", &tools);
+    let installations = discover_claude_installations_filtered(&[InstallationType::Npm]);
+
+    // Without npm on PATH there's nothing to ask, which isn't an error
+    env::set_var("PATH", temp_dir.path().join("empty"));
+    let without_npm = discover_claude_installations_filtered(&[InstallationType::Npm]);
+
+    // The env var wins over asking npm
+    env::set_var("npm_config_prefix", &prefix);
+    let from_env = discover_claude_installations_filtered(&[InstallationType::Npm]);
+
+    for (key, value) in keys.iter().zip(saved) {
+        match value {
+            Some(value) => env::set_var(key, value),
+            None => env::remove_var(key),
+        }
+    }
+
+    assert_eq!(installations.len(), 1);
+    assert_eq!(installations[0].path, claude.to_string_lossy());
+    assert_eq!(installations[0].install_type, InstallationType::Npm);
+    assert_eq!(installations[0].source, "npm-prefix");
+    assert_eq!(installations[0].version, ClaudeVersion::parse("1.0.61"));
+    assert!(without_npm.is_empty());
+    assert_eq!(from_env.len(), 1);
+    assert_eq!(from_env[0].path, claude.to_string_lossy());
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_candidates_env_selects_first_healthy() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let write = |name: &str, script: &str| {
+        let path = temp_dir.path().join(name).join("claude");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    };
+    let broken = write("broken", "#!/bin/sh\necho 'segmentation fault' >&2\nexit 139");
+    let working = write("working", "#!/bin/sh\necho '1.0.70 (Claude Code)'");
+    let later = write("later", "#!/bin/sh\necho '9.9.9 (Claude Code)'");
+    let missing = temp_dir.path().join("missing").join("claude");
+
+    let original = env::var_os("CLAUDIA_CLAUDE_CANDIDATES");
+    let list = env::join_paths([&missing, &broken, &working, &later]).unwrap();
+    env::set_var("CLAUDIA_CLAUDE_CANDIDATES", &list);
+    let selected = claude_from_candidates_env();
+    env::set_var("CLAUDIA_CLAUDE_CANDIDATES", env::join_paths([&broken]).unwrap());
+    let none_healthy = claude_from_candidates_env();
+    match original {
+        Some(value) => env::set_var("CLAUDIA_CLAUDE_CANDIDATES", value),
+        None => env::remove_var("CLAUDIA_CLAUDE_CANDIDATES"),
+    }
+
+    let selected = selected.expect("the working candidate should be selected");
+    assert_eq!(selected.path, working.to_string_lossy());
+    assert_eq!(selected.install_type, InstallationType::Custom);
+    assert_eq!(selected.version, ClaudeVersion::parse("1.0.70"));
+    assert!(none_healthy.is_none());
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_search_paths_env_adds_custom_installations() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let populated = temp_dir.path().join("populated");
+    let empty = temp_dir.path().join("empty");
+    fs::create_dir_all(&populated).unwrap();
+    fs::create_dir_all(&empty).unwrap();
+    let claude = populated.join("claude");
+    fs::write(&claude, "#!/bin/sh\necho '1.0.71 (Claude Code)'").unwrap();
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+    let missing = temp_dir.path().join("missing");
+
+    let original = env::var_os(SEARCH_PATHS_VAR);
+    env::set_var(SEARCH_PATHS_VAR, env::join_paths([&populated, &empty, &missing]).unwrap());
+    let locations = search_paths_from_env();
+    let installations = discover_claude_installations_filtered(&[InstallationType::Custom]);
+    match original {
+        Some(value) => env::set_var(SEARCH_PATHS_VAR, value),
+        None => env::remove_var(SEARCH_PATHS_VAR),
+    }
+
+    assert_eq!(locations, vec![populated.clone(), empty.clone()]);
+    assert_eq!(installations.len(), 1);
+    assert_eq!(installations[0].path, claude.to_string_lossy());
+    assert_eq!(installations[0].install_type, InstallationType::Custom);
+    assert_eq!(installations[0].version, ClaudeVersion::parse("1.0.71"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_project_claude_hint() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path().join("repo");
+    let nested = repo.join("packages").join("app");
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    fs::create_dir_all(repo.join(".claude")).unwrap();
+    fs::create_dir_all(repo.join("tools")).unwrap();
+    fs::create_dir_all(&nested).unwrap();
+    let pinned = repo.join("tools").join("claude");
+    fs::write(&pinned, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&pinned, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // No config anywhere up to the repo root
+    assert_eq!(project_claude_hint(&nested), None);
+
+    // A relative path in the repo's settings, found from a nested directory
+    let settings = repo.join(".claude").join("settings.json");
+    fs::write(&settings, r#"{ "binary": "tools/claude" }"#).unwrap();
+    assert_eq!(project_claude_hint(&nested), Some(repo.join("tools/claude")));
+
+    // The nearer package.json wins, but its binary doesn't exist
+    fs::write(nested.join("package.json"), r#"{ "claude": { "binary": "bin/claude" } }"#).unwrap();
+    assert_eq!(project_claude_hint(&nested), None);
+    assert_eq!(project_claude_hint(&repo), Some(repo.join("tools/claude")));
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_discovery_probes_with_extra_env() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let claude = temp_dir.path().join("claude");
+    fs::write(
+        &claude,
+        "#!/bin/sh\n[ \"$CLAUDIA_TEST_PROBE_TOKEN\" = open ] && echo '1.0.99 (Claude Code)'\nexit 0",
+    )
+    .unwrap();
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+    let find = |installs: Vec<ClaudeInstallation>| {
+        installs
+            .into_iter()
+            .find(|install| install.path == claude.to_string_lossy())
+            .expect("the mock claude should be discovered")
+    };
+
+    let _token = EnvVarGuard::remove("CLAUDIA_TEST_PROBE_TOKEN");
+    let _search_paths = EnvVarGuard::set(SEARCH_PATHS_VAR, temp_dir.path());
+    let ambient = find(discover_claude_installations_with_env(&[]));
+    let extra = [("CLAUDIA_TEST_PROBE_TOKEN".to_string(), "open".to_string())];
+    let with_token = find(discover_claude_installations_with_env(&extra));
+
+    assert_eq!(ambient.version, None);
+    assert_eq!(with_token.version, ClaudeVersion::parse("1.0.99"));
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_discovery_deadline_truncates_slow_probes() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Instant;
+
+    let temp_dir = TempDir::new().unwrap();
+    let pids = temp_dir.path().join("pids");
+    let dirs: Vec<PathBuf> = (0..4).map(|i| temp_dir.path().join(format!("slow{}", i))).collect();
+    for dir in &dirs {
+        let claude = dir.join("claude");
+        fs::create_dir_all(dir).unwrap();
+        let script = format!("#!/bin/sh\necho $$ >> '{}'\nexec sleep 10", pids.display());
+        fs::write(&claude, script).unwrap();
+        fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let search_paths = EnvVarGuard::set(SEARCH_PATHS_VAR, env::join_paths(&dirs).unwrap());
+    let started = Instant::now();
+    let result = discover_claude_installations_with_deadline(started + Duration::from_millis(1500));
+    let elapsed = started.elapsed();
+    // A deadline that has already passed searches nothing
+    let expired = discover_claude_installations_with_deadline(Instant::now());
+    drop(search_paths);
+
+    assert!(result.truncated);
+    assert!(elapsed < Duration::from_secs(3), "discovery took {:?}", elapsed);
+    // The slow candidates are still listed, just without a version
+    let slow: Vec<&ClaudeInstallation> = result
+        .installations
+        .iter()
+        .filter(|install| install.install_type == InstallationType::Custom)
+        .collect();
+    assert_eq!(slow.len(), dirs.len());
+    assert!(slow.iter().all(|install| install.version.is_none()));
+    
+    // Abandoned probes don't outlive the deadline
+    std::thread::sleep(Duration::from_millis(200));
+    let started_pids = fs::read_to_string(&pids).unwrap();
+    assert!(!started_pids.is_empty());
+    for pid in started_pids.lines() {
+        let pid: libc::pid_t = pid.parse().unwrap();
+        assert_ne!(unsafe { libc::kill(pid, 0) }, 0, "probe {} still running", pid);
+    }
+    
+    assert!(expired.truncated);
+    assert!(expired.installations.is_empty());
+}
+
+#[test]
+fn test_read_pinned_installation() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let valid = temp_dir.path().join("valid.json");
+    fs::write(&valid, r#"{ "claude_path": "/opt/claude/bin/claude", "theme": "dark" }"#).unwrap();
+    assert_eq!(
+        read_pinned_installation(&valid),
+        Some("/opt/claude/bin/claude".to_string())
+    );
+
+    let missing = temp_dir.path().join("missing.json");
+    assert_eq!(read_pinned_installation(&missing), None);
+
+    // Malformed JSON is logged and ignored
+    let malformed = temp_dir.path().join("malformed.json");
+    fs::write(&malformed, r#"{ "claude_path": "/opt/claude"#).unwrap();
+    assert_eq!(read_pinned_installation(&malformed), None);
+
+    let unpinned = temp_dir.path().join("unpinned.json");
+    fs::write(&unpinned, r#"{ "claude_path": "" }"#).unwrap();
+    assert_eq!(read_pinned_installation(&unpinned), None);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_find_version_conflicts_groups_distinct_binaries() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut installs = Vec::new();
+    for (dir, source) in [("homebrew", "homebrew"), ("nvm", "nvm-active")] {
+        let bin = temp_dir.path().join(dir).join("claude");
+        fs::create_dir_all(bin.parent().unwrap()).unwrap();
+        fs::write(&bin, "#!/bin/sh\necho '1.2.3 (Claude Code)'").unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+        let path = bin.to_string_lossy().to_string();
+        let output = run_claude_capture(&CommandSpec::new(&path).arg("--version")).unwrap();
+        installs.push(ClaudeInstallation {
+            version: ClaudeVersion::from_str(&String::from_utf8_lossy(&output.stdout)),
+            path,
+            source: source.to_string(),
+            ..Default::default()
+        });
+    }
+
+    // A symlink to an already-listed binary is the same install, not a conflict
+    let link = temp_dir.path().join("claude-link");
+    std::os::unix::fs::symlink(&installs[0].path, &link).unwrap();
+    installs.push(ClaudeInstallation {
+        path: link.to_string_lossy().to_string(),
+        version: installs[0].version.clone(),
+        source: "PATH".to_string(),
+        ..Default::default()
+    });
+
+    let conflicts = find_version_conflicts(&installs);
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].version, ClaudeVersion::parse("1.2.3").unwrap());
+    assert_eq!(
+        conflicts[0].paths,
+        vec![installs[0].path.clone(), installs[1].path.clone()]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_same_runtime_compares_node_interpreters() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let write = |path: PathBuf, contents: &str| {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        ClaudeInstallation {
+            path: path.to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    };
+    let node_18 = temp_dir.path().join("v18.20.0").join("bin").join("node");
+    let node_20 = temp_dir.path().join("v20.11.0").join("bin").join("node");
+    write(node_18.clone(), "#!/bin/sh\n");
+    write(node_20.clone(), "#!/bin/sh\n");
+    let shebang = |node: &PathBuf| format!("#!{}\nrequire('./cli.js')\n", node.display());
+
+    let global = write(temp_dir.path().join("global").join("claude"), &shebang(&node_18));
+    let linked = write(temp_dir.path().join("linked").join("claude"), &shebang(&node_18));
+    let other = write(temp_dir.path().join("other").join("claude"), &shebang(&node_20));
+    let native = write(temp_dir.path().join("native").join("claude"), "#!/bin/sh\n");
+
+    assert!(same_runtime(&global, &linked));
+    assert!(!same_runtime(&global, &other));
+    // No node interpreter to compare
+    assert!(!same_runtime(&native, &native));
+    assert!(!same_runtime(&global, &native));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_which_in_path_finds_first_executable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let dirs: Vec<PathBuf> = ["first", "second", "third"]
+        .iter()
+        .map(|name| temp_dir.path().join(name))
+        .collect();
+    for dir in &dirs {
+        fs::create_dir_all(dir).unwrap();
+    }
+    // Not executable, so the lookup moves past it like a shell would
+    fs::write(dirs[0].join("node"), "").unwrap();
+    for dir in &dirs[1..] {
+        let node = dir.join("node");
+        fs::write(&node, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&node, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = env::join_paths(&dirs).unwrap().to_string_lossy().to_string();
+
+    assert_eq!(which_in_path("node", &path), Some(dirs[1].join("node")));
+    assert_eq!(which_in_path("npm", &path), None);
+    assert_eq!(which_in_path("node", ""), None);
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_default_shell_claude() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let write_shell = |name: &str, bin: &std::path::Path| {
+        let claude = bin.join("claude");
+        fs::create_dir_all(bin).unwrap();
+        fs::write(&claude, "#!/bin/sh\necho '1.2.3 (Claude Code)'").unwrap();
+        // The login shell's rc files put `bin` on PATH before running the PATH query
+        let shell = temp_dir.path().join(name);
+        fs::write(
+            &shell,
+            format!("#!/bin/sh\nPATH={}:/usr/bin:/bin\neval \"$2\"", bin.display()),
+        )
+        .unwrap();
+        for file in [&claude, &shell] {
+            fs::set_permissions(file, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        (shell, claude)
+    };
+    let (shell, claude) = write_shell("mock-shell", &temp_dir.path().join("shell-bin"));
+    let home = temp_dir.path().join("home");
+    let (local_shell, local_claude) = write_shell("local-shell", &home.join(".local/bin"));
+    
+    let _home = EnvVarGuard::set("HOME", &home);
+    let mut shell_var = EnvVarGuard::set("SHELL", &shell);
+    let found = default_shell_claude();
+    drop(shell_var);
+    shell_var = EnvVarGuard::set("SHELL", &local_shell);
+    let local = default_shell_claude();
+    drop(shell_var);
+    let _no_shell = EnvVarGuard::remove("SHELL");
+    let without_shell = default_shell_claude();
+    
+    let found = found.expect("the login shell's claude should be resolved");
+    assert_eq!(found.path, claude.to_string_lossy());
+    assert_eq!(found.discovered_via, DiscoveryMethod::Which);
+    assert_eq!(found.install_type, InstallationType::Direct);
+    assert_eq!(found.version, ClaudeVersion::parse("1.2.3"));
+    // Classified like discovery classifies the same location
+    let local = local.expect("the login shell's claude should be resolved");
+    assert_eq!(local.path, local_claude.to_string_lossy());
+    assert_eq!(local.install_type, InstallationType::Standalone);
+    assert!(without_shell.is_none());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_probe_version_with_retries_classifies_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let write = |name: &str, body: &str| {
+        let path = temp_dir.path().join(name);
+        let runs = temp_dir.path().join(format!("{}.runs", name));
+        fs::write(&path, format!("#!/bin/sh\necho run >> '{}'\n{}", runs.display(), body))
+            .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().to_string()
+    };
+    let runs = |name: &str| {
+        fs::read_to_string(temp_dir.path().join(format!("{}.runs", name)))
+            .map_or(0, |runs| runs.lines().count())
+    };
+    let garbage = write("garbage", "echo 'hello world'");
+    let crashing = write("crashing", "kill -9 $$");
+    let working = write("working", "echo '1.0.42 (Claude Code)'");
+    
+    // Every probe invocation ran once and none was retried
+    assert_eq!(probe_version_with_retries(&garbage, 3), ProbeOutcome::NotClaude);
+    assert_eq!(runs("garbage"), 4);
+    
+    assert_eq!(probe_version_with_retries(&crashing, 3), ProbeOutcome::TransientError);
+    assert_eq!(runs("crashing"), 3);
+    
+    assert_eq!(
+        probe_version_with_retries(&working, 3),
+        ProbeOutcome::Version(ClaudeVersion::parse("1.0.42").unwrap())
+    );
+    // Files that can't be started at all are rejected without waiting on retries
+    let missing = temp_dir.path().join("missing").to_string_lossy().to_string();
+    let not_executable = temp_dir.path().join("not-executable");
+    fs::write(&not_executable, "#!/bin/sh\necho '1.0.42 (Claude Code)'").unwrap();
+    let started = std::time::Instant::now();
+    assert_eq!(probe_version_with_retries(&missing, 3), ProbeOutcome::NotRunnable);
+    assert_eq!(
+        probe_version_with_retries(&not_executable.to_string_lossy(), 3),
+        ProbeOutcome::NotRunnable
+    );
+    assert!(started.elapsed() < Duration::from_millis(250));
+    
+    // More output than a pipe buffer holds, on both streams, doesn't stall the probe
+    let chatty = write(
+        "chatty",
+        "head -c 200000 /dev/zero | tr '\\0' x >&2\n\
+         head -c 200000 /dev/zero | tr '\\0' x\n\
+         echo\necho '1.0.42 (Claude Code)'",
+    );
+    assert_eq!(
+        probe_version_with_retries(&chatty, 1),
+        ProbeOutcome::Version(ClaudeVersion::parse("1.0.42").unwrap())
+    );
+}
+
+#[test]
+fn test_group_installations_by_type() {
+    let install = |path: &str, install_type: InstallationType, version: Option<&str>| {
+        ClaudeInstallation {
+            path: path.to_string(),
+            version: version.and_then(ClaudeVersion::parse),
+            install_type,
+            ..Default::default()
+        }
+    };
+    let installs = vec![
+        install("/nvm/18/claude", InstallationType::Nvm, Some("1.0.30")),
+        install("/opt/homebrew/bin/claude", InstallationType::Homebrew, Some("1.0.40")),
+        install("/nvm/20/claude", InstallationType::Nvm, None),
+        install("/usr/bin/claude", InstallationType::System, Some("1.0.10")),
+        install("/nvm/22/claude", InstallationType::Nvm, Some("1.0.50")),
+    ];
+
+    let groups = group_installations(installs);
+    assert_eq!(
+        groups.keys().copied().collect::<Vec<_>>(),
+        vec![InstallationType::System, InstallationType::Homebrew, InstallationType::Nvm]
+    );
+    let nvm_paths: Vec<&str> = groups[&InstallationType::Nvm]
+        .iter()
+        .map(|install| install.path.as_str())
+        .collect();
+    assert_eq!(nvm_paths, vec!["/nvm/22/claude", "/nvm/18/claude", "/nvm/20/claude"]);
+    assert_eq!(groups[&InstallationType::Homebrew].len(), 1);
+}
+
+#[test]
+fn test_nvm_node_version() {
+    let path = PathBuf::from("/home/me/.nvm/versions/node/v20.3.1/bin/claude");
+    let version = nvm_node_version(&path).unwrap();
+    assert_eq!(version, NodeVersion { major: 20, minor: 3, patch: 1 });
+    assert_eq!(version.to_string(), "v20.3.1");
+    assert!(version > nvm_node_version(&PathBuf::from("/n/versions/node/v18.20.0")).unwrap());
+    
+    // Malformed version segments
+    assert!(nvm_node_version(&PathBuf::from("/home/me/.nvm/versions/node/v20.x/bin")).is_none());
+    assert!(nvm_node_version(&PathBuf::from("/home/me/.nvm/versions/node/20.3.1/bin")).is_none());
+    assert!(nvm_node_version(&PathBuf::from("/home/me/.nvm/versions/node/v20.3.1.4")).is_none());
+    
+    // Not an nvm path
+    assert!(nvm_node_version(&PathBuf::from("/usr/local/bin/claude")).is_none());
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_probe_runtime_versions_of_node_shim() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A fake node that logs each launch and runs the shim's body with sh
+    let write_shim = |bin: &std::path::Path| {
+        fs::create_dir_all(bin).unwrap();
+        let node = bin.join("node");
+        fs::write(
+            &node,
+            "#!/bin/sh\necho run >> \"$(dirname \"$0\")/runs\"\nif [ \"$1\" = --version ]; then echo v21.6.2; exit 0; fi\nexec /bin/sh \"$@\"\n",
+        )
+        .unwrap();
+        let claude = bin.join("claude");
+        fs::write(&claude, "#!/usr/bin/env node\necho '1.0.80 (Claude Code)'\n").unwrap();
+        for file in [&node, &claude] {
+            fs::set_permissions(file, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        ClaudeInstallation {
+            path: claude.to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    };
+    let runs = |bin: &std::path::Path| {
+        fs::read_to_string(bin.join("runs")).map_or(0, |runs| runs.lines().count())
+    };
+
+    let temp_dir = TempDir::new().unwrap();
+    let nvm_bin = temp_dir.path().join(".nvm/versions/node/v20.11.0/bin");
+    let other_bin = temp_dir.path().join("opt/node/bin");
+    let nvm_shim = write_shim(&nvm_bin);
+    let other_shim = write_shim(&other_bin);
+
+    let original_path = env::var("PATH").unwrap_or_default();
+    env::set_var("PATH", format!("{}:{}", nvm_bin.display(), original_path));
+    let from_nvm = probe_runtime_versions(&nvm_shim);
+    env::set_var("PATH", format!("{}:{}", other_bin.display(), original_path));
+    let from_other = probe_runtime_versions(&other_shim);
+    env::set_var("PATH", original_path);
+
+    // nvm's node version comes from its path: a single launch in total
+    assert_eq!(from_nvm.claude, ClaudeVersion::parse("1.0.80"));
+    assert_eq!(from_nvm.node, NodeVersion::parse("v20.11.0"));
+    assert_eq!(runs(&nvm_bin), 1);
+
+    // Elsewhere node is asked directly, still without a second claude launch
+    assert_eq!(from_other.claude, ClaudeVersion::parse("1.0.80"));
+    assert_eq!(from_other.node, NodeVersion::parse("v21.6.2"));
+    assert_eq!(runs(&other_bin), 2);
+}
+
+#[test]
+#[cfg(unix)]
+#[serial]
+fn test_node_version_mismatch() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let versions = temp_dir.path().join(".nvm/versions/node");
+    let node_in = |version: &str| {
+        let bin = versions.join(version).join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        let node = bin.join("node");
+        fs::write(&node, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&node, fs::Permissions::from_mode(0o755)).unwrap();
+        node
+    };
+    let install_node = node_in("v20.11.0");
+    let claude = install_node.with_file_name("claude");
+    fs::write(&claude, format!("#!{}\nconsole.log('hi')\n", install_node.display())).unwrap();
+    let install = ClaudeInstallation {
+        path: claude.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    let old_node = node_in("v16.20.2");
+    let same_major = node_in("v20.5.1");
+    let empty = temp_dir.path().join("empty");
+    fs::create_dir_all(&empty).unwrap();
+
+    let with_path = |dir: &std::path::Path| {
+        let _path = EnvVarGuard::set("PATH", dir);
+        node_version_mismatch(&install)
+    };
+    let mismatch = with_path(old_node.parent().unwrap());
+    let matching = with_path(same_major.parent().unwrap());
+    let unresolved = with_path(&empty);
+
+    let mismatch = mismatch.unwrap();
+    assert_eq!(mismatch.install_node, install_node);
+    assert_eq!(mismatch.install_version, NodeVersion::parse("v20.11.0").unwrap());
+    assert_eq!(mismatch.active_node, old_node);
+    assert_eq!(mismatch.active_version, NodeVersion::parse("v16.20.2").unwrap());
+    assert!(mismatch.to_string().starts_with("Your active node is 16 ("));
+    assert_eq!(matching, None);
+    assert_eq!(unresolved, None);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_stale_nvm_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let node_dir = temp_dir.path().join(".nvm").join("versions").join("node");
+    let installed = node_dir.join("v20.11.0").join("bin");
+    let uninstalled = node_dir.join("v18.19.0").join("bin");
+    fs::create_dir_all(&installed).unwrap();
+    let missing_other = temp_dir.path().join("gone").join("bin");
+    
+    let path = env::join_paths([&installed, &uninstalled, &missing_other]).unwrap();
+    let stale = stale_nvm_entries(&path.to_string_lossy());
+    
+    // Only the deleted node version; a missing non-nvm dir is not nvm's business
+    assert_eq!(stale, vec![uninstalled.to_string_lossy().to_string()]);
+}
+
+#[cfg(unix)]
+mod path_addition_tests {
+    use super::*;
+    use claudia_lib::path_utils::with_temp_path;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn write_executable(path: &std::path::Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_plain_binary_needs_its_own_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let bin = temp_dir.path().join("bin").join("claude");
+        write_executable(&bin, "#!/bin/sh\necho '1.0.0 (Claude Code)'");
+
+        let additions = with_temp_path("/usr/bin:/bin", || required_path_additions(&bin));
+        assert_eq!(additions, vec![bin.parent().unwrap().to_string_lossy().to_string()]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_node_shim_needs_node_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let node = temp_dir.path().join("node-20").join("bin").join("node");
+        write_executable(&node, "#!/bin/sh\n");
+        let bin = temp_dir.path().join("npm-global").join("bin").join("claude");
+        write_executable(&bin, &format!("#!{}\nrequire('./cli.js')", node.display()));
+
+        let additions = with_temp_path("/usr/bin:/bin", || required_path_additions(&bin));
+        assert_eq!(
+            additions,
+            vec![
+                bin.parent().unwrap().to_string_lossy().to_string(),
+                node.parent().unwrap().to_string_lossy().to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_env_node_shim_already_on_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let node_dir = temp_dir.path().join("node").join("bin");
+        write_executable(&node_dir.join("node"), "#!/bin/sh\n");
+        let bin_dir = temp_dir.path().join("bin");
+        write_executable(&bin_dir.join("claude"), "#!/usr/bin/env node\n");
+
+        let path = format!("{}:{}", bin_dir.display(), node_dir.display());
+        let additions = with_temp_path(&path, || required_path_additions(&bin_dir.join("claude")));
+        assert!(additions.is_empty(), "unexpected additions: {:?}", additions);
+    }
+}
+
+#[test]
+fn test_build_command_per_installation_type() {
+    use std::ffi::OsString;
+
+    fn built(
+        install_type: InstallationType,
+        invocation: Option<Vec<&str>>,
+    ) -> (String, Vec<OsString>) {
+        let install = ClaudeInstallation {
+            path: "/opt/claude/bin/claude".to_string(),
+            install_type,
+            invocation: invocation.map(|argv| argv.into_iter().map(String::from).collect()),
+            ..Default::default()
+        };
+        let spec = install.build_command();
+        (spec.program, spec.args)
+    }
+
+    for install_type in [
+        InstallationType::Direct,
+        InstallationType::System,
+        InstallationType::Homebrew,
+        InstallationType::Nvm,
+        InstallationType::Snap,
+    ] {
+        assert_eq!(
+            built(install_type, None),
+            ("/opt/claude/bin/claude".to_string(), vec![]),
+            "{:?}",
+            install_type
+        );
+    }
+
+    assert_eq!(
+        built(InstallationType::Flatpak, None),
+        (
+            "flatpak".to_string(),
+            vec!["run".into(), "/opt/claude/bin/claude".into()]
+        )
+    );
+    assert_eq!(
+        built(
+            InstallationType::Flatpak,
+            Some(vec!["/usr/bin/flatpak", "run", "com.anthropic.ClaudeCode"])
+        ),
+        (
+            "/usr/bin/flatpak".to_string(),
+            vec!["run".into(), "com.anthropic.ClaudeCode".into()]
+        )
+    );
+    assert_eq!(
+        built(InstallationType::Wsl, None),
+        (
+            "wsl.exe".to_string(),
+            vec!["-e".into(), "/opt/claude/bin/claude".into()]
+        )
+    );
+    let in_distro = ClaudeInstallation {
+        path: "/home/me/.local/bin/claude".to_string(),
+        install_type: InstallationType::Wsl,
+        wsl_distro: Some("Ubuntu".to_string()),
+        ..Default::default()
+    }
+    .build_command();
+    assert_eq!(in_distro.program, "wsl.exe");
+    assert_eq!(
+        in_distro.args,
+        ["-d", "Ubuntu", "-e", "/home/me/.local/bin/claude"].map(OsString::from)
+    );
+    assert_eq!(
+        built(
+            InstallationType::Wsl,
+            Some(vec!["wsl.exe", "-d", "Ubuntu", "--", "/home/me/.local/bin/claude"])
+        ),
+        (
+            "wsl.exe".to_string(),
+            vec![
+                "-d".into(),
+                "Ubuntu".into(),
+                "--".into(),
+                "/home/me/.local/bin/claude".into()
+            ]
+        )
+    );
+
+    // Caller arguments follow the launcher prefix
+    let install = ClaudeInstallation {
+        path: "com.anthropic.ClaudeCode".to_string(),
+        install_type: InstallationType::Flatpak,
+        ..Default::default()
+    };
+    assert_eq!(
+        install.build_command().arg("--version").to_shell_string(),
+        "flatpak run com.anthropic.ClaudeCode --version"
+    );
+}
+
+#[test]
+fn test_command_spec_args_and_shell_string() {
+    use std::ffi::OsString;
+
+    let spec = CommandSpec::new("/opt/my tools/claude")
+        .args(["--model", "claude-3.5"])
+        .arg("--print")
+        .arg("explain what it's doing")
+        .arg("");
+
+    let expected: Vec<OsString> = vec![
+        "--model".into(),
+        "claude-3.5".into(),
+        "--print".into(),
+        "explain what it's doing".into(),
+        "".into(),
+    ];
+    assert_eq!(spec.args, expected);
+    assert_eq!(
+        spec.to_shell_string(),
+        r#"'/opt/my tools/claude' --model claude-3.5 --print 'explain what it'\''s doing' ''"#
+    );
+
+    // The Command gets the args verbatim
+    let cmd = spec.to_command();
+    let cmd_args: Vec<OsString> = cmd.get_args().map(|a| a.to_os_string()).collect();
+    assert_eq!(cmd_args, expected);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_command_spec_shell_string_round_trips() {
+    // Feeding the rendered string back through sh yields the original args
+    let spec = CommandSpec::new("printf")
+        .arg("[%s]")
+        .args(["two words", "it's", "", "\"double\"", "$HOME"]);
+    let output = std::process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(spec.to_shell_string())
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "[two words][it's][][\"double\"][$HOME]"
+    );
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use claudia_lib::claude_binary::*;
+
+    #[test]
+    fn test_event_parser_handles_split_chunks() {
+        let mut parser = ClaudeEventParser::new();
+
+        // The second object is split mid-key across two chunks
+        let mut events = parser.push(b"{\"type\":\"system\",\"subtype\":\"init\"}\n{\"ty");
+        assert_eq!(events.len(), 1);
+        events.extend(parser.push(b"pe\":\"assistant\",\"message\":{\"content\":[]}}\n"));
+        events.extend(parser.push(b"\n\r\n{\"type\":\"result\",\"is_error\":false}\r\n"));
+
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], ClaudeEvent::System(v) if v["subtype"] == "init"));
+        assert!(matches!(&events[1], ClaudeEvent::Assistant(v) if v["message"]["content"].is_array()));
+        assert!(matches!(&events[2], ClaudeEvent::Result(v) if v["is_error"] == false));
+        assert!(parser.finish().is_none());
+    }
+
+    #[test]
+    fn test_event_parser_surfaces_unparseable_lines() {
+        let mut parser = ClaudeEventParser::new();
+        let events = parser.push(b"not json\n{\"type\":\"progress\"}\n{\"type\":\"user\"}");
+
+        assert_eq!(
+            events,
+            vec![
+                ClaudeEvent::Unknown("not json".to_string()),
+                ClaudeEvent::Other(serde_json::json!({"type": "progress"})),
+            ]
+        );
+
+        // A final line without a newline is flushed by finish()
+        assert!(matches!(parser.finish(), Some(ClaudeEvent::User(_))));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_spawn_claude_streaming_delivers_events() {
+        let mut spec = CommandSpec::new("/bin/sh");
+        spec.args = vec![
+            "-c".into(),
+            r#"printf '{"type":"system"}\n'; printf '{"type":"res'; printf 'ult"}\n'"#.into(),
+        ];
+
+        let (handle, events) = spawn_claude_streaming(&spec).unwrap();
+        let events: Vec<ClaudeEvent> = events.iter().collect();
+        assert!(handle.wait().unwrap().success());
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], ClaudeEvent::System(_)));
+        assert!(matches!(events[1], ClaudeEvent::Result(_)));
+    }
+
+    /// A process counts as gone once it no longer exists or is an unreaped zombie
+    #[cfg(unix)]
+    fn process_alive(pid: u32) -> bool {
+        if let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            let state = stat.rsplit(')').next().unwrap_or("").trim_start();
+            return !state.starts_with('Z');
+        }
+        unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_cancel_kills_process_group() {
+        use std::time::{Duration, Instant};
+
+        // The mock forks a long-running child, reports its pid, then waits on it
+        let mut spec = CommandSpec::new("/bin/sh");
+        spec.args = vec![
+            "-c".into(),
+            r#"sleep 30 & printf '{"type":"system","child_pid":%s}\n' "$!"; wait"#.into(),
+        ];
+
+        let (handle, events) = spawn_claude_streaming(&spec).unwrap();
+        let first = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        let child_pid = match first {
+            ClaudeEvent::System(value) => value["child_pid"].as_u64().unwrap() as u32,
+            other => panic!("unexpected event: {:?}", other),
+        };
+        let pid = handle.pid();
+        assert!(process_alive(pid));
+        assert!(process_alive(child_pid));
+
+        handle.cancel();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while (process_alive(pid) || process_alive(child_pid)) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!process_alive(pid), "claude process survived cancel");
+        assert!(!process_alive(child_pid), "forked child survived cancel");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_cancel_while_waiting() {
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        let mut spec = CommandSpec::new("/bin/sh");
+        spec.args = vec!["-c".into(), "sleep 30".into()];
+        let (handle, _events) = spawn_claude_streaming(&spec).unwrap();
+        let handle = Arc::new(handle);
+
+        let waiter = {
+            let handle = Arc::clone(&handle);
+            std::thread::spawn(move || handle.wait())
+        };
+        std::thread::sleep(Duration::from_millis(200));
+
+        let started = Instant::now();
+        handle.cancel();
+        let status = waiter.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!status.success());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_cancel_after_leader_exit_kills_group() {
+        use std::time::{Duration, Instant};
+
+        // The leader exits right away, leaving its forked child in the process group
+        let mut spec = CommandSpec::new("/bin/sh");
+        spec.args = vec![
+            "-c".into(),
+            r#"sleep 30 & printf '{"type":"system","child_pid":%s}\n' "$!"; exit 0"#.into(),
+        ];
+
+        let (handle, events) = spawn_claude_streaming(&spec).unwrap();
+        let child_pid = match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            ClaudeEvent::System(value) => value["child_pid"].as_u64().unwrap() as u32,
+            other => panic!("unexpected event: {:?}", other),
+        };
+        assert!(handle.wait().unwrap().success());
+        assert!(process_alive(child_pid));
+
+        handle.cancel();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while process_alive(child_pid) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!process_alive(child_pid), "forked child survived cancel");
+    }
+}
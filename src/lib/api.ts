@@ -92,6 +92,38 @@ export interface ClaudeInstallation {
   version?: string;
   /** Source of discovery (e.g., "nvm", "system", "homebrew", "which") */
   source: string;
+  /** Kind of installation */
+  install_type:
+    | "direct"
+    | "system"
+    | "homebrew"
+    | "nvm"
+    | "snap"
+    | "flatpak"
+    | "wsl"
+    | "pnpm"
+    | "npm"
+    | "custom"
+    | "app_bundle"
+    | "standalone";
+  /** Whether it was found in a known location or by resolving `claude` on a PATH */
+  discovered_via?: "scan" | "which";
+  /** Launcher command line for installations that can't be executed directly */
+  invocation?: string[];
+  /** WSL distribution of a "wsl" installation; absent for the default one */
+  wsl_distro?: string;
+  /** Last modification time of the binary */
+  modified?: { secs_since_epoch: number; nanos_since_epoch: number } | null;
+  /** Size of the binary in bytes */
+  size_bytes?: number | null;
+  /** Version information inferred from the path alone, e.g. "node v20.11.0" for nvm */
+  version_hint?: string | null;
+  /** Whether the path is a symlink */
+  is_symlink?: boolean;
+  /** The file a symlinked path points at */
+  resolved_target?: string | null;
+  /** User-chosen label, e.g. "work account" */
+  label?: string;
 }
 
 // Sandbox API types